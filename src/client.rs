@@ -1,11 +1,86 @@
+use orderbook::admin_client::AdminClient;
 use orderbook::order_book_client::OrderBookClient;
-use orderbook::{OrderRequest, TradeBookRequest};
+use orderbook::{
+    AddPairRequest,
+    CancelAllRequest,
+    CancelOrderRequest,
+    ClientOrderRequest,
+    DepthRequest,
+    EngineStatusRequest,
+    GetOrderRequest,
+    HaltMode,
+    HaltRequest,
+    OpenOrdersRequest,
+    OrderBookRequest,
+    OrderDetails,
+    OrderGroupRequest,
+    OrderGroupType,
+    OrderRequest,
+    OrderType,
+    PairRequest,
+    PostOnly,
+    ReplaceOrderRequest,
+    SelfTradePrevention,
+    Side,
+    TimeInForce,
+    TradeBookRequest,
+    TradeStreamRequest,
+};
+use rust_decimal::Decimal;
 use structopt::StructOpt;
 
 pub mod orderbook {
     tonic::include_proto!("orderbook");
 }
 
+// Proto enum values by their lowercase name on the command line (e.g. stop_limit for ORDER_TYPE_STOP_LIMIT)
+fn parse_side(value: &str) -> Result<Side, String> {
+    Side::from_str_name(&format!("SIDE_{}", value.to_uppercase())).ok_or_else(|| format!("Unknown side: {}", value))
+}
+
+fn parse_order_type(value: &str) -> Result<OrderType, String> {
+    OrderType::from_str_name(&format!("ORDER_TYPE_{}", value.to_uppercase())).ok_or_else(||
+        format!("Unknown order type: {}", value)
+    )
+}
+
+fn parse_time_in_force(value: &str) -> Result<TimeInForce, String> {
+    TimeInForce::from_str_name(&format!("TIME_IN_FORCE_{}", value.to_uppercase())).ok_or_else(||
+        format!("Unknown time in force: {}", value)
+    )
+}
+
+fn parse_group_type(value: &str) -> Result<OrderGroupType, String> {
+    OrderGroupType::from_str_name(&format!("ORDER_GROUP_TYPE_{}", value.to_uppercase())).ok_or_else(||
+        format!("Unknown order group type: {}", value)
+    )
+}
+
+fn parse_post_only(value: &str) -> Result<PostOnly, String> {
+    PostOnly::from_str_name(&format!("POST_ONLY_{}", value.to_uppercase())).ok_or_else(|| format!("Unknown post-only mode: {}", value))
+}
+
+fn parse_self_trade_prevention(value: &str) -> Result<SelfTradePrevention, String> {
+    SelfTradePrevention::from_str_name(&format!("SELF_TRADE_PREVENTION_{}", value.to_uppercase())).ok_or_else(||
+        format!("Unknown self-trade prevention: {}", value)
+    )
+}
+
+// Lowercase name of a proto enum value for display (e.g. partially_filled for ORDER_STATUS_PARTIALLY_FILLED)
+fn display_name(proto_name: &str, prefix: &str) -> String {
+    proto_name.trim_start_matches(prefix).to_lowercase()
+}
+
+fn print_order(order: &OrderDetails) {
+    println!(
+        "{}: ID: {}, Client order ID: {}, Pair: {}, Side: {}, Type: {}, Price: {}, Stop price: {}, Volume: {}/{}, STP canceled: {}, Timestamp: {}, Expires: {}",
+        display_name(order.status().as_str_name(), "ORDER_STATUS_"), order.order_id, order.client_order_id, order.pair,
+        display_name(order.side().as_str_name(), "SIDE_"), display_name(order.order_type().as_str_name(), "ORDER_TYPE_"),
+        order.price, order.stop_price, order.remaining_volume, order.original_volume, order.stp_canceled_volume, order.timestamp,
+        order.expire_time
+    );
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "Trading-CLI", about = "A CLI to submit market and limit trades or retrieve trades from the trade book.")]
 struct Cli {
@@ -24,25 +99,214 @@ enum Command {
         
         /// Volume of the asset to trade (e.g., 0.01, 0.1, 1.0)
         #[structopt(help = "Volume of the asset to trade")]
-        volume: f64,
+        volume: Decimal,
         
         /// Side of the order (buy or sell)
-        #[structopt(help = "Side of the order (buy or sell)")]
-        side: String,
+        #[structopt(parse(try_from_str = parse_side), help = "Side of the order (buy or sell)")]
+        side: Side,
         
-        /// Type of the order (market or limit)
-        #[structopt(help = "Type of the order (market or limit)")]
-        order_type: String,
+        /// Type of the order (market, limit, stop, stop_limit, trailing_stop, market_to_limit, primary_peg or midpoint_peg)
+        #[structopt(parse(try_from_str = parse_order_type), help = "Type of the order (market, limit, stop, stop_limit, trailing_stop, market_to_limit, primary_peg or midpoint_peg)")]
+        order_type: OrderType,
         
         /// Price for the limit order
         #[structopt(help = "Price for the limit order")]
-        price: f64,
+        price: Decimal,
         
         /// Trader's identifier
         #[structopt(help = "Trader's identifier")]
         trader: String,
+
+        /// Trigger price for stop and stop_limit orders
+        #[structopt(long, default_value = "0.0", help = "Trigger price for stop and stop_limit orders")]
+        stop_price: Decimal,
+
+        /// Time in force (gtc, ioc, fok, gtd or day)
+        #[structopt(long, default_value = "gtc", parse(try_from_str = parse_time_in_force), help = "Time in force (gtc, ioc, fok, gtd or day)")]
+        time_in_force: TimeInForce,
+
+        /// Expiry of gtd orders (RFC 3339, e.g. 2024-06-19T21:00:00Z)
+        #[structopt(long, default_value = "", help = "Expiry of gtd orders (RFC 3339)")]
+        expire_time: String,
+
+        /// Return once the order is queued instead of waiting for the execution report
+        #[structopt(long, help = "Return once the order is queued instead of waiting for the execution report")]
+        fire_and_forget: bool,
+
+        /// Own order identifier, a retried order with the same id is not placed twice
+        #[structopt(long, default_value = "", help = "Own order identifier, a retried order with the same id is not placed twice")]
+        client_order_id: String,
+
+        /// Self-trade prevention (none, cancel_newest, cancel_oldest, cancel_both or decrement_and_cancel), defaults to the trader's configured mode
+        #[structopt(long, default_value = "unspecified", parse(try_from_str = parse_self_trade_prevention), help = "Self-trade prevention (none, cancel_newest, cancel_oldest, cancel_both or decrement_and_cancel)")]
+        self_trade_prevention: SelfTradePrevention,
+
+        /// Post-only limit order: reject it or reprice it one tick behind the best price when it would cross the book
+        #[structopt(long, default_value = "unspecified", parse(try_from_str = parse_post_only), help = "Post-only limit order (reject or reprice when it would cross the book)")]
+        post_only: PostOnly,
+
+        /// Only accept the order if it decreases the trader's position in the pair
+        #[structopt(long, help = "Only accept the order if it decreases the trader's position in the pair")]
+        reduce_only: bool,
+
+        /// Iceberg limit order: volume shown in the book, the rest stays hidden and is shown slice by slice
+        #[structopt(long, default_value = "0", help = "Iceberg limit order: volume shown in the book, the rest stays hidden")]
+        display_volume: Decimal,
+
+        /// Distance of a trailing_stop's stop price from the market, absolute (e.g. 50) or percent (e.g. 2%)
+        #[structopt(long, default_value = "", help = "Distance of a trailing_stop's stop price from the market (e.g. 50 or 2%)")]
+        trailing_offset: String,
     },
     
+    /// Place an OCO pair (take-profit limit and stop-loss on the same side) or a bracket order (entry plus OCO exits on
+    /// the opposite side, placed once the entry is filled) (example: client order-group bracket XXBTZUSD 1.0 buy Rock 70000.0 60000.0 --entry-price 65000.0)
+    #[structopt(name = "order-group")]
+    OrderGroup {
+        /// Group type (oco or bracket)
+        #[structopt(parse(try_from_str = parse_group_type), help = "Group type (oco or bracket)")]
+        group_type: OrderGroupType,
+
+        /// Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)
+        #[structopt(help = "Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)")]
+        pair: String,
+
+        /// Volume of the legs (of the entry for bracket orders)
+        #[structopt(help = "Volume of the legs (of the entry for bracket orders)")]
+        volume: Decimal,
+
+        /// Side of the legs (of the entry for bracket orders)
+        #[structopt(parse(try_from_str = parse_side), help = "Side of the legs (of the entry for bracket orders)")]
+        side: Side,
+
+        /// Trader's identifier
+        #[structopt(help = "Trader's identifier")]
+        trader: String,
+
+        /// Limit price of the take-profit leg
+        #[structopt(help = "Limit price of the take-profit leg")]
+        take_profit: Decimal,
+
+        /// Stop price of the stop-loss leg
+        #[structopt(help = "Stop price of the stop-loss leg")]
+        stop_loss: Decimal,
+
+        /// Limit price of the bracket entry, 0 for a market entry
+        #[structopt(long, default_value = "0.0", help = "Limit price of the bracket entry, 0 for a market entry")]
+        entry_price: Decimal,
+    },
+
+    /// Cancel a resting limit order (example: client cancel-order 4b80e237-5db6-4e96-b677-98b32574716b Rock)
+    #[structopt(name = "cancel-order")]
+    CancelOrder {
+        /// Order id returned when the order was placed
+        #[structopt(help = "Order id returned when the order was placed")]
+        order_id: String,
+
+        /// Trader's identifier
+        #[structopt(help = "Trader's identifier")]
+        trader: String,
+    },
+
+    /// Change price and/or volume of a resting limit order, 0 keeps the current value (example: client replace-order 4b80e237-5db6-4e96-b677-98b32574716b Rock 65250.0 0.5)
+    #[structopt(name = "replace-order")]
+    ReplaceOrder {
+        /// Order id returned when the order was placed
+        #[structopt(help = "Order id returned when the order was placed")]
+        order_id: String,
+
+        /// Trader's identifier
+        #[structopt(help = "Trader's identifier")]
+        trader: String,
+
+        /// New limit price (0 keeps the current price)
+        #[structopt(help = "New limit price (0 keeps the current price)")]
+        price: Decimal,
+
+        /// New remaining volume (0 keeps the current volume)
+        #[structopt(help = "New remaining volume (0 keeps the current volume)")]
+        volume: Decimal,
+    },
+
+    /// Look up an order by its client order id (example: client order-by-client-id Rock bot-42)
+    #[structopt(name = "order-by-client-id")]
+    OrderByClientId {
+        /// Trader's identifier
+        #[structopt(help = "Trader's identifier")]
+        trader: String,
+
+        /// Client order id the order was placed with
+        #[structopt(help = "Client order id the order was placed with")]
+        client_order_id: String,
+    },
+
+    /// Show the current state of an order (example: client get-order 4b80e237-5db6-4e96-b677-98b32574716b)
+    #[structopt(name = "get-order")]
+    GetOrder {
+        /// Order id returned when the order was placed
+        #[structopt(help = "Order id returned when the order was placed")]
+        order_id: String,
+    },
+
+    /// List the trader's open orders (example: client open-orders Rock --pair XXBTZUSD)
+    #[structopt(name = "open-orders")]
+    OpenOrders {
+        /// Trader's identifier
+        #[structopt(help = "Trader's identifier")]
+        trader: String,
+
+        /// Only orders of this pair
+        #[structopt(long, default_value = "", help = "Only orders of this pair")]
+        pair: String,
+    },
+
+    /// Show aggregated order book depth (example: client depth XXBTZUSD --levels 10 --group 10)
+    #[structopt(name = "depth")]
+    Depth {
+        /// Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)
+        #[structopt(help = "Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)")]
+        pair: String,
+
+        /// Number of price levels per side (0 for all)
+        #[structopt(long, default_value = "10", help = "Number of price levels per side (0 for all)")]
+        levels: u32,
+
+        /// Price bucket to group levels by (e.g. 1, 10, 100), 0 keeps the book's levels
+        #[structopt(long, default_value = "0.0", help = "Price bucket to group levels by (e.g. 1, 10, 100)")]
+        group: Decimal,
+    },
+
+    /// Stream order book snapshot and level updates for a pair (example: client watch-order-book XXBTZUSD)
+    #[structopt(name = "watch-order-book")]
+    WatchOrderBook {
+        /// Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)
+        #[structopt(help = "Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)")]
+        pair: String,
+    },
+
+    /// Stream every execution in a pair (example: client watch-trades XXBTZUSD)
+    #[structopt(name = "watch-trades")]
+    WatchTrades {
+        /// Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)
+        #[structopt(help = "Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)")]
+        pair: String,
+    },
+
+    /// Stream the call auction state of a pair (example: client watch-auction XXBTZUSD)
+    #[structopt(name = "watch-auction")]
+    WatchAuction {
+        /// Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)
+        #[structopt(help = "Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)")]
+        pair: String,
+    },
+
+    /// Stream trading halts and resumes of a pair (example: client watch-trading-status XXBTZUSD)
+    #[structopt(name = "watch-trading-status")]
+    WatchTradingStatus {
+        /// Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)
+        #[structopt(help = "Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)")]
+        pair: String,
+    },
+
     /// Retrieve trades for a specific trader (example: client retrieve-trades Rock)
     #[structopt(name = "retrieve-trades")]
     RetrieveTrades {
@@ -50,39 +314,343 @@ enum Command {
         #[structopt(help = "Trader's identifier")]
         trader: String,
     },
+
+    /// Admin: halt trading in a pair (example: client halt XXBTZUSD 300 --auction --reason news)
+    #[structopt(name = "halt")]
+    Halt {
+        /// Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)
+        #[structopt(help = "Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)")]
+        pair: String,
+
+        /// Seconds until trading resumes
+        #[structopt(help = "Seconds until trading resumes")]
+        duration: u64,
+
+        /// Re-open with a call auction instead of pausing
+        #[structopt(long, help = "Re-open with a call auction instead of pausing")]
+        auction: bool,
+
+        /// Reason published with the halt
+        #[structopt(long, default_value = "", help = "Reason published with the halt")]
+        reason: String,
+    },
+
+    /// Admin: end a pause with the re-opening auction or uncross a call auction (example: client resume XXBTZUSD)
+    #[structopt(name = "resume")]
+    Resume {
+        /// Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)
+        #[structopt(help = "Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)")]
+        pair: String,
+    },
+
+    /// Admin: add a pair with its trading rules from the server's config.yaml (example: client add-pair SUIUSD)
+    #[structopt(name = "add-pair")]
+    AddPair {
+        /// Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)
+        #[structopt(help = "Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)")]
+        pair: String,
+    },
+
+    /// Admin: remove a pair, canceling its orders (example: client remove-pair SUIUSD)
+    #[structopt(name = "remove-pair")]
+    RemovePair {
+        /// Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)
+        #[structopt(help = "Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)")]
+        pair: String,
+    },
+
+    /// Admin: empty a pair's book, canceling its orders (example: client purge-book XXBTZUSD)
+    #[structopt(name = "purge-book")]
+    PurgeBook {
+        /// Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)
+        #[structopt(help = "Trading pair (e.g., XXBTZUSD, XETHZUSD, SUIUSD)")]
+        pair: String,
+    },
+
+    /// Admin: cancel all orders of a trader (example: client cancel-all Rock --pair XXBTZUSD)
+    #[structopt(name = "cancel-all")]
+    CancelAll {
+        /// Trader's identifier
+        #[structopt(help = "Trader's identifier")]
+        trader: String,
+
+        /// Only orders of this pair
+        #[structopt(long, default_value = "", help = "Only orders of this pair")]
+        pair: String,
+    },
+
+    /// Admin: show the pairs, their trading phase and the matching engine queue (example: client engine-status)
+    #[structopt(name = "engine-status")]
+    EngineStatus,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = OrderBookClient::connect("http://[::1]:50051").await?;
+    let channel = tonic::transport::Channel::from_static("http://[::1]:50051").connect().await?;
+    let mut client = OrderBookClient::new(channel.clone());
+    let mut admin = AdminClient::new(channel);
     println!("Connected to Server...");
 
     let args = Cli::from_args();
 
     match args.command {
-        Command::MarketOrder { pair, volume, side, order_type, price, trader } => {
+        Command::MarketOrder {
+            pair,
+            volume,
+            side,
+            order_type,
+            price,
+            trader,
+            stop_price,
+            time_in_force,
+            expire_time,
+            fire_and_forget,
+            client_order_id,
+            self_trade_prevention,
+            post_only,
+            reduce_only,
+            display_volume,
+            trailing_offset,
+        } => {
+            // Market and stop (market) orders carry no limit price, the engine prices market-to-limit and
+            // pegged orders
+            let price = if matches!(
+                order_type,
+                OrderType::Market | OrderType::Stop | OrderType::TrailingStop | OrderType::MarketToLimit |
+                    OrderType::PrimaryPeg | OrderType::MidpointPeg
+            ) {
+                String::new()
+            } else {
+                price.to_string()
+            };
             let market_order_request = tonic::Request::new(OrderRequest {
                 pair,
-                volume,
-                side,
-                order_type,
+                volume: volume.to_string(),
+                side: side.into(),
+                order_type: order_type.into(),
                 price,
                 trader,
+                stop_price: stop_price.to_string(),
+                time_in_force: time_in_force.into(),
+                expire_time,
+                fire_and_forget,
+                client_order_id,
+                self_trade_prevention: self_trade_prevention.into(),
+                post_only: post_only.into(),
+                reduce_only,
+                display_volume: display_volume.to_string(),
+                trailing_offset,
             });
             let response = client.place_market_order(market_order_request).await?;
             println!("Order Response: {:?}", response.into_inner());
         },
+        Command::OrderGroup { group_type, pair, volume, side, trader, take_profit, stop_loss, entry_price } => {
+            let leg = |side: Side, order_type: OrderType, price: &Decimal, volume: String| OrderRequest {
+                pair: pair.clone(),
+                trader: trader.clone(),
+                side: side.into(),
+                order_type: order_type.into(),
+                price: if order_type == OrderType::Limit { price.to_string() } else { String::new() },
+                stop_price: if order_type == OrderType::Stop { price.to_string() } else { String::new() },
+                volume,
+                ..Default::default()
+            };
+            // Bracket exits close the entry's position and take its filled volume
+            let (entry, exit_side, exit_volume) = if group_type == OrderGroupType::Bracket {
+                let entry_type = if entry_price.is_zero() { OrderType::Market } else { OrderType::Limit };
+                let exit_side = if side == Side::Buy { Side::Sell } else { Side::Buy };
+                (Some(leg(side, entry_type, &entry_price, volume.to_string())), exit_side, String::new())
+            } else {
+                (None, side, volume.to_string())
+            };
+            let order_group_request = tonic::Request::new(OrderGroupRequest {
+                group_type: group_type.into(),
+                entry,
+                take_profit: Some(leg(exit_side, OrderType::Limit, &take_profit, exit_volume.clone())),
+                stop_loss: Some(leg(exit_side, OrderType::Stop, &stop_loss, exit_volume)),
+            });
+            let response = client.place_order_group(order_group_request).await?.into_inner();
+            println!("Order group {}: {:?}", response.group_id, response.response);
+            for order in response.orders.iter() {
+                print_order(order);
+            }
+        },
+        Command::CancelOrder { order_id, trader } => {
+            let cancel_order_request = tonic::Request::new(CancelOrderRequest {
+                order_id,
+                trader,
+            });
+            let response = client.cancel_order(cancel_order_request).await?;
+            println!("Cancel Response: {:?}", response.into_inner());
+        },
+        Command::ReplaceOrder { order_id, trader, price, volume } => {
+            let replace_order_request = tonic::Request::new(ReplaceOrderRequest {
+                order_id,
+                trader,
+                price: price.to_string(),
+                volume: volume.to_string(),
+            });
+            let response = client.replace_order(replace_order_request).await?;
+            println!("Replace Response: {:?}", response.into_inner());
+        },
+        Command::OrderByClientId { trader, client_order_id } => {
+            let client_order_request = tonic::Request::new(ClientOrderRequest {
+                trader,
+                client_order_id,
+            });
+            let response = client.get_order_by_client_id(client_order_request).await?;
+            println!("Order Response: {:?}", response.into_inner());
+        },
+        Command::GetOrder { order_id } => {
+            let get_order_request = tonic::Request::new(GetOrderRequest { order_id });
+            let order = client.get_order(get_order_request).await?.into_inner();
+            print_order(&order);
+        },
+        Command::OpenOrders { trader, pair } => {
+            let open_orders_request = tonic::Request::new(OpenOrdersRequest {
+                trader: trader.clone(),
+                pair,
+            });
+            let response = client.list_open_orders(open_orders_request).await?.into_inner();
+            println!("Open orders for trader {}:", trader);
+            for order in response.orders.iter() {
+                print_order(order);
+            }
+        },
+        Command::Depth { pair, levels, group } => {
+            let depth_request = tonic::Request::new(DepthRequest {
+                pair,
+                depth: levels,
+                group: group.to_string(),
+            });
+            let depth = client.get_depth(depth_request).await?.into_inner();
+            println!("Depth for {} #{}:", depth.pair, depth.sequence);
+            for level in depth.asks.iter().rev() {
+                println!("  ask {} {} ({} orders)", level.price, level.volume, level.order_count);
+            }
+            for level in depth.bids.iter() {
+                println!("  bid {} {} ({} orders)", level.price, level.volume, level.order_count);
+            }
+        },
+        Command::WatchOrderBook { pair } => {
+            let order_book_request = tonic::Request::new(OrderBookRequest { pair });
+            let mut stream = client.subscribe_order_book(order_book_request).await?.into_inner();
+            while let Some(update) = stream.message().await? {
+                let kind = if update.snapshot { "snapshot" } else { "update" };
+                println!("{} {} #{}:", update.pair, kind, update.sequence);
+                for level in update.asks.iter().rev() {
+                    println!("  ask {} {}", level.price, level.volume);
+                }
+                for level in update.bids.iter().rev() {
+                    println!("  bid {} {}", level.price, level.volume);
+                }
+            }
+        },
+        Command::WatchTrades { pair } => {
+            let trade_stream_request = tonic::Request::new(TradeStreamRequest { pair });
+            let mut stream = client.subscribe_trades(trade_stream_request).await?.into_inner();
+            while let Some(trade) = stream.message().await? {
+                println!(
+                    "{} {} {} @ {}, ID: {}, Maker: {}, Taker: {}, Timestamp: {}",
+                    trade.pair, display_name(trade.aggressor_side().as_str_name(), "SIDE_"), trade.volume, trade.price, trade.trade_id,
+                    trade.maker_order_id, trade.taker_order_id, trade.timestamp
+                );
+            }
+        },
+        Command::WatchAuction { pair } => {
+            let order_book_request = tonic::Request::new(OrderBookRequest { pair });
+            let mut stream = client.subscribe_auction(order_book_request).await?.into_inner();
+            while let Some(update) = stream.message().await? {
+                let price: &str = if update.indicative_price.is_empty() { "-" } else { &update.indicative_price };
+                println!(
+                    "{} {}, Indicative Price: {}, Volume: {}, Surplus: {}, Uncross: {}",
+                    update.pair, display_name(update.phase().as_str_name(), "TRADING_PHASE_"), price,
+                    update.indicative_volume, update.surplus, update.uncross_time
+                );
+            }
+        },
+        Command::WatchTradingStatus { pair } => {
+            let order_book_request = tonic::Request::new(OrderBookRequest { pair });
+            let mut stream = client.subscribe_trading_status(order_book_request).await?.into_inner();
+            while let Some(update) = stream.message().await? {
+                println!(
+                    "{} {}, Reason: {}, Resume: {}, Timestamp: {}",
+                    update.pair, display_name(update.phase().as_str_name(), "TRADING_PHASE_"), update.reason,
+                    update.resume_time, update.timestamp
+                );
+            }
+        },
         Command::RetrieveTrades { trader } => {
             let trade_book_request = tonic::Request::new(TradeBookRequest {
                 trader: trader.clone(),
             });
             let response = client.get_trade_book(trade_book_request).await?;
             let trade_book_response = response.into_inner();
-            println!("Trades for trader {}:", trader.clone());
-            for trade in trade_book_response.trades {
+            println!("Orders for trader {}:", trader.clone());
+            for order in trade_book_response.orders {
                 println!(
-                    "{}: ID: {}, Pair: {}, Side: {}, Price: {:.5}, Volume: {:.3}, Timestamp: {}",
-                    trade.status, trade.id, trade.pair, trade.side, trade.price, trade.volume, trade.timestamp
+                    "{}: ID: {}, Client order ID: {}, Pair: {}, Side: {}, Price: {}, Filled: {}/{}, Timestamp: {}",
+                    display_name(order.status().as_str_name(), "ORDER_STATUS_"), order.order_id, order.client_order_id, order.pair,
+                    display_name(order.side().as_str_name(), "SIDE_"), order.price, order.filled_volume, order.original_volume,
+                    order.timestamp
+                );
+            }
+            println!("Order groups for trader {}:", trader.clone());
+            for group in trade_book_response.groups.iter() {
+                println!(
+                    "{}: ID: {}, Type: {}, Pair: {}, Volume: {}, Entry: {}, Take profit: {}, Stop loss: {}, Timestamp: {}",
+                    display_name(group.status().as_str_name(), "ORDER_GROUP_STATUS_"), group.group_id,
+                    display_name(group.group_type().as_str_name(), "ORDER_GROUP_TYPE_"), group.pair, group.volume, group.entry_order_id,
+                    group.take_profit_order_id, group.stop_loss_order_id, group.timestamp
+                );
+            }
+            println!("Fills for trader {}:", trader.clone());
+            for fill in trade_book_response.fills {
+                println!(
+                    "ID: {}, Order ID: {}, Side: {}, Price: {}, Volume: {}, Fee: {}, Liquidity: {}, Maker: {}, Taker: {}, Timestamp: {}",
+                    fill.trade_id, fill.order_id, display_name(fill.side().as_str_name(), "SIDE_"), fill.price, fill.volume, fill.fee,
+                    display_name(fill.liquidity().as_str_name(), "LIQUIDITY_"), fill.maker_trader, fill.taker_trader, fill.timestamp
+                );
+            }
+        },
+        Command::Halt { pair, duration, auction, reason } => {
+            let mode: HaltMode = if auction { HaltMode::Auction } else { HaltMode::Pause };
+            let halt_request = tonic::Request::new(HaltRequest { pair, duration, mode: mode.into(), reason });
+            let status = admin.halt_trading(halt_request).await?.into_inner();
+            println!("Halt Response: {:?}", status);
+        },
+        Command::Resume { pair } => {
+            let status = admin.resume_trading(tonic::Request::new(PairRequest { pair })).await?.into_inner();
+            println!("Resume Response: {:?}", status);
+        },
+        Command::AddPair { pair } => {
+            let add_pair_request = tonic::Request::new(AddPairRequest { pair, rules: None });
+            let status = admin.add_pair(add_pair_request).await?.into_inner();
+            println!("Add Pair Response: {:?}", status);
+        },
+        Command::RemovePair { pair } => {
+            let response = admin.remove_pair(tonic::Request::new(PairRequest { pair })).await?.into_inner();
+            println!("Remove Pair Response: {:?}", response);
+        },
+        Command::PurgeBook { pair } => {
+            let response = admin.purge_book(tonic::Request::new(PairRequest { pair })).await?.into_inner();
+            println!("Purge Book Response: {:?}", response);
+        },
+        Command::CancelAll { trader, pair } => {
+            let cancel_all_request = tonic::Request::new(CancelAllRequest { trader, pair });
+            let response = admin.cancel_all_orders(cancel_all_request).await?.into_inner();
+            println!("Cancel All Response: {:?}", response);
+        },
+        Command::EngineStatus => {
+            let status = admin.get_engine_status(tonic::Request::new(EngineStatusRequest {})).await?.into_inner();
+            println!("Queued orders: {}, Traders: {}, Offline: {}, Timestamp: {}", status.queued_orders, status.traders, status.offline, status.timestamp);
+            for pair in status.pairs.iter() {
+                let price = |price: &str| if price.is_empty() { "-".to_string() } else { price.to_string() };
+                println!(
+                    "{} {}, Resume: {}, Resting: {}, Stops: {}, Bid: {}, Ask: {}, Last: {}, Sequence: {}",
+                    pair.pair, display_name(pair.phase().as_str_name(), "TRADING_PHASE_"), price(&pair.resume_time),
+                    pair.resting_orders, pair.stop_orders, price(&pair.best_bid), price(&pair.best_ask),
+                    price(&pair.last_price), pair.sequence
                 );
             }
         },
@@ -94,6 +662,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rust_decimal_macros::dec;
 
     #[test]
     fn test_cli_market_order() {
@@ -108,18 +677,330 @@ mod tests {
             "Rock",
         ];
         let cli = Cli::from_iter_safe(args).unwrap();
-        if let Command::MarketOrder { pair, volume, side, order_type, price, trader } = cli.command {
+        if let Command::MarketOrder { pair, volume, side, order_type, price, trader, stop_price, time_in_force, fire_and_forget, display_volume, .. } = cli.command {
             assert_eq!(pair, "XXBTZUSD");
-            assert_eq!(volume, 0.01);
-            assert_eq!(side, "sell");
-            assert_eq!(order_type, "limit");
-            assert_eq!(price, 65290.1);
+            assert_eq!(volume, dec!(0.01));
+            assert_eq!(side, Side::Sell);
+            assert_eq!(order_type, OrderType::Limit);
+            assert_eq!(price, dec!(65290.1));
             assert_eq!(trader, "Rock");
+            assert_eq!(stop_price, Decimal::ZERO);
+            assert_eq!(time_in_force, TimeInForce::Gtc);
+            assert!(!fire_and_forget);
+            assert_eq!(display_volume, Decimal::ZERO);
+        } else {
+            panic!("Expected MarketOrder command");
+        }
+    }
+
+    #[test]
+    fn test_cli_stop_order() {
+        let args = vec![
+            "client",
+            "market-order",
+            "XXBTZUSD",
+            "0.01",
+            "sell",
+            "stop_limit",
+            "65000.0",
+            "Rock",
+            "--stop-price",
+            "65100.0",
+            "--fire-and-forget",
+        ];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::MarketOrder { order_type, price, stop_price, fire_and_forget, .. } = cli.command {
+            assert!(fire_and_forget);
+            assert_eq!(order_type, OrderType::StopLimit);
+            assert_eq!(price, dec!(65000.0));
+            assert_eq!(stop_price, dec!(65100.0));
+        } else {
+            panic!("Expected MarketOrder command");
+        }
+    }
+
+    #[test]
+    fn test_cli_unknown_enum_values() {
+        let order = |side: &'static str, order_type: &'static str| vec!["client", "market-order", "XXBTZUSD", "0.01", side, order_type, "65290.1", "Rock"];
+        assert!(Cli::from_iter_safe(order("byu", "limit")).is_err());
+        assert!(Cli::from_iter_safe(order("buy", "stoplimit")).is_err());
+        let cli = Cli::from_iter_safe(order("Buy", "STOP_LIMIT")).unwrap();
+        if let Command::MarketOrder { side, order_type, .. } = cli.command {
+            assert_eq!((side, order_type), (Side::Buy, OrderType::StopLimit));
+        } else {
+            panic!("Expected MarketOrder command");
+        }
+    }
+
+    #[test]
+    fn test_cli_time_in_force() {
+        let args = vec![
+            "client",
+            "market-order",
+            "XXBTZUSD",
+            "0.01",
+            "buy",
+            "limit",
+            "65200.0",
+            "Rock",
+            "--time-in-force",
+            "gtd",
+            "--expire-time",
+            "2024-06-19T21:00:00Z",
+        ];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::MarketOrder { time_in_force, expire_time, .. } = cli.command {
+            assert_eq!(time_in_force, TimeInForce::Gtd);
+            assert_eq!(expire_time, "2024-06-19T21:00:00Z");
+        } else {
+            panic!("Expected MarketOrder command");
+        }
+    }
+
+    #[test]
+    fn test_cli_client_order_id() {
+        let args = vec!["client", "market-order", "XXBTZUSD", "0.01", "buy", "market", "0", "Rock", "--client-order-id", "bot-42"];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::MarketOrder { client_order_id, .. } = cli.command {
+            assert_eq!(client_order_id, "bot-42");
+        } else {
+            panic!("Expected MarketOrder command");
+        }
+
+        let cli = Cli::from_iter_safe(vec!["client", "order-by-client-id", "Rock", "bot-42"]).unwrap();
+        if let Command::OrderByClientId { trader, client_order_id } = cli.command {
+            assert_eq!((trader.as_str(), client_order_id.as_str()), ("Rock", "bot-42"));
+        } else {
+            panic!("Expected OrderByClientId command");
+        }
+    }
+
+    #[test]
+    fn test_cli_self_trade_prevention() {
+        let args = |extra: &'static str| {
+            let mut args = vec!["client", "market-order", "XXBTZUSD", "0.01", "buy", "market", "0", "Rock"];
+            if !extra.is_empty() {
+                args.extend(["--self-trade-prevention", extra]);
+            }
+            args
+        };
+        let mode = |extra: &'static str| match Cli::from_iter_safe(args(extra)).map(|cli| cli.command) {
+            Ok(Command::MarketOrder { self_trade_prevention, .. }) => Ok(self_trade_prevention),
+            Ok(_) => panic!("Expected MarketOrder command"),
+            Err(e) => Err(e),
+        };
+        assert_eq!(mode("").unwrap(), SelfTradePrevention::Unspecified);
+        assert_eq!(mode("decrement_and_cancel").unwrap(), SelfTradePrevention::DecrementAndCancel);
+        assert_eq!(mode("CANCEL_OLDEST").unwrap(), SelfTradePrevention::CancelOldest);
+        assert!(mode("cancel_all").is_err());
+    }
+
+    #[test]
+    fn test_cli_iceberg_order() {
+        let args = vec!["client", "market-order", "XXBTZUSD", "5", "buy", "limit", "65290.1", "Rock", "--display-volume", "0.5"];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::MarketOrder { volume, display_volume, .. } = cli.command {
+            assert_eq!((volume, display_volume), (dec!(5), dec!(0.5)));
+        } else {
+            panic!("Expected MarketOrder command");
+        }
+    }
+
+    #[test]
+    fn test_cli_trailing_stop() {
+        let args = vec!["client", "market-order", "XXBTZUSD", "0.5", "sell", "trailing_stop", "0", "Rock", "--trailing-offset", "2%"];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::MarketOrder { order_type, trailing_offset, .. } = cli.command {
+            assert_eq!(order_type, OrderType::TrailingStop);
+            assert_eq!(trailing_offset, "2%");
         } else {
             panic!("Expected MarketOrder command");
         }
     }
 
+    #[test]
+    fn test_cli_market_to_limit_and_pegged() {
+        for (order_type, expected) in [
+            ("market_to_limit", OrderType::MarketToLimit),
+            ("primary_peg", OrderType::PrimaryPeg),
+            ("midpoint_peg", OrderType::MidpointPeg),
+        ] {
+            let args = vec!["client", "market-order", "XXBTZUSD", "0.5", "buy", order_type, "0", "Rock"];
+            let cli = Cli::from_iter_safe(args).unwrap();
+            if let Command::MarketOrder { order_type, .. } = cli.command {
+                assert_eq!(order_type, expected);
+            } else {
+                panic!("Expected MarketOrder command");
+            }
+        }
+    }
+
+    #[test]
+    fn test_cli_order_group() {
+        let args = vec!["client", "order-group", "bracket", "XXBTZUSD", "1.0", "buy", "Rock", "70000.0", "60000.0", "--entry-price", "65000.0"];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::OrderGroup { group_type, side, take_profit, stop_loss, entry_price, .. } = cli.command {
+            assert_eq!((group_type, side), (OrderGroupType::Bracket, Side::Buy));
+            assert_eq!((take_profit, stop_loss, entry_price), (dec!(70000.0), dec!(60000.0), dec!(65000.0)));
+        } else {
+            panic!("Expected OrderGroup command");
+        }
+        let args = vec!["client", "order-group", "one_cancels_other", "XXBTZUSD", "1.0", "sell", "Rock", "70000.0", "60000.0"];
+        assert!(Cli::from_iter_safe(args).is_err());
+    }
+
+    #[test]
+    fn test_cli_post_only_reduce_only() {
+        let args = vec!["client", "market-order", "XXBTZUSD", "0.01", "sell", "limit", "65290.1", "Rock", "--post-only", "reprice", "--reduce-only"];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::MarketOrder { post_only, reduce_only, .. } = cli.command {
+            assert_eq!(post_only, PostOnly::Reprice);
+            assert!(reduce_only);
+        } else {
+            panic!("Expected MarketOrder command");
+        }
+
+        let cli = Cli::from_iter_safe(vec!["client", "market-order", "XXBTZUSD", "0.01", "sell", "limit", "65290.1", "Rock"]).unwrap();
+        if let Command::MarketOrder { post_only, reduce_only, .. } = cli.command {
+            assert_eq!((post_only, reduce_only), (PostOnly::Unspecified, false));
+        } else {
+            panic!("Expected MarketOrder command");
+        }
+        assert!(Cli::from_iter_safe(vec!["client", "market-order", "XXBTZUSD", "0.01", "sell", "limit", "65290.1", "Rock", "--post-only", "yes"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_cancel_order() {
+        let args = vec!["client", "cancel-order", "4b80e237-5db6-4e96-b677-98b32574716b", "Rock"];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::CancelOrder { order_id, trader } = cli.command {
+            assert_eq!(order_id, "4b80e237-5db6-4e96-b677-98b32574716b");
+            assert_eq!(trader, "Rock");
+        } else {
+            panic!("Expected CancelOrder command");
+        }
+    }
+
+    #[test]
+    fn test_cli_replace_order() {
+        let args = vec![
+            "client",
+            "replace-order",
+            "4b80e237-5db6-4e96-b677-98b32574716b",
+            "Rock",
+            "65250.0",
+            "0.5",
+        ];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::ReplaceOrder { order_id, trader, price, volume } = cli.command {
+            assert_eq!(order_id, "4b80e237-5db6-4e96-b677-98b32574716b");
+            assert_eq!(trader, "Rock");
+            assert_eq!(price, dec!(65250.0));
+            assert_eq!(volume, dec!(0.5));
+        } else {
+            panic!("Expected ReplaceOrder command");
+        }
+    }
+
+    #[test]
+    fn test_cli_order_lookup() {
+        let cli = Cli::from_iter_safe(vec!["client", "get-order", "4b80e237-5db6-4e96-b677-98b32574716b"]).unwrap();
+        if let Command::GetOrder { order_id } = cli.command {
+            assert_eq!(order_id, "4b80e237-5db6-4e96-b677-98b32574716b");
+        } else {
+            panic!("Expected GetOrder command");
+        }
+
+        let cli = Cli::from_iter_safe(vec!["client", "open-orders", "Rock"]).unwrap();
+        if let Command::OpenOrders { trader, pair } = cli.command {
+            assert_eq!((trader.as_str(), pair.as_str()), ("Rock", ""));
+        } else {
+            panic!("Expected OpenOrders command");
+        }
+    }
+
+    #[test]
+    fn test_cli_depth() {
+        let args = vec!["client", "depth", "XXBTZUSD", "--group", "10"];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::Depth { pair, levels, group } = cli.command {
+            assert_eq!(pair, "XXBTZUSD");
+            assert_eq!(levels, 10);
+            assert_eq!(group, dec!(10));
+        } else {
+            panic!("Expected Depth command");
+        }
+    }
+
+    #[test]
+    fn test_cli_watch_order_book() {
+        let args = vec!["client", "watch-order-book", "XXBTZUSD"];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::WatchOrderBook { pair } = cli.command {
+            assert_eq!(pair, "XXBTZUSD");
+        } else {
+            panic!("Expected WatchOrderBook command");
+        }
+    }
+
+    #[test]
+    fn test_cli_watch_trades() {
+        let args = vec!["client", "watch-trades", "XXBTZUSD"];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::WatchTrades { pair } = cli.command {
+            assert_eq!(pair, "XXBTZUSD");
+        } else {
+            panic!("Expected WatchTrades command");
+        }
+    }
+
+    #[test]
+    fn test_cli_watch_auction() {
+        let args = vec!["client", "watch-auction", "XXBTZUSD"];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::WatchAuction { pair } = cli.command {
+            assert_eq!(pair, "XXBTZUSD");
+        } else {
+            panic!("Expected WatchAuction command");
+        }
+    }
+
+    #[test]
+    fn test_cli_watch_trading_status() {
+        let args = vec!["client", "watch-trading-status", "XXBTZUSD"];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::WatchTradingStatus { pair } = cli.command {
+            assert_eq!(pair, "XXBTZUSD");
+        } else {
+            panic!("Expected WatchTradingStatus command");
+        }
+    }
+
+    #[test]
+    fn test_cli_halt() {
+        let args = vec!["client", "halt", "XXBTZUSD", "300", "--auction", "--reason", "news"];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::Halt { pair, duration, auction, reason } = cli.command {
+            assert_eq!((pair.as_str(), duration, auction, reason.as_str()), ("XXBTZUSD", 300, true, "news"));
+        } else {
+            panic!("Expected Halt command");
+        }
+        assert!(Cli::from_iter_safe(vec!["client", "halt", "XXBTZUSD"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_cancel_all() {
+        let args = vec!["client", "cancel-all", "Rock", "--pair", "XXBTZUSD"];
+        let cli = Cli::from_iter_safe(args).unwrap();
+        if let Command::CancelAll { trader, pair } = cli.command {
+            assert_eq!((trader.as_str(), pair.as_str()), ("Rock", "XXBTZUSD"));
+        } else {
+            panic!("Expected CancelAll command");
+        }
+        let cli = Cli::from_iter_safe(vec!["client", "engine-status"]).unwrap();
+        assert!(matches!(cli.command, Command::EngineStatus));
+    }
+
     #[test]
     fn test_cli_retrieve_trades() {
         let args = vec!["client", "retrieve-trades", "Rock"];
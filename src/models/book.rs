@@ -0,0 +1,417 @@
+use std::collections::{ BTreeMap, BTreeSet, HashMap, VecDeque };
+use chrono::{ DateTime, Utc };
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+use crate::models::model::models::{ Fill, Order, Side };
+
+type Levels = BTreeMap<Decimal, VecDeque<Order>>;
+
+// Aggregated view of one price level (volume 0 means the level is gone)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Level {
+    pub price: Decimal,
+    pub volume: Decimal,
+    pub order_count: usize,
+}
+
+impl Level {
+    fn aggregate(price: Decimal, queue: Option<&VecDeque<Order>>) -> Level {
+        Level {
+            price,
+            volume: queue.into_iter().flatten().map(|o| o.volume).sum(),
+            order_count: queue.map_or(0, |q| q.len()),
+        }
+    }
+}
+
+// Outcome of a call auction at its current state: the single price executing the most volume
+// between crossing bids and asks, and the volume left over at that price (positive on the buy side)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Equilibrium {
+    pub price: Decimal,
+    pub volume: Decimal,
+    pub surplus: Decimal,
+}
+
+// Order book for a single pair: bids and asks keyed by price level, FIFO queue inside each level
+// (price-time priority) and an index by order id so lookups/removals don't scan the whole book
+#[derive(Debug, Clone, Default)]
+pub struct Book {
+    bids: Levels,
+    asks: Levels,
+    index: HashMap<Uuid, (Side, Decimal)>,
+    last_price: Option<Decimal>,
+    sequence: u64, // increases with every batch of level changes taken for publishing
+    changed: BTreeSet<(Side, Decimal)>, // levels touched since the last batch
+    fills: Vec<Fill>, // executions not yet published on the trade stream
+    auction: Option<DateTime<Utc>>, // uncross time while the pair is in a call auction
+    halt: Option<DateTime<Utc>>, // resume time while trading in the pair is halted
+    trade_prices: VecDeque<(DateTime<Utc>, Decimal)>, // recent trade prices for the volatility check
+    working_groups: BTreeSet<(String, Uuid)>, // (trader, group id) of the pair's working order groups
+    pegged: BTreeSet<Uuid>, // resting pegged orders, repriced when the quotes they follow change
+    peg_quotes: (Option<Decimal>, Option<Decimal>), // best bid and ask the pegs were last priced at
+}
+
+impl Book {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Buy orders rest as bids, sell orders as asks
+    fn levels(&self, side: Side) -> &Levels {
+        match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        }
+    }
+
+    fn levels_mut(&mut self, side: Side) -> &mut Levels {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    // Add an order to the back of its price level
+    pub fn insert(&mut self, order: Order) {
+        self.remove(&order.id);
+        self.changed.insert((order.side, order.price));
+        self.push(order);
+    }
+
+    fn push(&mut self, order: Order) {
+        self.index.insert(order.id, (order.side, order.price));
+        if order.order_type.is_pegged() {
+            self.pegged.insert(order.id);
+        }
+        self.levels_mut(order.side).entry(order.price).or_default().push_back(order);
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<&Order> {
+        let (side, price) = self.index.get(id)?;
+        self.levels(*side)
+            .get(price)?
+            .iter()
+            .find(|o| o.id == *id)
+    }
+
+    // Mutable access marks the level as changed, callers may update the volume in place
+    pub fn get_mut(&mut self, id: &Uuid) -> Option<&mut Order> {
+        let (side, price) = *self.index.get(id)?;
+        self.changed.insert((side, price));
+        self.levels_mut(side)
+            .get_mut(&price)?
+            .iter_mut()
+            .find(|o| o.id == *id)
+    }
+
+    pub fn remove(&mut self, id: &Uuid) -> Option<Order> {
+        let (side, price) = self.index.remove(id)?;
+        self.pegged.remove(id);
+        self.changed.insert((side, price));
+        let levels: &mut Levels = self.levels_mut(side);
+        let queue: &mut VecDeque<Order> = levels.get_mut(&price)?;
+        let pos: usize = queue.iter().position(|o| o.id == *id)?;
+        let order: Option<Order> = queue.remove(pos);
+        if queue.is_empty() {
+            levels.remove(&price);
+        }
+        order
+    }
+
+    // Order with the highest priority on the given side (best price, oldest first)
+    pub fn best(&self, side: Side) -> Option<&Order> {
+        match side {
+            Side::Buy => self.bids.values().next_back()?.front(),
+            Side::Sell => self.asks.values().next()?.front(),
+        }
+    }
+
+    pub fn best_mut(&mut self, side: Side) -> Option<&mut Order> {
+        let price: Decimal = match side {
+            Side::Buy => self.best_bid()?,
+            Side::Sell => self.best_ask()?,
+        };
+        self.changed.insert((side, price));
+        self.levels_mut(side).get_mut(&price)?.front_mut()
+    }
+
+    // Price of the last trade matched against this book
+    pub fn last_price(&self) -> Option<Decimal> {
+        self.last_price
+    }
+
+    // Record an execution against this book (updates the last price)
+    pub fn record_fill(&mut self, fill: Fill) {
+        self.last_price = Some(fill.price);
+        self.trade_prices.push_back((Utc::now(), fill.price));
+        self.fills.push(fill);
+    }
+
+    // Executions recorded but not taken yet, oldest first
+    pub fn fills(&self) -> &[Fill] {
+        &self.fills
+    }
+
+    // Executions recorded since the last call, oldest first
+    pub fn take_fills(&mut self) -> Vec<Fill> {
+        std::mem::take(&mut self.fills)
+    }
+
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.bids.keys().next_back().copied()
+    }
+
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.asks.keys().next().copied()
+    }
+
+    // Asks in matching priority (lowest price first)
+    pub fn asks(&self) -> impl Iterator<Item = &Order> {
+        self.asks.values().flatten()
+    }
+
+    // Bids in matching priority (highest price first)
+    pub fn bids(&self) -> impl Iterator<Item = &Order> {
+        self.bids.values().rev().flatten()
+    }
+
+    // All orders as displayed/persisted: asks then bids, both by descending price
+    pub fn iter(&self) -> impl Iterator<Item = &Order> {
+        self.asks.values().rev().flatten().chain(self.bids())
+    }
+
+    // Aggregated levels of one side, best price first
+    pub fn depth(&self, side: Side) -> Vec<Level> {
+        let aggregate = |(price, queue): (&Decimal, &VecDeque<Order>)| {
+            Level::aggregate(*price, Some(queue))
+        };
+        match side {
+            Side::Buy => self.bids.iter().rev().map(aggregate).collect(),
+            Side::Sell => self.asks.iter().map(aggregate).collect(),
+        }
+    }
+
+    // Aggregated levels of one side merged into price buckets of the given size (bids rounded down,
+    // asks rounded up, so a bucket never looks better than its orders), best first and limited to
+    // max_levels (0 for all)
+    pub fn grouped_depth(&self, side: Side, max_levels: usize, group: Decimal) -> Vec<Level> {
+        let mut grouped: Vec<Level> = Vec::new();
+        for level in self.depth(side) {
+            let price: Decimal = if group <= Decimal::ZERO {
+                level.price
+            } else if side == Side::Buy {
+                ((level.price / group).floor() * group).normalize()
+            } else {
+                ((level.price / group).ceil() * group).normalize()
+            };
+            match grouped.last_mut() {
+                Some(last) if last.price == price => {
+                    last.volume += level.volume;
+                    last.order_count += level.order_count;
+                }
+                _ => {
+                    if max_levels > 0 && grouped.len() == max_levels {
+                        break;
+                    }
+                    grouped.push(Level { price, ..level });
+                }
+            }
+        }
+        grouped
+    }
+
+    // Stop continuous matching until the uncross time, incoming orders only rest in the book meanwhile
+    pub fn start_auction(&mut self, uncross_at: DateTime<Utc>) {
+        self.auction = Some(uncross_at);
+    }
+
+    pub fn end_auction(&mut self) {
+        self.auction = None;
+    }
+
+    // Uncross time of the running call auction, None during continuous trading
+    pub fn auction(&self) -> Option<DateTime<Utc>> {
+        self.auction
+    }
+
+    // Reject new orders until the resume time
+    pub fn halt(&mut self, resume_at: DateTime<Utc>) {
+        self.halt = Some(resume_at);
+    }
+
+    pub fn resume(&mut self) {
+        self.halt = None;
+    }
+
+    // Resume time of the running halt, None while the pair trades
+    pub fn halted(&self) -> Option<DateTime<Utc>> {
+        self.halt
+    }
+
+    // Lowest and highest price traded since the given time (older trades are forgotten), None
+    // without trades
+    pub fn price_range(&mut self, since: DateTime<Utc>) -> Option<(Decimal, Decimal)> {
+        while self.trade_prices.front().is_some_and(|(time, _)| *time < since) {
+            self.trade_prices.pop_front();
+        }
+        let prices = self.trade_prices.iter().map(|(_, price)| *price);
+        Some((prices.clone().min()?, prices.max()?))
+    }
+
+    // Forget the recent trade prices, so a pair resuming after a halt isn't halted again by the
+    // move that halted it
+    pub fn clear_trade_prices(&mut self) {
+        self.trade_prices.clear();
+    }
+
+    // Best price of one side without the pegged orders, the price pegs follow
+    pub fn best_unpegged(&self, side: Side) -> Option<Decimal> {
+        let orders: Box<dyn Iterator<Item = &Order>> = match side {
+            Side::Buy => Box::new(self.bids()),
+            Side::Sell => Box::new(self.asks()),
+        };
+        orders
+            .filter(|o| !self.pegged.contains(&o.id))
+            .map(|o| o.price)
+            .next()
+    }
+
+    pub fn pegged_orders(&self) -> Vec<Uuid> {
+        self.pegged.iter().copied().collect()
+    }
+
+    // Record the best bid and ask the pegs are priced at; false when they haven't changed since the
+    // last time, so the pegs don't need repricing
+    pub fn update_peg_quotes(&mut self) -> bool {
+        let quotes: (Option<Decimal>, Option<Decimal>) = (self.best_unpegged(Side::Buy), self.best_unpegged(Side::Sell));
+        if quotes == self.peg_quotes {
+            return false;
+        }
+        self.peg_quotes = quotes;
+        true
+    }
+
+    // Order groups still working in this pair, settled after every matching step
+    pub fn add_group(&mut self, trader: &str, group_id: Uuid) {
+        self.working_groups.insert((trader.to_string(), group_id));
+    }
+
+    pub fn end_group(&mut self, trader: &str, group_id: Uuid) {
+        self.working_groups.remove(&(trader.to_string(), group_id));
+    }
+
+    pub fn working_groups(&self) -> Vec<(String, Uuid)> {
+        self.working_groups.iter().cloned().collect()
+    }
+
+    // Equilibrium of the crossing part of the book (hidden iceberg reserves included), None when bids
+    // and asks don't cross. The price executes the most volume; ties go to the smallest surplus, then
+    // to the price closest to the last trade, then to the lowest price.
+    pub fn equilibrium(&self) -> Option<Equilibrium> {
+        let last_price: Option<Decimal> = self.last_price;
+        self.bids
+            .keys()
+            .chain(self.asks.keys())
+            .filter_map(|price| {
+                let demand: Decimal = self.bids
+                    .range(*price..)
+                    .flat_map(|(_, queue)| queue)
+                    .map(Order::total_volume)
+                    .sum();
+                let supply: Decimal = self.asks
+                    .range(..=*price)
+                    .flat_map(|(_, queue)| queue)
+                    .map(Order::total_volume)
+                    .sum();
+                let volume: Decimal = demand.min(supply);
+                (volume > Decimal::ZERO).then_some(Equilibrium { price: *price, volume, surplus: demand - supply })
+            })
+            .max_by_key(|equilibrium| {
+                let distance: Decimal = last_price.map_or(Decimal::ZERO, |last| (equilibrium.price - last).abs());
+                (equilibrium.volume, -equilibrium.surplus.abs(), -distance, -equilibrium.price)
+            })
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    // Aggregated state of every level changed since the last call, tagged with the next sequence
+    // number (None when nothing changed)
+    pub fn take_changes(&mut self) -> Option<(u64, Vec<(Side, Level)>)> {
+        if self.changed.is_empty() {
+            return None;
+        }
+        let changes: Vec<(Side, Level)> = std::mem::take(&mut self.changed)
+            .into_iter()
+            .map(|(side, price)| {
+                let queue: Option<&VecDeque<Order>> = self.levels(side).get(&price);
+                let level: Level = Level::aggregate(price, queue);
+                (side, level)
+            })
+            .collect();
+        self.sequence += 1;
+        Some((self.sequence, changes))
+    }
+
+    // Replace the orders with a freshly fetched book, keeping last price and sequence and marking
+    // only the levels whose aggregated volume or order count differ; orders owned by traders stay,
+    // behind the fetched liquidity of their level
+    pub fn refresh(&mut self, fresh: Book) {
+        let before: HashMap<(Side, Decimal), Level> = self.level_map();
+        let owned: Vec<Order> = self
+            .iter()
+            .filter(|o| !o.trader.is_empty())
+            .cloned()
+            .collect();
+        self.bids = fresh.bids;
+        self.asks = fresh.asks;
+        self.index = fresh.index;
+        for order in owned {
+            self.push(order);
+        }
+        let after: HashMap<(Side, Decimal), Level> = self.level_map();
+
+        for (key, level) in before.iter() {
+            if after.get(key) != Some(level) {
+                self.changed.insert(*key);
+            }
+        }
+        for key in after.keys() {
+            if !before.contains_key(key) {
+                self.changed.insert(*key);
+            }
+        }
+    }
+
+    fn level_map(&self) -> HashMap<(Side, Decimal), Level> {
+        [Side::Sell, Side::Buy]
+            .into_iter()
+            .flat_map(|side| {
+                self.depth(side)
+                    .into_iter()
+                    .map(move |level| ((side, level.price), level))
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+}
+
+impl FromIterator<Order> for Book {
+    fn from_iter<I: IntoIterator<Item = Order>>(iter: I) -> Self {
+        let mut book: Book = Book::new();
+        for order in iter {
+            book.insert(order);
+        }
+        book
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use rust_decimal::Decimal;
+
+use crate::models::model::models::{ Execution, OrderGroup, OrderRecord, Side };
+
+// Trade book of a single trader: the order ledger (one record per order with its status
+// transitions, in placing order), the fills ledger (one record per execution of the trader's
+// orders, oldest first) and the trader's order groups
+#[derive(Debug, Clone, Default)]
+pub struct TradeBook {
+    orders: Vec<OrderRecord>,
+    index: HashMap<Uuid, usize>, // position of each order in the ledger
+    fills: Vec<Execution>,
+    groups: Vec<OrderGroup>,
+}
+
+impl TradeBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_order(&mut self, record: OrderRecord) {
+        self.index.insert(record.id, self.orders.len());
+        self.orders.push(record);
+    }
+
+    pub fn order(&self, id: &Uuid) -> Option<&OrderRecord> {
+        self.orders.get(*self.index.get(id)?)
+    }
+
+    pub fn order_mut(&mut self, id: &Uuid) -> Option<&mut OrderRecord> {
+        self.orders.get_mut(*self.index.get(id)?)
+    }
+
+    pub fn orders(&self) -> &[OrderRecord] {
+        &self.orders
+    }
+
+    pub fn add_fill(&mut self, fill: Execution) {
+        self.fills.push(fill);
+    }
+
+    pub fn fills(&self) -> &[Execution] {
+        &self.fills
+    }
+
+    pub fn add_group(&mut self, group: OrderGroup) {
+        self.groups.push(group);
+    }
+
+    pub fn group(&self, id: &Uuid) -> Option<&OrderGroup> {
+        self.groups.iter().find(|group| group.id == *id)
+    }
+
+    pub fn group_mut(&mut self, id: &Uuid) -> Option<&mut OrderGroup> {
+        self.groups.iter_mut().find(|group| group.id == *id)
+    }
+
+    pub fn groups(&self) -> &[OrderGroup] {
+        &self.groups
+    }
+
+    // Net position in a pair from the fills ledger: bought minus sold volume
+    pub fn position(&self, pair: &str) -> Decimal {
+        self.fills
+            .iter()
+            .filter(|fill| fill.pair == pair)
+            .map(|fill| if fill.side == Side::Buy { fill.volume } else { -fill.volume })
+            .sum()
+    }
+}
@@ -1 +1,3 @@
-pub mod model;
\ No newline at end of file
+pub mod model;
+pub mod book;
+pub mod ledger;
\ No newline at end of file
@@ -1,7 +1,10 @@
 pub mod models {
+    use std::collections::HashMap;
     use std::fmt;
-    use ordered_float::OrderedFloat;
-    use serde::{ Serialize, Serializer, ser::SerializeStruct, Deserialize, Deserializer };
+    use std::str::FromStr;
+    use chrono::{ DateTime, Utc };
+    use rust_decimal::Decimal;
+    use serde::{ de, Serialize, Serializer, ser::SerializeStruct, Deserialize, Deserializer };
     use uuid::Uuid;
 
     use colored::*;
@@ -22,17 +25,370 @@ pub mod models {
         pub symbols: Vec<String>,
         pub persist: String,
         pub offline: Vec<String>,
+        #[serde(default)]
+        pub stop_trigger: StopTrigger,
+        #[serde(default = "default_session_close")]
+        pub session_close: String, // UTC time when DAY orders expire (HH:MM:SS)
+        #[serde(default)]
+        pub instruments: HashMap<String, Instrument>, // per pair settings, missing pairs use defaults
+        #[serde(default = "default_client_order_window")]
+        pub client_order_window: u64, // seconds a client_order_id stays reserved per trader
+        #[serde(default)]
+        pub self_trade_prevention: HashMap<String, SelfTradePrevention>, // default mode per trader
+        #[serde(default = "default_auction_duration")]
+        pub auction_duration: u64, // seconds orders are collected before a call auction uncrosses
+    }
+
+    impl KrakenConfig {
+        // Tradable pairs with their trading rules (orders for other pairs are rejected)
+        pub fn instrument_registry(&self) -> HashMap<String, Instrument> {
+            self.symbols
+                .iter()
+                .map(|pair| (pair.clone(), self.instruments.get(pair).copied().unwrap_or_default()))
+                .collect()
+        }
+    }
+
+    fn default_session_close() -> String {
+        "00:00:00".to_string()
+    }
+
+    fn default_client_order_window() -> u64 {
+        86400
+    }
+
+    fn default_auction_duration() -> u64 {
+        60
+    }
+
+    // Trading rules of a pair: decimal places allowed for prices and volumes plus tick/lot size and
+    // order size limits, price protections and the volatility circuit breaker (zero disables a limit)
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+    pub struct Instrument {
+        #[serde(default = "default_scale")]
+        pub price_scale: u32,
+        #[serde(default = "default_scale")]
+        pub volume_scale: u32,
+        #[serde(default)]
+        pub tick_size: Decimal, // prices must be a multiple of the tick size
+        #[serde(default)]
+        pub lot_size: Decimal, // volumes must be a multiple of the lot size
+        #[serde(default)]
+        pub min_volume: Decimal,
+        #[serde(default)]
+        pub max_volume: Decimal,
+        #[serde(default)]
+        pub max_price: Decimal, // highest limit or stop price accepted
+        #[serde(default)]
+        pub min_notional: Decimal, // minimum price * volume of priced orders
+        #[serde(default)]
+        pub maker_fee: Decimal, // fee rate of the resting order's trader (e.g. 0.0016 for 0.16%)
+        #[serde(default)]
+        pub taker_fee: Decimal, // fee rate of the incoming order's trader
+        #[serde(default)]
+        pub price_band: Decimal, // percent from the reference price market orders may execute at
+        #[serde(default)]
+        pub limit_collar: Decimal, // percent limit prices may cross the reference price by
+        #[serde(default)]
+        pub halt_move: Decimal, // percent move of trade prices within halt_window that halts the pair
+        #[serde(default = "default_halt_seconds")]
+        pub halt_window: u64, // seconds
+        #[serde(default = "default_halt_seconds")]
+        pub halt_duration: u64, // seconds
+        #[serde(default)]
+        pub halt_mode: HaltMode,
+    }
+
+    fn default_scale() -> u32 {
+        8
+    }
+
+    fn default_halt_seconds() -> u64 {
+        60
+    }
+
+    // What a volatility halt does to a pair until it resumes
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum HaltMode {
+        #[default]
+        Pause, // new orders are rejected, cancels are still accepted; re-opens with a call auction
+        Auction, // orders are collected in a call auction that uncrosses at the resume time
+    }
+
+    impl Default for Instrument {
+        fn default() -> Self {
+            Instrument {
+                price_scale: default_scale(),
+                volume_scale: default_scale(),
+                tick_size: Decimal::ZERO,
+                lot_size: Decimal::ZERO,
+                min_volume: Decimal::ZERO,
+                max_volume: Decimal::ZERO,
+                max_price: Decimal::ZERO,
+                min_notional: Decimal::ZERO,
+                maker_fee: Decimal::ZERO,
+                taker_fee: Decimal::ZERO,
+                price_band: Decimal::ZERO,
+                limit_collar: Decimal::ZERO,
+                halt_move: Decimal::ZERO,
+                halt_window: default_halt_seconds(),
+                halt_duration: default_halt_seconds(),
+                halt_mode: HaltMode::default(),
+            }
+        }
+    }
+
+    impl Instrument {
+        // Smallest price step: the tick size, or one unit of the price scale without one
+        pub fn price_increment(&self) -> Decimal {
+            if self.tick_size > Decimal::ZERO {
+                self.tick_size
+            } else {
+                Decimal::new(1, self.price_scale)
+            }
+        }
+    }
+
+    // Reference price used to trigger stop orders
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum StopTrigger {
+        #[default]
+        LastTrade, // last price traded in the exchange
+        BestQuote, // best ask for buy stops, best bid for sell stops
+    }
+
+    // Side of an order; buy orders rest in the book as bids, sell orders as asks
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub enum Side {
+        #[default]
+        Buy,
+        Sell,
+    }
+
+    impl Side {
+        pub fn opposite(self) -> Side {
+            match self {
+                Side::Buy => Side::Sell,
+                Side::Sell => Side::Buy,
+            }
+        }
+
+        // Name of the book side (bid or ask) as used in CSV snapshots and the Kraken API
+        pub fn book_side(self) -> &'static str {
+            match self {
+                Side::Buy => "bid",
+                Side::Sell => "ask",
+            }
+        }
+
+        pub fn from_book_side(value: &str) -> Option<Side> {
+            match value {
+                "bid" => Some(Side::Buy),
+                "ask" => Some(Side::Sell),
+                _ => None,
+            }
+        }
+    }
+
+    impl fmt::Display for Side {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(match self {
+                Side::Buy => "buy",
+                Side::Sell => "sell",
+            })
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum OrderType {
+        Market,
+        #[default]
+        Limit,
+        Stop, // stop (market) order, released as a market order
+        StopLimit, // released as a limit order
+        TrailingStop, // stop (market) order whose stop price follows the reference price
+        MarketToLimit, // takes the best price, the rest rests as a limit order at that price
+        PrimaryPeg, // price follows the best bid (buy) or ask (sell)
+        MidpointPeg, // price follows the midpoint of the best bid and ask
+    }
+
+    impl OrderType {
+        pub fn as_str(self) -> &'static str {
+            match self {
+                OrderType::Market => "market",
+                OrderType::Limit => "limit",
+                OrderType::Stop => "stop",
+                OrderType::StopLimit => "stop_limit",
+                OrderType::TrailingStop => "trailing_stop",
+                OrderType::MarketToLimit => "market_to_limit",
+                OrderType::PrimaryPeg => "primary_peg",
+                OrderType::MidpointPeg => "midpoint_peg",
+            }
+        }
+
+        pub fn is_stop(self) -> bool {
+            matches!(self, OrderType::Stop | OrderType::StopLimit | OrderType::TrailingStop)
+        }
+
+        pub fn is_pegged(self) -> bool {
+            matches!(self, OrderType::PrimaryPeg | OrderType::MidpointPeg)
+        }
+    }
+
+    impl FromStr for OrderType {
+        type Err = String;
+
+        fn from_str(value: &str) -> Result<OrderType, String> {
+            match value {
+                "market" => Ok(OrderType::Market),
+                "limit" => Ok(OrderType::Limit),
+                "stop" => Ok(OrderType::Stop),
+                "stop_limit" => Ok(OrderType::StopLimit),
+                "trailing_stop" => Ok(OrderType::TrailingStop),
+                "market_to_limit" => Ok(OrderType::MarketToLimit),
+                "primary_peg" => Ok(OrderType::PrimaryPeg),
+                "midpoint_peg" => Ok(OrderType::MidpointPeg),
+                other => Err(format!("Unknown order type: {}", other)),
+            }
+        }
+    }
+
+    impl fmt::Display for OrderType {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.as_str())
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum TimeInForce {
+        #[default]
+        Gtc, // good till canceled
+        Ioc, // immediate or cancel
+        Fok, // fill or kill
+        Gtd, // good till date
+        Day, // expires at the session close
+    }
+
+    // What happens when an incoming order would match a resting order of the same trader; the
+    // incoming order's mode applies
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum SelfTradePrevention {
+        #[default]
+        None, // self-trades are allowed
+        CancelNewest, // cancel the rest of the incoming order
+        CancelOldest, // cancel the resting order and keep matching
+        CancelBoth,
+        DecrementAndCancel, // reduce both by the smaller volume, canceling the smaller order
+    }
+
+    // Distance of a trailing stop's stop price from the best reference price seen since it was
+    // placed: below it for sell stops, above it for buy stops
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum TrailingOffset {
+        Absolute(Decimal), // in quote currency
+        Percent(Decimal), // of the reference price
+    }
+
+    impl TrailingOffset {
+        pub fn amount(self, reference_price: Decimal) -> Decimal {
+            match self {
+                TrailingOffset::Absolute(offset) => offset,
+                TrailingOffset::Percent(percent) => reference_price * percent / Decimal::ONE_HUNDRED,
+            }
+        }
+    }
+
+    impl fmt::Display for TrailingOffset {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                TrailingOffset::Absolute(offset) => write!(f, "{}", offset.normalize()),
+                TrailingOffset::Percent(percent) => write!(f, "{}%", percent.normalize()),
+            }
+        }
+    }
+
+    // Post-only limit orders never take liquidity: when they would cross the book they are rejected
+    // or repriced one tick behind the best contra price
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum PostOnly {
+        #[default]
+        Off,
+        Reject,
+        Reprice,
+    }
+
+    // Status of an order as recorded in the order ledger (see Trade status in the README)
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum OrderStatus {
+        New,
+        Pending,
+        Open,
+        PartiallyFilled,
+        Filled,
+        Canceled,
+        Rejected,
+        Expired,
+        PendingReplace,
+        Replaced,
+        Triggered,
+        StpCanceled, // (rest of the) order canceled by self-trade prevention
+    }
+
+    impl fmt::Display for OrderStatus {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(match self {
+                OrderStatus::New => "new",
+                OrderStatus::Pending => "pending",
+                OrderStatus::Open => "open",
+                OrderStatus::PartiallyFilled => "partially_filled",
+                OrderStatus::Filled => "filled",
+                OrderStatus::Canceled => "canceled",
+                OrderStatus::Rejected => "rejected",
+                OrderStatus::Expired => "expired",
+                OrderStatus::PendingReplace => "pending_replace",
+                OrderStatus::Replaced => "replaced",
+                OrderStatus::Triggered => "triggered",
+                OrderStatus::StpCanceled => "stp_canceled",
+            })
+        }
     }
 
     // For Orderbook
     #[derive(Debug, Clone, PartialEq, Eq)]
     pub struct Order {
         pub id: Uuid,
-        pub price: OrderedFloat<f64>,
-        pub volume: OrderedFloat<f64>,
-        pub side: String,
+        pub trader: String, // owner, empty for Kraken liquidity
+        pub price: Decimal,
+        pub volume: Decimal, // remaining displayed volume (the current slice of iceberg orders)
+        pub original_volume: Decimal, // volume when the order was placed (or replaced)
+        pub display_volume: Decimal, // slice size of iceberg orders, zero when fully displayed
+        pub hidden_volume: Decimal, // iceberg reserve not shown in the book
+        pub side: Side,
         pub timestamp: String,
-        pub order_type: String,
+        pub order_type: OrderType,
+        pub expire_time: Option<DateTime<Utc>>, // GTD/DAY orders are removed from the book after this time
+        pub client_order_id: String, // empty for Kraken liquidity and orders placed without one
+    }
+
+    impl Order {
+        // Open volume including the hidden reserve of iceberg orders
+        pub fn total_volume(&self) -> Decimal {
+            self.volume + self.hidden_volume
+        }
+
+        // Show the next slice of an iceberg order from its reserve; false when nothing is left
+        pub fn replenish(&mut self) -> bool {
+            if self.hidden_volume <= Decimal::ZERO {
+                return false;
+            }
+            let slice: Decimal = self.display_volume.min(self.hidden_volume);
+            self.volume += slice;
+            self.hidden_volume -= slice;
+            true
+        }
     }
 
     // Custom deserialization for Order
@@ -43,8 +399,8 @@ pub mod models {
         {
             #[derive(Deserialize)]
             struct OrderData {
-                price: f64,
-                volume: f64,
+                price: String,
+                volume: String,
                 side: String,
                 timestamp: String,
                 order_type: String,
@@ -53,19 +409,32 @@ pub mod models {
             let helper = OrderData::deserialize(deserializer)?;
             //let id = Uuid::parse_str(&helper.id).map_err(de::Error::custom)?;
 
+            // Prices and volumes are read as text so they stay exact
+            let price: Decimal = Decimal::from_str(&helper.price).map_err(de::Error::custom)?;
+            let volume: Decimal = Decimal::from_str(&helper.volume).map_err(de::Error::custom)?;
+            let side: Side = Side::from_book_side(&helper.side).ok_or_else(||
+                de::Error::custom(format!("Unknown side: {}", helper.side))
+            )?;
+            let order_type: OrderType = helper.order_type.parse().map_err(de::Error::custom)?;
+
             Ok(Order {
                 id: Uuid::new_v4(),
-                price: OrderedFloat(helper.price),
-                volume: OrderedFloat(helper.volume),
-                side: helper.side,
+                trader: String::new(),
+                price: price.normalize(),
+                volume: volume.normalize(),
+                original_volume: volume.normalize(),
+                display_volume: Decimal::ZERO,
+                hidden_volume: Decimal::ZERO,
+                side,
                 timestamp: helper.timestamp,
-                order_type: helper.order_type,
+                order_type,
+                expire_time: None,
+                client_order_id: String::new(),
             })
         }
     }
 
-    // Custom serialization for Order (to avoid serialization of OrderedFloat for csv crate)
-    // TODO: find a better way to handle serialization of OrderedFloat
+    // Custom serialization for Order (decimals are written as text, without the ids, owner and expiry)
     impl Serialize for Order {
         fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where
@@ -73,11 +442,11 @@ pub mod models {
         {
             let mut state: <S as Serializer>::SerializeStruct =
                 serializer.serialize_struct("Order", 5)?;
-            state.serialize_field("price", &self.price.into_inner())?;
-            state.serialize_field("volume", &self.volume.into_inner())?;
-            state.serialize_field("side", &self.side)?;
+            state.serialize_field("price", &self.price.to_string())?;
+            state.serialize_field("volume", &self.volume.to_string())?;
+            state.serialize_field("side", self.side.book_side())?;
             state.serialize_field("timestamp", &self.timestamp)?;
-            state.serialize_field("order_type", &self.order_type)?;
+            state.serialize_field("order_type", self.order_type.as_str())?;
             //state.serialize_field("id", &self.id.to_string())?;
             state.end()
         }
@@ -90,11 +459,11 @@ pub mod models {
                 "Price: {:.5}, Volume: {:.3}, Side: {}, ID: {}, Timestamp: {}",
                 self.price,
                 self.volume,
-                self.side,
+                self.side.book_side(),
                 self.id,
                 self.timestamp
             );
-            if self.side == "ask" {
+            if self.side == Side::Sell {
                 write!(f, "{}", output.red())
             } else {
                 write!(f, "{}", output.green())
@@ -102,17 +471,278 @@ pub mod models {
         }
     }
 
-    //For Tradebook
+    // Order request accepted by the engine, parsed from the gRPC request with exact prices and volumes
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct NewOrder {
+        pub pair: String,
+        pub trader: String,
+        pub side: Side,
+        pub order_type: OrderType,
+        pub price: Decimal,
+        pub volume: Decimal,
+        pub stop_price: Decimal, // trigger price for stop orders (trailed for trailing stops)
+        pub trailing_offset: Option<TrailingOffset>, // trailing stops only
+        pub time_in_force: TimeInForce,
+        pub expire_time: Option<DateTime<Utc>>, // resolved expiry of GTD/DAY orders
+        pub client_order_id: String,
+        pub self_trade_prevention: SelfTradePrevention, // order's mode, or the trader's default
+        pub post_only: PostOnly,
+        pub reduce_only: bool, // may only decrease the trader's position in the pair
+        pub display_volume: Decimal, // iceberg slice size, zero when fully displayed
+        pub group_id: Option<Uuid>, // order group the order is a leg of
+    }
+
+    // Public execution published on the trade stream (one per fill)
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Fill {
+        pub id: Uuid,
+        pub pair: String,
+        pub price: Decimal,
+        pub volume: Decimal,
+        pub aggressor_side: Side, // side of the incoming (taker) order
+        pub maker_order_id: Uuid,
+        pub taker_order_id: Uuid,
+        pub timestamp: String,
+        pub maker_client_order_id: String,
+        pub taker_client_order_id: String,
+    }
+
+    // Result of processing an incoming order, returned to the trader when placing synchronously
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ExecutionReport {
+        pub order_id: Uuid,
+        pub status: OrderStatus,
+        pub filled_volume: Decimal,
+        pub average_price: Option<Decimal>, // volume weighted, None without fills
+        pub remaining_volume: Decimal,
+        pub stp_canceled_volume: Decimal, // canceled by self-trade prevention
+        pub reject_reason: String, // empty unless the order was rejected
+        pub group_id: Option<Uuid>,
+        pub fills: Vec<Fill>,
+    }
+
+    // Whether the trader's order rested in the book (maker) or was the incoming order (taker)
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Liquidity {
+        Maker,
+        Taker,
+    }
+
+    // Order ledger entry: one per order placed through the exchange, updated on every status change
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct OrderRecord {
+        pub id: Uuid,
+        pub trader: String,
+        pub pair: String,
+        pub side: Side,
+        pub order_type: OrderType,
+        pub price: Decimal, // limit price (changes on replace)
+        pub stop_price: Decimal, // current trigger price (moves with the reference for trailing stops)
+        pub trailing_offset: Option<TrailingOffset>,
+        pub volume: Decimal, // ordered volume (filled plus open volume after a replace)
+        pub filled_volume: Decimal,
+        pub open_volume: Decimal, // still working in the book or trigger book, 0 once the order is done
+        pub stp_canceled_volume: Decimal, // canceled by self-trade prevention
+        pub status: OrderStatus, // latest status
+        pub timestamp: String, // when the order was placed
+        pub expire_time: Option<DateTime<Utc>>,
+        pub client_order_id: String,
+        pub self_trade_prevention: SelfTradePrevention,
+        pub post_only: PostOnly,
+        pub reduce_only: bool,
+        pub display_volume: Decimal,
+        pub group_id: Option<Uuid>,
+        pub reject_reason: String, // empty unless the order was rejected
+        pub transitions: Vec<StatusChange>, // oldest first, starting with new
+    }
+
+    impl OrderRecord {
+        pub fn new(id: Uuid, order: &NewOrder, timestamp: String) -> Self {
+            let mut record: OrderRecord = OrderRecord {
+                id,
+                trader: order.trader.clone(),
+                pair: order.pair.clone(),
+                side: order.side,
+                order_type: order.order_type,
+                price: order.price,
+                stop_price: order.stop_price,
+                trailing_offset: order.trailing_offset,
+                volume: order.volume,
+                filled_volume: Decimal::ZERO,
+                open_volume: order.volume,
+                stp_canceled_volume: Decimal::ZERO,
+                status: OrderStatus::New,
+                timestamp: timestamp.clone(),
+                expire_time: order.expire_time,
+                client_order_id: order.client_order_id.clone(),
+                self_trade_prevention: order.self_trade_prevention,
+                post_only: order.post_only,
+                reduce_only: order.reduce_only,
+                display_volume: order.display_volume,
+                group_id: order.group_id,
+                reject_reason: String::new(),
+                transitions: Vec::new(),
+            };
+            record.transition(OrderStatus::New, timestamp);
+            record
+        }
+
+        // Change the status, keeping the price and open volume at the time in the transition
+        pub fn transition(&mut self, status: OrderStatus, timestamp: String) {
+            self.status = status;
+            self.transitions.push(StatusChange {
+                status,
+                price: self.price,
+                stop_price: self.stop_price,
+                open_volume: self.open_volume,
+                timestamp,
+            });
+        }
+
+        // Execution of part of the open volume
+        pub fn fill(&mut self, volume: Decimal, timestamp: String) {
+            self.filled_volume += volume;
+            self.open_volume -= volume;
+            let status: OrderStatus = if self.open_volume > Decimal::ZERO {
+                OrderStatus::PartiallyFilled
+            } else {
+                OrderStatus::Filled
+            };
+            self.transition(status, timestamp);
+        }
+
+        // Part of the open volume canceled by self-trade prevention; the status only changes once
+        // nothing is left open
+        pub fn stp_cancel(&mut self, volume: Decimal, timestamp: String) {
+            self.stp_canceled_volume += volume;
+            self.open_volume -= volume;
+            let status: OrderStatus = if self.open_volume > Decimal::ZERO {
+                self.status
+            } else {
+                OrderStatus::StpCanceled
+            };
+            self.transition(status, timestamp);
+        }
+
+        // New stop price of a trailing stop, recorded as a transition keeping the current status
+        pub fn trail(&mut self, stop_price: Decimal, timestamp: String) {
+            self.stop_price = stop_price;
+            self.transition(self.status, timestamp);
+        }
+
+        // New price of a pegged order that followed the book, recorded as a transition keeping the
+        // current status
+        pub fn reprice(&mut self, price: Decimal, timestamp: String) {
+            self.price = price;
+            self.transition(self.status, timestamp);
+        }
+
+        // Open volume cut down by the order's group (the sibling leg of an OCO pair was filled)
+        pub fn reduce(&mut self, open_volume: Decimal, timestamp: String) {
+            self.volume -= self.open_volume - open_volume;
+            self.open_volume = open_volume;
+            self.transition(self.status, timestamp);
+        }
+
+        pub fn reject(&mut self, reason: &str, timestamp: String) {
+            self.reject_reason = reason.to_string();
+            self.close(OrderStatus::Rejected, timestamp);
+        }
+
+        // Final status of an order leaving the book or trigger book without (further) executions
+        pub fn close(&mut self, status: OrderStatus, timestamp: String) {
+            self.open_volume = Decimal::ZERO;
+            self.transition(status, timestamp);
+        }
+    }
+
     #[derive(Debug, Clone, PartialEq, Eq)]
-    pub struct Trade {
+    pub struct StatusChange {
+        pub status: OrderStatus,
+        pub price: Decimal,
+        pub stop_price: Decimal, // trigger price at the time (trailing stops record each move)
+        pub open_volume: Decimal, // open volume after the change
+        pub timestamp: String,
+    }
+
+    // Linked orders: an OCO pair is a take-profit limit and a stop-loss leg sharing one volume, a
+    // bracket is an entry order whose OCO exit pair is placed once the entry is done
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum GroupType {
+        Oco,
+        Bracket,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum GroupStatus {
+        Waiting, // bracket entry still working, exits not placed yet
+        Active, // exit legs working
+        Completed, // legs done with executions
+        Canceled, // legs done without executions (or the entry was never filled)
+    }
+
+    impl fmt::Display for GroupStatus {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(match self {
+                GroupStatus::Waiting => "waiting",
+                GroupStatus::Active => "active",
+                GroupStatus::Completed => "completed",
+                GroupStatus::Canceled => "canceled",
+            })
+        }
+    }
+
+    // Exit leg of a group with the order placed for it (volume taken from the group when placed)
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct GroupLeg {
         pub id: Uuid,
+        pub order: NewOrder,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct OrderGroup {
+        pub id: Uuid,
+        pub group_type: GroupType,
+        pub status: GroupStatus,
         pub trader: String,
         pub pair: String,
-        pub price: OrderedFloat<f64>,
-        pub volume: OrderedFloat<f64>,
-        pub side: String,
+        pub volume: Decimal, // shared by the exit legs (the entry's filled volume for brackets)
+        pub entry: Option<Uuid>, // bracket entry order
+        pub take_profit: GroupLeg, // limit leg
+        pub stop_loss: GroupLeg, // stop, stop_limit or trailing_stop leg
+        pub timestamp: String,
+    }
+
+    impl OrderGroup {
+        pub fn is_working(&self) -> bool {
+            matches!(self.status, GroupStatus::Waiting | GroupStatus::Active)
+        }
+
+        pub fn exits(&self) -> [&GroupLeg; 2] {
+            [&self.take_profit, &self.stop_loss]
+        }
+    }
+
+    // Counterparty recorded for fills against Kraken liquidity (resting orders without an owner)
+    pub const EXTERNAL_TRADER: &str = "external";
+
+    // Fills ledger entry: one execution of one of the trader's orders
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Execution {
+        pub id: Uuid, // trade id, as on the public trade stream
+        pub pair: String,
+        pub order_id: Uuid, // the trader's order
+        pub client_order_id: String, // of the trader's order
+        pub side: Side, // the trader's side
+        pub price: Decimal,
+        pub volume: Decimal,
+        pub fee: Decimal, // in the quote currency
+        pub liquidity: Liquidity,
+        pub aggressor_side: Side,
+        pub taker_trader: String,
+        pub taker_order_id: Uuid,
+        pub maker_trader: String, // EXTERNAL_TRADER for Kraken liquidity
+        pub maker_order_id: Uuid,
         pub timestamp: String,
-        pub order_type: String,
-        pub status: String,
     }
 }
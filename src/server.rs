@@ -1,28 +1,58 @@
 use std::collections::HashMap;
 use std::env;
 use std::fs::File;
-use std::sync::Arc;
+use std::sync::{ Arc, RwLock };
 use std::error::Error;
-use models::model::models::Config;
-use tokio::sync::{ Mutex, mpsc };
+use std::pin::Pin;
+use std::str::FromStr;
+use models::model::models::{ Config, Instrument, StopTrigger };
+use tokio::sync::{ Mutex, broadcast, mpsc, oneshot };
 use tokio::time::{ sleep, Duration };
 use tonic::{ transport::Server, Request, Response, Status };
 use futures::future::join_all;
+use futures::{ Stream, StreamExt, stream };
 use csv::{ ReaderBuilder, Writer };
-use chrono::Utc;
+use chrono::{ DateTime, NaiveTime, Utc };
 use serde_json::Value;
-use ordered_float::OrderedFloat;
+use rust_decimal::Decimal;
 use uuid::Uuid;
 use crate::utils::config::load_config;
 
+use orderbook::admin_server::{ Admin, AdminServer };
 use orderbook::order_book_server::{ OrderBook, OrderBookServer };
 use orderbook::{
+    AddPairRequest,
+    AdminResponse,
+    AuctionUpdate,
+    CancelAllRequest,
+    CancelOrderRequest,
+    ClientOrderRequest,
+    DepthRequest,
+    DepthResponse,
+    EngineStatus,
+    EngineStatusRequest,
+    GetOrderRequest,
+    HaltRequest,
+    InstrumentRules,
+    OpenOrdersRequest,
+    OpenOrdersResponse,
     OrderBookRequest,
     OrderBookResponse,
+    OrderBookUpdate,
+    OrderDetails,
+    OrderGroupRequest,
+    OrderGroupResponse,
     OrderRequest,
     OrderResponse,
+    PairRequest,
+    PairStatus,
+    PriceLevel,
+    PublicTrade,
+    ReplaceOrderRequest,
     TradeBookRequest,
     TradeBookResponse,
+    TradeStreamRequest,
+    TradingStatusUpdate,
 };
 
 pub mod utils;
@@ -33,34 +63,298 @@ pub mod orderbook {
 
 use log::info;
 
-use crate::models::model::models::{ Order, Trade };
+use crate::models::model::models::{
+    EXTERNAL_TRADER,
+    Execution,
+    ExecutionReport,
+    Fill,
+    GroupLeg,
+    GroupStatus,
+    GroupType,
+    HaltMode,
+    Liquidity,
+    NewOrder,
+    Order,
+    OrderGroup,
+    OrderRecord,
+    OrderStatus,
+    OrderType,
+    PostOnly,
+    SelfTradePrevention,
+    Side,
+    TimeInForce,
+    TrailingOffset,
+};
+use crate::models::book::{ Book, Equilibrium, Level };
+use crate::models::ledger::TradeBook;
+
+// Order accepted by the gRPC handler and queued for matching with the id returned to the trader
+#[derive(Debug)]
+pub struct PendingOrder {
+    id: Uuid,
+    request: NewOrder,
+}
+
+// Order sent to the matching engine, the execution report goes back on reply unless the order was
+// placed fire-and-forget
+#[derive(Debug)]
+pub struct QueuedOrder {
+    order: PendingOrder,
+    group: Option<OrderGroup>, // set when the order is the first leg of an order group
+    reply: Option<oneshot::Sender<ExecutionReport>>,
+}
+
+// Order placed with a client_order_id, kept per trader for the deduplication window
+#[derive(Debug, Clone)]
+struct ClientOrder {
+    order_id: Uuid,
+    placed_at: DateTime<Utc>,
+    response: Option<OrderResponse>, // None while the order is being matched
+}
 
 #[derive(Debug)]
 pub struct OrderBookService {
-    order_books: Arc<Mutex<HashMap<String, Vec<Order>>>>,
-    order_tx: mpsc::Sender<OrderRequest>,
-    trade_books: Arc<Mutex<HashMap<String, Vec<Trade>>>>,
+    order_books: Arc<Mutex<HashMap<String, Book>>>,
+    order_tx: mpsc::Sender<QueuedOrder>,
+    trade_books: Arc<Mutex<HashMap<String, TradeBook>>>,
+    stop_books: Arc<Mutex<HashMap<String, Vec<PendingOrder>>>>, // stop orders waiting for their trigger per pair
+    stop_trigger: StopTrigger,
+    session_close: NaiveTime, // DAY orders expire at this UTC time
+    instruments: RwLock<HashMap<String, Instrument>>, // tradable pairs with their trading rules, changed by the admin service
+    client_orders: Arc<Mutex<HashMap<(String, String), ClientOrder>>>, // by trader and client_order_id
+    client_order_window: chrono::Duration, // how long a client_order_id can't be reused
+    auction_duration: chrono::Duration, // how long a pair collects orders when it re-opens after a pause
+    self_trade_prevention: HashMap<String, SelfTradePrevention>, // default mode per trader
+    book_tx: broadcast::Sender<OrderBookUpdate>, // level updates for SubscribeOrderBook streams
+    trade_tx: broadcast::Sender<PublicTrade>, // executions for SubscribeTrades streams
+    auction_tx: broadcast::Sender<AuctionUpdate>, // call auction states for SubscribeAuction streams
+    status_tx: broadcast::Sender<TradingStatusUpdate>, // halts and resumes for SubscribeTradingStatus streams
+    removed_tx: broadcast::Sender<String>, // pairs removed by the admin service, ends their streams
+}
+
+impl OrderBookService {
+    pub fn new(order_books: HashMap<String, Book>, order_tx: mpsc::Sender<QueuedOrder>) -> Self {
+        let (book_tx, _) = broadcast::channel(1024);
+        let (trade_tx, _) = broadcast::channel(1024);
+        let (auction_tx, _) = broadcast::channel(1024);
+        let (status_tx, _) = broadcast::channel(1024);
+        let (removed_tx, _) = broadcast::channel(16);
+        let instruments: HashMap<String, Instrument> = order_books
+            .keys()
+            .map(|pair| (pair.clone(), Instrument::default()))
+            .collect();
+        OrderBookService {
+            order_books: Arc::new(Mutex::new(order_books)),
+            order_tx,
+            trade_books: Arc::new(Mutex::new(HashMap::new())), //TODO: load from CSV (recovery/optional)
+            stop_books: Arc::new(Mutex::new(HashMap::new())),
+            stop_trigger: StopTrigger::default(),
+            session_close: NaiveTime::MIN,
+            instruments: RwLock::new(instruments),
+            client_orders: Arc::new(Mutex::new(HashMap::new())),
+            client_order_window: chrono::Duration::days(1),
+            auction_duration: chrono::Duration::seconds(60),
+            self_trade_prevention: HashMap::new(),
+            book_tx,
+            trade_tx,
+            auction_tx,
+            status_tx,
+            removed_tx,
+        }
+    }
+
+    fn instrument(&self, pair: &str) -> Option<Instrument> {
+        self.instruments.read().unwrap().get(pair).copied()
+    }
+
+    // Tradable pairs, sorted
+    fn pairs(&self) -> Vec<String> {
+        let mut pairs: Vec<String> = self.instruments.read().unwrap().keys().cloned().collect();
+        pairs.sort();
+        pairs
+    }
+
+    // Parse the order and check it against the trading rules of its pair
+    fn accept_order(&self, market_order: &OrderRequest) -> Result<NewOrder, String> {
+        let instrument: Instrument = self
+            .instrument(&market_order.pair)
+            .ok_or_else(|| format!("Unknown pair: {}", market_order.pair))?;
+        let mut new_order: NewOrder = parse_order(market_order, instrument, self.session_close, Utc::now())?;
+        check_trading_rules(&new_order, &instrument)?;
+        if market_order.self_trade_prevention == i32::from(orderbook::SelfTradePrevention::Unspecified) {
+            new_order.self_trade_prevention = self.self_trade_prevention
+                .get(&new_order.trader)
+                .copied()
+                .unwrap_or_default();
+        }
+        Ok(new_order)
+    }
+
+    // Parse and check the legs of an order group; returns the first leg to match (the entry of a
+    // bracket, the take-profit of an OCO pair) and the group linking it to the other legs
+    fn accept_order_group(&self, request: &OrderGroupRequest) -> Result<(PendingOrder, OrderGroup), String> {
+        let group_type: GroupType = group_type_from_proto(request.group_type)?;
+        let entry_volume: String = request.entry
+            .as_ref()
+            .map(|entry| entry.volume.clone())
+            .unwrap_or_default();
+        let accept_leg = |name: &str, leg: &Option<OrderRequest>| -> Result<NewOrder, String> {
+            let mut leg: OrderRequest = leg.clone().ok_or_else(|| format!("{} order is missing", name))?;
+            if !leg.client_order_id.is_empty() {
+                return Err(format!("{}: client_order_id is not supported on order group legs", name));
+            }
+            // Bracket exits take the entry volume unless given
+            if leg.volume.trim().is_empty() {
+                leg.volume = entry_volume.clone();
+            }
+            self.accept_order(&leg).map_err(|reason| format!("{}: {}", name, reason))
+        };
+
+        let entry: Option<NewOrder> = match group_type {
+            GroupType::Bracket => Some(accept_leg("entry", &request.entry)?),
+            GroupType::Oco if request.entry.is_some() => {
+                return Err("oco groups have no entry order".to_string());
+            }
+            GroupType::Oco => None,
+        };
+        let mut take_profit: NewOrder = accept_leg("take_profit", &request.take_profit)?;
+        let mut stop_loss: NewOrder = accept_leg("stop_loss", &request.stop_loss)?;
+        if take_profit.order_type != OrderType::Limit {
+            return Err("take_profit must be a limit order".to_string());
+        }
+        if !stop_loss.order_type.is_stop() {
+            return Err("stop_loss must be a stop, stop_limit or trailing_stop order".to_string());
+        }
+        if [&stop_loss].into_iter().chain(&entry).any(|leg| leg.trader != take_profit.trader || leg.pair != take_profit.pair) {
+            return Err("order group legs must have the same trader and pair".to_string());
+        }
+        let (side, volume): (Side, Decimal) = match &entry {
+            Some(entry) => (entry.side.opposite(), entry.volume),
+            None => (take_profit.side, take_profit.volume),
+        };
+        if take_profit.side != side || stop_loss.side != side {
+            return Err(match group_type {
+                GroupType::Bracket => "exit legs must be on the opposite side of the entry".to_string(),
+                GroupType::Oco => "oco legs must be on the same side".to_string(),
+            });
+        }
+        if take_profit.volume != volume || stop_loss.volume != volume {
+            return Err("order group legs must have the same volume".to_string());
+        }
+
+        let group_id: Uuid = Uuid::new_v4();
+        take_profit.group_id = Some(group_id);
+        stop_loss.group_id = Some(group_id);
+        let take_profit: GroupLeg = GroupLeg { id: Uuid::new_v4(), order: take_profit };
+        let stop_loss: GroupLeg = GroupLeg { id: Uuid::new_v4(), order: stop_loss };
+        let first: PendingOrder = match entry {
+            Some(entry) => PendingOrder { id: Uuid::new_v4(), request: NewOrder { group_id: Some(group_id), ..entry } },
+            None => PendingOrder { id: take_profit.id, request: take_profit.order.clone() },
+        };
+        let group: OrderGroup = OrderGroup {
+            id: group_id,
+            group_type,
+            status: if group_type == GroupType::Bracket { GroupStatus::Waiting } else { GroupStatus::Active },
+            trader: first.request.trader.clone(),
+            pair: first.request.pair.clone(),
+            volume,
+            entry: (group_type == GroupType::Bracket).then_some(first.id),
+            take_profit,
+            stop_loss,
+            timestamp: Utc::now().to_rfc3339(),
+        };
+        Ok((first, group))
+    }
+
+    // Reserve the trader's client_order_id for a new order; when it was already used within the
+    // deduplication window the original order's response is returned instead
+    async fn reserve_client_order_id(
+        &self,
+        trader: &str,
+        client_order_id: &str,
+        order_id: Uuid
+    ) -> Option<OrderResponse> {
+        if client_order_id.is_empty() {
+            return None;
+        }
+        let now: DateTime<Utc> = Utc::now();
+        let mut client_orders: tokio::sync::MutexGuard<
+            HashMap<(String, String), ClientOrder>
+        > = self.client_orders.lock().await;
+        client_orders.retain(|_, placed| now - placed.placed_at < self.client_order_window);
+
+        let key: (String, String) = (trader.to_string(), client_order_id.to_string());
+        if let Some(placed) = client_orders.get(&key) {
+            info!("Duplicate client order id {} for {}: {}", client_order_id, trader, placed.order_id);
+            // The original order is still being matched, acknowledge it again
+            return Some(
+                placed.response
+                    .clone()
+                    .unwrap_or_else(|| queued_response(placed.order_id, client_order_id))
+            );
+        }
+        client_orders.insert(key, ClientOrder { order_id, placed_at: now, response: None });
+        None
+    }
+
+    // Drop the reservation of an order that never reached matching, so a retry places it again
+    async fn release_client_order_id(&self, trader: &str, client_order_id: &str) {
+        if client_order_id.is_empty() {
+            return;
+        }
+        let mut client_orders: tokio::sync::MutexGuard<
+            HashMap<(String, String), ClientOrder>
+        > = self.client_orders.lock().await;
+        client_orders.remove(&(trader.to_string(), client_order_id.to_string()));
+    }
+
+    // Keep the response of an order placed with a client_order_id for retried submissions
+    async fn complete_client_order(&self, trader: &str, response: &OrderResponse) {
+        if response.client_order_id.is_empty() {
+            return;
+        }
+        let mut client_orders: tokio::sync::MutexGuard<
+            HashMap<(String, String), ClientOrder>
+        > = self.client_orders.lock().await;
+        let key: (String, String) = (trader.to_string(), response.client_order_id.clone());
+        if let Some(placed) = client_orders.get_mut(&key) {
+            placed.response = Some(response.clone());
+        }
+    }
 }
 
 // Implement the OrderBook trait for OrderBookService to handle gRPC requests (core)
 #[tonic::async_trait]
 impl OrderBook for Arc<OrderBookService> {
+    type SubscribeOrderBookStream = Pin<
+        Box<dyn Stream<Item = Result<OrderBookUpdate, Status>> + Send + 'static>
+    >;
+    type SubscribeTradesStream = Pin<
+        Box<dyn Stream<Item = Result<PublicTrade, Status>> + Send + 'static>
+    >;
+    type SubscribeAuctionStream = Pin<
+        Box<dyn Stream<Item = Result<AuctionUpdate, Status>> + Send + 'static>
+    >;
+    type SubscribeTradingStatusStream = Pin<
+        Box<dyn Stream<Item = Result<TradingStatusUpdate, Status>> + Send + 'static>
+    >;
+
     async fn get_order_book(
         &self,
         request: Request<OrderBookRequest>
     ) -> Result<Response<OrderBookResponse>, Status> {
         let pair: String = request.into_inner().pair;
         let order_books: tokio::sync::MutexGuard<
-            HashMap<String, Vec<Order>>
+            HashMap<String, Book>
         > = self.order_books.lock().await;
-        if let Some(orders) = order_books.get(&pair) {
+        if let Some(book) = order_books.get(&pair) {
             Ok(
                 Response::new(OrderBookResponse {
-                    orders: orders
+                    orders: book
                         .iter()
                         .map(|o: &Order| orderbook::Order {
-                            price: o.price.into_inner(),
-                            volume: o.volume.into_inner(),
+                            price: o.price.normalize().to_string(),
+                            volume: o.volume.normalize().to_string(),
                         })
                         .collect(),
                 })
@@ -75,15 +369,84 @@ impl OrderBook for Arc<OrderBookService> {
         request: Request<OrderRequest>
     ) -> Result<Response<OrderResponse>, Status> {
         let market_order: OrderRequest = request.into_inner();
-        if let Err(_) = self.order_tx.send(market_order).await {
+        let order_id: Uuid = Uuid::new_v4();
+        let new_order: NewOrder = match self.accept_order(&market_order) {
+            Ok(new_order) => new_order,
+            Err(reason) => {
+                // Rejected orders never reach matching but stay visible in the trader's trade book
+                // (unless side or order type are unknown and there is nothing meaningful to record)
+                info!("Rejected order for {}: {}", market_order.trader, reason);
+                if
+                    let (Ok(side), Ok(order_type)) = (
+                        side_from_proto(market_order.side),
+                        order_type_from_proto(market_order.order_type),
+                    )
+                {
+                    let rejected: NewOrder = NewOrder {
+                        pair: market_order.pair.clone(),
+                        trader: market_order.trader.clone(),
+                        side,
+                        order_type,
+                        price: Decimal::from_str(&market_order.price).unwrap_or_default(),
+                        volume: Decimal::from_str(&market_order.volume).unwrap_or_default(),
+                        ..Default::default()
+                    };
+                    let mut record: OrderRecord = OrderRecord::new(
+                        order_id,
+                        &rejected,
+                        Utc::now().to_rfc3339()
+                    );
+                    record.reject(&reason, Utc::now().to_rfc3339());
+                    let mut trade_books: tokio::sync::MutexGuard<
+                        HashMap<String, TradeBook>
+                    > = self.trade_books.lock().await;
+                    trade_books.entry(rejected.trader.clone()).or_default().add_order(record);
+                }
+                return Err(Status::invalid_argument(reason));
+            }
+        };
+
+        // Retried submissions get the response of the original order instead of placing it again
+        let trader: String = new_order.trader.clone();
+        let client_order_id: String = new_order.client_order_id.clone();
+        if
+            let Some(response) = self.reserve_client_order_id(
+                &trader,
+                &client_order_id,
+                order_id
+            ).await
+        {
+            return Ok(Response::new(response));
+        }
+
+        let (reply, report_rx) = if market_order.fire_and_forget {
+            (None, None)
+        } else {
+            let (reply, report_rx) = oneshot::channel();
+            (Some(reply), Some(report_rx))
+        };
+        let queued: QueuedOrder = QueuedOrder {
+            order: PendingOrder { id: order_id, request: new_order },
+            group: None,
+            reply,
+        };
+        if self.order_tx.send(queued).await.is_err() {
+            self.release_client_order_id(&trader, &client_order_id).await;
             return Err(Status::internal("Failed to process order"));
         }
-        Ok(
-            Response::new(OrderResponse {
-                status: "new".into(),
-                message: "order registerted and is being processed".into(),
-            })
-        )
+
+        // Fire-and-forget orders are acknowledged once queued, others after matching
+        let response: OrderResponse = if let Some(report_rx) = report_rx {
+            let Ok(report) = report_rx.await else {
+                self.release_client_order_id(&trader, &client_order_id).await;
+                return Err(Status::internal("Failed to process order"));
+            };
+            report_response(&report, client_order_id)
+        } else {
+            queued_response(order_id, &client_order_id)
+        };
+        self.complete_client_order(&trader, &response).await;
+        Ok(Response::new(response))
     }
 
     async fn get_trade_book(
@@ -92,487 +455,2951 @@ impl OrderBook for Arc<OrderBookService> {
     ) -> Result<Response<TradeBookResponse>, Status> {
         let trader: String = request.into_inner().trader;
         let trade_books: tokio::sync::MutexGuard<
-            HashMap<String, Vec<Trade>>
+            HashMap<String, TradeBook>
         > = self.trade_books.lock().await;
-        if let Some(trades) = trade_books.get(&trader) {
+        if let Some(trade_book) = trade_books.get(&trader) {
             Ok(
                 Response::new(TradeBookResponse {
-                    trades: trades
-                        .iter()
-                        .map(|t: &Trade| orderbook::Trade {
-                            id: t.id.to_string(),
-                            trader: t.trader.clone(),
-                            order_type: t.order_type.clone(),
-                            pair: t.pair.clone(),
-                            side: t.side.clone(),
-                            price: t.price.into_inner(),
-                            volume: t.volume.into_inner(),
-                            timestamp: t.timestamp.clone(),
-                            status: t.status.clone(),
-                        })
-                        .collect(),
+                    orders: trade_book.orders().iter().map(order_details).collect(),
+                    fills: trade_book.fills().iter().map(execution).collect(),
+                    groups: trade_book.groups().iter().map(group_details).collect(),
                 })
             )
         } else {
             Err(Status::not_found("Trade book not found"))
         }
     }
+
+    async fn cancel_order(
+        &self,
+        request: Request<CancelOrderRequest>
+    ) -> Result<Response<OrderResponse>, Status> {
+        let cancel: CancelOrderRequest = request.into_inner();
+        let order_id: Uuid = Uuid::parse_str(&cancel.order_id).map_err(|_|
+            Status::invalid_argument("Invalid order id")
+        )?;
+
+        // Lock in the same order as process_orders
+        let mut order_books: tokio::sync::MutexGuard<
+            HashMap<String, Book>
+        > = self.order_books.lock().await;
+        let mut trade_books: tokio::sync::MutexGuard<
+            HashMap<String, TradeBook>
+        > = self.trade_books.lock().await;
+
+        // Only the trader who placed the order can cancel it
+        let placed: OrderRecord = placed_order(&trade_books, &cancel.trader, order_id)
+            .cloned()
+            .ok_or_else(|| Status::not_found("Order not found"))?;
+
+        let mut stop_books: tokio::sync::MutexGuard<
+            HashMap<String, Vec<PendingOrder>>
+        > = self.stop_books.lock().await;
+
+        // Resting limit order or a stop order still waiting for its trigger
+        let volume: Decimal = if
+            let Some(canceled) = order_books
+                .get_mut(&placed.pair)
+                .and_then(|book| book.remove(&order_id))
+        {
+            info!("Canceled order for {}: {}", cancel.trader, canceled);
+            if let Some(book) = order_books.get_mut(&placed.pair) {
+                publish_market_data(self, &placed.pair, book);
+            }
+            canceled.total_volume() // including the hidden reserve of an iceberg
+        } else if
+            let Some(stop_orders) = stop_books
+                .get_mut(&placed.pair)
+                .filter(|stop_orders| stop_orders.iter().any(|stop| stop.id == order_id))
+        {
+            stop_orders.retain(|stop| stop.id != order_id);
+            info!("Canceled stop order for {}: {}", cancel.trader, order_id);
+            placed.open_volume
+        } else {
+            return Err(Status::failed_precondition("Order is not open"));
+        };
+
+        if let Some(record) = order_record(&mut trade_books, &cancel.trader, order_id) {
+            record.close(OrderStatus::Canceled, Utc::now().to_rfc3339());
+        }
+
+        // Canceling a group leg cancels the rest of the pair, a canceled bracket entry places its
+        // exits for what was filled and pegged orders follow a canceled best price
+        if let Some(book) = order_books.get_mut(&placed.pair) {
+            let instrument: Instrument = self.instrument(&placed.pair).unwrap_or_default();
+            let stop_orders: &mut Vec<PendingOrder> = stop_books.entry(placed.pair.clone()).or_default();
+            trigger_stop_orders(book, &placed.pair, stop_orders, &mut trade_books, self.stop_trigger, &instrument);
+            check_volatility(self, &placed.pair, book, &instrument);
+            publish_market_data(self, &placed.pair, book);
+        }
+
+        Ok(
+            Response::new(OrderResponse {
+                status: status_to_proto(OrderStatus::Canceled),
+                message: format!("order canceled, remaining volume: {}", volume),
+                order_id: order_id.to_string(),
+                report: None,
+                client_order_id: placed.client_order_id,
+            })
+        )
+    }
+
+    async fn replace_order(
+        &self,
+        request: Request<ReplaceOrderRequest>
+    ) -> Result<Response<OrderResponse>, Status> {
+        let replace: ReplaceOrderRequest = request.into_inner();
+        let order_id: Uuid = Uuid::parse_str(&replace.order_id).map_err(|_|
+            Status::invalid_argument("Invalid order id")
+        )?;
+
+        // Lock in the same order as process_orders
+        let mut order_books: tokio::sync::MutexGuard<
+            HashMap<String, Book>
+        > = self.order_books.lock().await;
+        let mut trade_books: tokio::sync::MutexGuard<
+            HashMap<String, TradeBook>
+        > = self.trade_books.lock().await;
+
+        let placed: OrderRecord = placed_order(&trade_books, &replace.trader, order_id)
+            .cloned()
+            .ok_or_else(|| Status::not_found("Order not found"))?;
+        let instrument: Instrument = self.instrument(&placed.pair).unwrap_or_default();
+        let new_price: Decimal = parse_decimal(
+            "price",
+            &replace.price,
+            instrument.price_scale
+        ).map_err(Status::invalid_argument)?;
+        let new_volume: Decimal = parse_decimal(
+            "volume",
+            &replace.volume,
+            instrument.volume_scale
+        ).map_err(Status::invalid_argument)?;
+        let mut stop_books: tokio::sync::MutexGuard<
+            HashMap<String, Vec<PendingOrder>>
+        > = self.stop_books.lock().await;
+        let book: &mut Book = order_books
+            .get_mut(&placed.pair)
+            .ok_or_else(|| Status::failed_precondition("Order is not open"))?;
+        let resting: Order = book
+            .get(&order_id)
+            .cloned()
+            .ok_or_else(|| Status::failed_precondition("Order is not open"))?;
+        if let Some(resume_at) = book.halted() {
+            return Err(Status::failed_precondition(format!("Trading is halted until {}", resume_at.to_rfc3339())));
+        }
+
+        // The price of a pegged order follows the book, only its volume can be replaced
+        let pegged: bool = resting.order_type.is_pegged();
+        if pegged && new_price > Decimal::ZERO {
+            return Err(Status::invalid_argument("The price of a pegged order can't be replaced"));
+        }
+
+        let price: Decimal = if new_price > Decimal::ZERO { new_price } else { resting.price };
+        let volume: Decimal = if new_volume > Decimal::ZERO { new_volume } else { resting.total_volume() };
+        let mut replaced: NewOrder = NewOrder {
+            pair: placed.pair.clone(),
+            trader: replace.trader.clone(),
+            side: placed.side,
+            order_type: if pegged { resting.order_type } else { OrderType::Limit },
+            price,
+            volume,
+            expire_time: resting.expire_time,
+            client_order_id: placed.client_order_id.clone(),
+            self_trade_prevention: placed.self_trade_prevention,
+            post_only: placed.post_only,
+            reduce_only: placed.reduce_only,
+            display_volume: resting.display_volume,
+            ..Default::default()
+        };
+        check_trading_rules(&replaced, &instrument).map_err(Status::invalid_argument)?;
+
+        // A replace that breaks the order's post-only or reduce-only flag leaves the order unchanged
+        let position: Decimal = trade_books
+            .get(&replace.trader)
+            .map(|trade_book| trade_book.position(&placed.pair))
+            .unwrap_or_default();
+        apply_order_flags(book, position, &mut replaced, &instrument).map_err(Status::failed_precondition)?;
+        check_price_protection(book, &replaced, &instrument).map_err(Status::failed_precondition)?;
+        let price: Decimal = replaced.price;
+
+        if let Some(record) = order_record(&mut trade_books, &replace.trader, order_id) {
+            record.price = price;
+            record.volume = record.filled_volume + volume;
+            record.open_volume = volume;
+            record.transition(OrderStatus::PendingReplace, Utc::now().to_rfc3339());
+        }
+
+        let remaining_volume: Decimal = if
+            price == resting.price &&
+            volume <= resting.total_volume()
+        {
+            // Reducing volume at the same price keeps the order's place in the queue (an iceberg
+            // loses hidden reserve before displayed volume)
+            if let Some(order) = book.get_mut(&order_id) {
+                order.volume = order.volume.min(volume);
+                order.hidden_volume = volume - order.volume;
+            }
+            volume
+        } else {
+            // A new price or a larger volume goes to the back of the queue (and may match)
+            book.remove(&order_id);
+            execute_order(book, &mut trade_books, &replaced, order_id, &instrument)
+        };
+
+        let status: OrderStatus = if remaining_volume > Decimal::ZERO {
+            OrderStatus::Replaced
+        } else {
+            OrderStatus::Filled
+        };
+        info!("Replaced order for {}: {} -> price: {}, volume: {}", replace.trader, resting, price, volume);
+        // A replacement filled completely got its filled status from matching
+        if status == OrderStatus::Replaced {
+            if let Some(record) = order_record(&mut trade_books, &replace.trader, order_id) {
+                record.transition(OrderStatus::Replaced, Utc::now().to_rfc3339());
+            }
+        }
+        let stop_orders: &mut Vec<PendingOrder> = stop_books.entry(placed.pair.clone()).or_default();
+        trigger_stop_orders(book, &placed.pair, stop_orders, &mut trade_books, self.stop_trigger, &instrument);
+        check_volatility(self, &placed.pair, book, &instrument);
+        publish_market_data(self, &placed.pair, book);
+
+        Ok(
+            Response::new(OrderResponse {
+                status: status_to_proto(status),
+                message: format!("order replaced, remaining volume: {}", remaining_volume),
+                order_id: order_id.to_string(),
+                report: None,
+                client_order_id: placed.client_order_id,
+            })
+        )
+    }
+
+    async fn subscribe_order_book(
+        &self,
+        request: Request<OrderBookRequest>
+    ) -> Result<Response<Self::SubscribeOrderBookStream>, Status> {
+        let pair: String = request.into_inner().pair;
+
+        // Subscribe while holding the lock so no update is missed between snapshot and stream
+        let (snapshot, updates, removed): (OrderBookUpdate, broadcast::Receiver<OrderBookUpdate>, broadcast::Receiver<String>) = {
+            let order_books: tokio::sync::MutexGuard<
+                HashMap<String, Book>
+            > = self.order_books.lock().await;
+            let book: &Book = order_books
+                .get(&pair)
+                .ok_or_else(|| Status::not_found("Order book not found"))?;
+            let snapshot: OrderBookUpdate = OrderBookUpdate {
+                pair: pair.clone(),
+                sequence: book.sequence(),
+                snapshot: true,
+                bids: book.depth(Side::Buy).iter().map(price_level).collect(),
+                asks: book.depth(Side::Sell).iter().map(price_level).collect(),
+            };
+            (snapshot, self.book_tx.subscribe(), self.removed_tx.subscribe())
+        };
+
+        let updates = pair_stream(updates, removed, pair, |update: &OrderBookUpdate| update.pair.clone());
+        let stream = stream::once(async move { Ok(snapshot) }).chain(updates);
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn subscribe_trades(
+        &self,
+        request: Request<TradeStreamRequest>
+    ) -> Result<Response<Self::SubscribeTradesStream>, Status> {
+        let pair: String = request.into_inner().pair;
+
+        // Subscribe while holding the lock so a removal of the pair can't be missed
+        let (trades, removed): (broadcast::Receiver<PublicTrade>, broadcast::Receiver<String>) = {
+            let order_books: tokio::sync::MutexGuard<
+                HashMap<String, Book>
+            > = self.order_books.lock().await;
+            if !order_books.contains_key(&pair) {
+                return Err(Status::not_found("Order book not found"));
+            }
+            (self.trade_tx.subscribe(), self.removed_tx.subscribe())
+        };
+        let trades = pair_stream(trades, removed, pair, |trade: &PublicTrade| {
+            trade.pair.clone()
+        });
+        Ok(Response::new(Box::pin(trades)))
+    }
+
+    async fn subscribe_auction(
+        &self,
+        request: Request<OrderBookRequest>
+    ) -> Result<Response<Self::SubscribeAuctionStream>, Status> {
+        let pair: String = request.into_inner().pair;
+
+        // Subscribe while holding the lock so no update is missed after the current state
+        let (current, updates, removed): (AuctionUpdate, broadcast::Receiver<AuctionUpdate>, broadcast::Receiver<String>) = {
+            let order_books: tokio::sync::MutexGuard<
+                HashMap<String, Book>
+            > = self.order_books.lock().await;
+            let book: &Book = order_books
+                .get(&pair)
+                .ok_or_else(|| Status::not_found("Order book not found"))?;
+            (auction_update(&pair, book), self.auction_tx.subscribe(), self.removed_tx.subscribe())
+        };
+
+        let updates = pair_stream(updates, removed, pair, |update: &AuctionUpdate| update.pair.clone());
+        let stream = stream::once(async move { Ok(current) }).chain(updates);
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn subscribe_trading_status(
+        &self,
+        request: Request<OrderBookRequest>
+    ) -> Result<Response<Self::SubscribeTradingStatusStream>, Status> {
+        let pair: String = request.into_inner().pair;
+
+        // Subscribe while holding the lock so no change is missed after the current phase
+        let (current, updates, removed): (TradingStatusUpdate, broadcast::Receiver<TradingStatusUpdate>, broadcast::Receiver<String>) = {
+            let order_books: tokio::sync::MutexGuard<
+                HashMap<String, Book>
+            > = self.order_books.lock().await;
+            let book: &Book = order_books
+                .get(&pair)
+                .ok_or_else(|| Status::not_found("Order book not found"))?;
+            (trading_status(&pair, book, ""), self.status_tx.subscribe(), self.removed_tx.subscribe())
+        };
+
+        let updates = pair_stream(updates, removed, pair, |update: &TradingStatusUpdate| update.pair.clone());
+        let stream = stream::once(async move { Ok(current) }).chain(updates);
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn get_depth(
+        &self,
+        request: Request<DepthRequest>
+    ) -> Result<Response<DepthResponse>, Status> {
+        let depth_request: DepthRequest = request.into_inner();
+        let group: Decimal = parse_decimal(
+            "group",
+            &depth_request.group,
+            self.instrument(&depth_request.pair).unwrap_or_default().price_scale
+        ).map_err(Status::invalid_argument)?;
+        let max_levels: usize = depth_request.depth as usize;
+
+        let order_books: tokio::sync::MutexGuard<
+            HashMap<String, Book>
+        > = self.order_books.lock().await;
+        let book: &Book = order_books
+            .get(&depth_request.pair)
+            .ok_or_else(|| Status::not_found("Order book not found"))?;
+
+        Ok(
+            Response::new(DepthResponse {
+                pair: depth_request.pair.clone(),
+                sequence: book.sequence(),
+                bids: book
+                    .grouped_depth(Side::Buy, max_levels, group)
+                    .iter()
+                    .map(price_level)
+                    .collect(),
+                asks: book
+                    .grouped_depth(Side::Sell, max_levels, group)
+                    .iter()
+                    .map(price_level)
+                    .collect(),
+            })
+        )
+    }
+
+    async fn get_order_by_client_id(
+        &self,
+        request: Request<ClientOrderRequest>
+    ) -> Result<Response<OrderResponse>, Status> {
+        let lookup: ClientOrderRequest = request.into_inner();
+        let placed: ClientOrder = self.client_orders
+            .lock().await
+            .get(&(lookup.trader.clone(), lookup.client_order_id.clone()))
+            .cloned()
+            .ok_or_else(|| Status::not_found("Order not found"))?;
+        let mut response: OrderResponse = placed.response.unwrap_or_else(||
+            queued_response(placed.order_id, &lookup.client_order_id)
+        );
+
+        // Later changes (cancel, replace, trigger, expiry...) override the status the order was
+        // placed with
+        let trade_books: tokio::sync::MutexGuard<
+            HashMap<String, TradeBook>
+        > = self.trade_books.lock().await;
+        if let Some(record) = placed_order(&trade_books, &lookup.trader, placed.order_id) {
+            response.status = status_to_proto(record.status);
+            response.message = format!("order {}", record.status);
+        }
+        Ok(Response::new(response))
+    }
+
+    async fn get_order(
+        &self,
+        request: Request<GetOrderRequest>
+    ) -> Result<Response<OrderDetails>, Status> {
+        let order_id: Uuid = Uuid::parse_str(&request.into_inner().order_id).map_err(|_|
+            Status::invalid_argument("Invalid order id")
+        )?;
+
+        // Orders are recorded in the ledger of the trader who placed them
+        let trade_books: tokio::sync::MutexGuard<
+            HashMap<String, TradeBook>
+        > = self.trade_books.lock().await;
+        trade_books
+            .values()
+            .find_map(|trade_book| trade_book.order(&order_id))
+            .map(|record| Response::new(order_details(record)))
+            .ok_or_else(|| Status::not_found("Order not found"))
+    }
+
+    async fn list_open_orders(
+        &self,
+        request: Request<OpenOrdersRequest>
+    ) -> Result<Response<OpenOrdersResponse>, Status> {
+        let open_orders: OpenOrdersRequest = request.into_inner();
+        if open_orders.trader.is_empty() {
+            return Err(Status::invalid_argument("trader is required"));
+        }
+
+        if
+            !open_orders.pair.is_empty() &&
+            !self.order_books.lock().await.contains_key(&open_orders.pair)
+        {
+            return Err(Status::not_found("Order book not found"));
+        }
+
+        // Orders still working in the book or trigger book, in placing order (bracket exits waiting
+        // for their entry are recorded as new and not working yet)
+        let trade_books: tokio::sync::MutexGuard<
+            HashMap<String, TradeBook>
+        > = self.trade_books.lock().await;
+        let orders: Vec<OrderDetails> = trade_books
+            .get(&open_orders.trader)
+            .map(|trade_book| {
+                trade_book
+                    .orders()
+                    .iter()
+                    .filter(|record| record.open_volume > Decimal::ZERO && record.status != OrderStatus::New)
+                    .filter(|record| open_orders.pair.is_empty() || record.pair == open_orders.pair)
+                    .map(order_details)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Response::new(OpenOrdersResponse { orders }))
+    }
+
+    async fn place_order_group(
+        &self,
+        request: Request<OrderGroupRequest>
+    ) -> Result<Response<OrderGroupResponse>, Status> {
+        let group_request: OrderGroupRequest = request.into_inner();
+        let (first, group): (PendingOrder, OrderGroup) = self
+            .accept_order_group(&group_request)
+            .map_err(Status::invalid_argument)?;
+        let trader: String = group.trader.clone();
+        let group_id: Uuid = group.id;
+        let leg_ids: Vec<Uuid> = group.entry
+            .into_iter()
+            .chain(group.exits().map(|leg| leg.id))
+            .collect();
+        info!("Order group {} for {}: {:?}", group_id, trader, group.group_type);
+
+        // The engine registers the group with its first leg and places the other legs itself
+        let (reply, report_rx) = oneshot::channel();
+        let queued: QueuedOrder = QueuedOrder { order: first, group: Some(group), reply: Some(reply) };
+        if self.order_tx.send(queued).await.is_err() {
+            return Err(Status::internal("Failed to process order group"));
+        }
+        let report: ExecutionReport = report_rx.await.map_err(|_|
+            Status::internal("Failed to process order group")
+        )?;
+
+        let trade_books: tokio::sync::MutexGuard<
+            HashMap<String, TradeBook>
+        > = self.trade_books.lock().await;
+        let orders: Vec<OrderDetails> = leg_ids
+            .iter()
+            .filter_map(|id| placed_order(&trade_books, &trader, *id))
+            .map(order_details)
+            .collect();
+        Ok(
+            Response::new(OrderGroupResponse {
+                group_id: group_id.to_string(),
+                response: Some(report_response(&report, String::new())),
+                orders,
+            })
+        )
+    }
+}
+
+// Operator service sharing the order book service's state
+#[derive(Debug)]
+pub struct AdminService {
+    service: Arc<OrderBookService>,
+    offline_mode: bool, // books of added pairs are not fetched from Kraken
+    configured: HashMap<String, Instrument>, // trading rules from config.yaml for pairs added without rules
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn halt_trading(&self, request: Request<HaltRequest>) -> Result<Response<TradingStatusUpdate>, Status> {
+        let halt: HaltRequest = request.into_inner();
+        if halt.duration == 0 {
+            return Err(Status::invalid_argument("duration is required"));
+        }
+        let mode: HaltMode = halt_mode_from_proto(halt.mode).map_err(Status::invalid_argument)?;
+        let reason: &str = if halt.reason.is_empty() { "halted by the operator" } else { &halt.reason };
+
+        let mut order_books: tokio::sync::MutexGuard<
+            HashMap<String, Book>
+        > = self.service.order_books.lock().await;
+        let book: &mut Book = order_books
+            .get_mut(&halt.pair)
+            .ok_or_else(|| Status::not_found("Order book not found"))?;
+        if book.halted().is_some() || book.auction().is_some() {
+            return Err(Status::failed_precondition("Trading is already halted"));
+        }
+        let resume_at: DateTime<Utc> = Utc::now() + chrono::Duration::seconds(halt.duration as i64);
+        halt_pair(&self.service, &halt.pair, book, mode, resume_at, reason);
+        Ok(Response::new(trading_status(&halt.pair, book, reason)))
+    }
+
+    async fn resume_trading(&self, request: Request<PairRequest>) -> Result<Response<TradingStatusUpdate>, Status> {
+        let pair: String = request.into_inner().pair;
+
+        // Lock in the same order as process_orders
+        let mut order_books: tokio::sync::MutexGuard<
+            HashMap<String, Book>
+        > = self.service.order_books.lock().await;
+        let mut trade_books: tokio::sync::MutexGuard<
+            HashMap<String, TradeBook>
+        > = self.service.trade_books.lock().await;
+        let mut stop_books: tokio::sync::MutexGuard<
+            HashMap<String, Vec<PendingOrder>>
+        > = self.service.stop_books.lock().await;
+        let book: &mut Book = order_books
+            .get_mut(&pair)
+            .ok_or_else(|| Status::not_found("Order book not found"))?;
+        if book.halted().is_none() && book.auction().is_none() {
+            return Err(Status::failed_precondition("Trading is not halted"));
+        }
+        // Ending a pause starts the re-opening auction, ending an auction uncrosses it right away
+        let stop_orders: &mut Vec<PendingOrder> = stop_books.entry(pair.clone()).or_default();
+        update_trading_phase(&self.service, &pair, book, stop_orders, &mut trade_books, DateTime::<Utc>::MAX_UTC);
+        Ok(Response::new(trading_status(&pair, book, "")))
+    }
+
+    async fn add_pair(&self, request: Request<AddPairRequest>) -> Result<Response<PairStatus>, Status> {
+        let add: AddPairRequest = request.into_inner();
+        if add.pair.is_empty() {
+            return Err(Status::invalid_argument("pair is required"));
+        }
+        let instrument: Instrument = match &add.rules {
+            Some(rules) => instrument_from_proto(rules).map_err(Status::invalid_argument)?,
+            None => self.configured.get(&add.pair).copied().unwrap_or_default(),
+        };
+        if self.service.instrument(&add.pair).is_some() {
+            return Err(Status::already_exists(format!("Pair {} already exists", add.pair)));
+        }
+
+        // Fetched before locking, the polling loop keeps it fresh from then on
+        let book: Book = if self.offline_mode {
+            Book::new()
+        } else {
+            match fetch_order_book(&add.pair).await {
+                Ok(book) if !book.is_empty() => book,
+                Ok(_) => {
+                    return Err(Status::not_found(format!("Kraken has no order book for {}", add.pair)));
+                }
+                Err(e) => {
+                    return Err(Status::unavailable(format!("Failed to fetch the order book of {}: {}", add.pair, e)));
+                }
+            }
+        };
+        let mut order_books: tokio::sync::MutexGuard<
+            HashMap<String, Book>
+        > = self.service.order_books.lock().await;
+        if order_books.contains_key(&add.pair) {
+            return Err(Status::already_exists(format!("Pair {} already exists", add.pair)));
+        }
+        order_books.insert(add.pair.clone(), book);
+        self.service.instruments.write().unwrap().insert(add.pair.clone(), instrument);
+        info!("Added pair {}: {:?}", add.pair, instrument);
+
+        let stop_books: tokio::sync::MutexGuard<
+            HashMap<String, Vec<PendingOrder>>
+        > = self.service.stop_books.lock().await;
+        Ok(Response::new(pair_status(&add.pair, &order_books[&add.pair], &stop_books, &instrument)))
+    }
+
+    async fn remove_pair(&self, request: Request<PairRequest>) -> Result<Response<AdminResponse>, Status> {
+        let pair: String = request.into_inner().pair;
+
+        let mut order_books: tokio::sync::MutexGuard<
+            HashMap<String, Book>
+        > = self.service.order_books.lock().await;
+        let mut trade_books: tokio::sync::MutexGuard<
+            HashMap<String, TradeBook>
+        > = self.service.trade_books.lock().await;
+        let mut stop_books: tokio::sync::MutexGuard<
+            HashMap<String, Vec<PendingOrder>>
+        > = self.service.stop_books.lock().await;
+        if !order_books.contains_key(&pair) {
+            return Err(Status::not_found("Order book not found"));
+        }
+
+        // Orders for the pair are rejected as unknown from now on
+        self.service.instruments.write().unwrap().remove(&pair);
+        let canceled: Vec<Uuid> = cancel_open_orders(&mut order_books, &mut trade_books, &mut stop_books, None, Some(&pair));
+        order_books.remove(&pair);
+        stop_books.remove(&pair);
+        // Market data streams of the pair end (sent while locked, so no subscriber misses it)
+        let _ = self.service.removed_tx.send(pair.clone());
+        info!("Removed pair {}, canceled {} orders", pair, canceled.len());
+        Ok(Response::new(admin_response(format!("pair removed, {} orders canceled", canceled.len()), &canceled)))
+    }
+
+    async fn purge_book(&self, request: Request<PairRequest>) -> Result<Response<AdminResponse>, Status> {
+        let pair: String = request.into_inner().pair;
+
+        let mut order_books: tokio::sync::MutexGuard<
+            HashMap<String, Book>
+        > = self.service.order_books.lock().await;
+        let mut trade_books: tokio::sync::MutexGuard<
+            HashMap<String, TradeBook>
+        > = self.service.trade_books.lock().await;
+        let mut stop_books: tokio::sync::MutexGuard<
+            HashMap<String, Vec<PendingOrder>>
+        > = self.service.stop_books.lock().await;
+        if !order_books.contains_key(&pair) {
+            return Err(Status::not_found("Order book not found"));
+        }
+
+        // Traders' orders are canceled, Kraken liquidity is dropped until the next refresh
+        let canceled: Vec<Uuid> = cancel_open_orders(&mut order_books, &mut trade_books, &mut stop_books, None, Some(&pair));
+        let Some(book) = order_books.get_mut(&pair) else {
+            return Err(Status::not_found("Order book not found"));
+        };
+        let external: Vec<Uuid> = book.iter().map(|order| order.id).collect();
+        for order_id in &external {
+            book.remove(order_id);
+        }
+        publish_market_data(&self.service, &pair, book);
+        info!("Purged book {}: {} orders canceled, {} external orders removed", pair, canceled.len(), external.len());
+        Ok(
+            Response::new(
+                admin_response(
+                    format!("book purged, {} orders canceled, {} external orders removed", canceled.len(), external.len()),
+                    &canceled
+                )
+            )
+        )
+    }
+
+    async fn cancel_all_orders(&self, request: Request<CancelAllRequest>) -> Result<Response<AdminResponse>, Status> {
+        let cancel: CancelAllRequest = request.into_inner();
+        if cancel.trader.is_empty() {
+            return Err(Status::invalid_argument("trader is required"));
+        }
+        let pair: Option<&str> = Some(cancel.pair.as_str()).filter(|pair| !pair.is_empty());
+
+        let mut order_books: tokio::sync::MutexGuard<
+            HashMap<String, Book>
+        > = self.service.order_books.lock().await;
+        if pair.is_some_and(|pair| !order_books.contains_key(pair)) {
+            return Err(Status::not_found("Order book not found"));
+        }
+        let mut trade_books: tokio::sync::MutexGuard<
+            HashMap<String, TradeBook>
+        > = self.service.trade_books.lock().await;
+        let mut stop_books: tokio::sync::MutexGuard<
+            HashMap<String, Vec<PendingOrder>>
+        > = self.service.stop_books.lock().await;
+
+        let canceled: Vec<Uuid> = cancel_open_orders(&mut order_books, &mut trade_books, &mut stop_books, Some(&cancel.trader), pair);
+        for (pair, book) in order_books.iter_mut() {
+            publish_market_data(&self.service, pair, book);
+        }
+        info!("Canceled {} orders for {}", canceled.len(), cancel.trader);
+        Ok(Response::new(admin_response(format!("{} orders canceled", canceled.len()), &canceled)))
+    }
+
+    async fn get_engine_status(&self, _request: Request<EngineStatusRequest>) -> Result<Response<EngineStatus>, Status> {
+        let order_books: tokio::sync::MutexGuard<
+            HashMap<String, Book>
+        > = self.service.order_books.lock().await;
+        let trade_books: tokio::sync::MutexGuard<
+            HashMap<String, TradeBook>
+        > = self.service.trade_books.lock().await;
+        let stop_books: tokio::sync::MutexGuard<
+            HashMap<String, Vec<PendingOrder>>
+        > = self.service.stop_books.lock().await;
+
+        let pairs: Vec<PairStatus> = self.service
+            .pairs()
+            .iter()
+            .filter_map(|pair| {
+                let instrument: Instrument = self.service.instrument(pair)?;
+                Some(pair_status(pair, order_books.get(pair)?, &stop_books, &instrument))
+            })
+            .collect();
+        let queued_orders: usize = self.service.order_tx.max_capacity() - self.service.order_tx.capacity();
+        Ok(
+            Response::new(EngineStatus {
+                pairs,
+                queued_orders: queued_orders as u32,
+                traders: trade_books.len() as u32,
+                offline: self.offline_mode,
+                timestamp: Utc::now().to_rfc3339(),
+            })
+        )
+    }
+}
+
+// Stream the broadcast messages of one pair until the pair is removed; a subscriber that falls
+// behind gets a data loss error
+fn pair_stream<T: Clone + Send + 'static>(
+    rx: broadcast::Receiver<T>,
+    removed: broadcast::Receiver<String>,
+    pair: String,
+    pair_of: fn(&T) -> String
+) -> impl Stream<Item = Result<T, Status>> + Send + 'static {
+    stream::unfold((rx, removed, pair), move |(mut rx, mut removed, pair)| async move {
+        loop {
+            // Messages published before the removal are delivered first
+            let message: Result<T, broadcast::error::RecvError> = tokio::select! {
+                biased;
+                message = rx.recv() => message,
+                removed_pair = removed.recv() => match removed_pair {
+                    Ok(removed_pair) if removed_pair == pair => return None,
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+            };
+            match message {
+                Ok(message) if pair_of(&message) == pair => {
+                    return Some((Ok(message), (rx, removed, pair)));
+                }
+                Ok(_) => {
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    let status: Status = Status::data_loss(
+                        format!("subscriber lagged behind by {} messages, resubscribe", skipped)
+                    );
+                    return Some((Err(status), (rx, removed, pair)));
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    return None;
+                }
+            }
+        }
+    })
+}
+
+// Response to an order matched synchronously
+fn report_response(report: &ExecutionReport, client_order_id: String) -> OrderResponse {
+    OrderResponse {
+        status: status_to_proto(report.status),
+        message: if report.reject_reason.is_empty() {
+            format!(
+                "order {}, filled volume: {}, remaining volume: {}",
+                report.status,
+                report.filled_volume,
+                report.remaining_volume
+            )
+        } else {
+            format!("order rejected: {}", report.reject_reason)
+        },
+        order_id: report.order_id.to_string(),
+        report: Some(orderbook::ExecutionReport {
+            filled_volume: report.filled_volume.normalize().to_string(),
+            average_price: report.average_price
+                .map(|price| price.normalize().to_string())
+                .unwrap_or_default(),
+            remaining_volume: report.remaining_volume.normalize().to_string(),
+            fills: report.fills.iter().map(public_trade).collect(),
+            stp_canceled_volume: report.stp_canceled_volume.normalize().to_string(),
+            reject_reason: report.reject_reason.clone(),
+            group_id: report.group_id.map(|id| id.to_string()).unwrap_or_default(),
+        }),
+        client_order_id,
+    }
+}
+
+// Acknowledgement of an order that was queued and is not matched yet
+fn queued_response(order_id: Uuid, client_order_id: &str) -> OrderResponse {
+    OrderResponse {
+        status: status_to_proto(OrderStatus::New),
+        message: "order registerted and is being processed".into(),
+        order_id: order_id.to_string(),
+        report: None,
+        client_order_id: client_order_id.to_string(),
+    }
+}
+
+fn order_details(record: &OrderRecord) -> OrderDetails {
+    OrderDetails {
+        order_id: record.id.to_string(),
+        trader: record.trader.clone(),
+        pair: record.pair.clone(),
+        side: side_to_proto(record.side),
+        order_type: order_type_to_proto(record.order_type),
+        status: status_to_proto(record.status),
+        price: limit_price(record.order_type, record.price),
+        stop_price: stop_price(record.order_type, record.stop_price),
+        original_volume: record.volume.normalize().to_string(),
+        remaining_volume: record.open_volume.normalize().to_string(),
+        timestamp: record.timestamp.clone(),
+        expire_time: record.expire_time.map(|t| t.to_rfc3339()).unwrap_or_default(),
+        client_order_id: record.client_order_id.clone(),
+        filled_volume: record.filled_volume.normalize().to_string(),
+        stp_canceled_volume: record.stp_canceled_volume.normalize().to_string(),
+        reject_reason: record.reject_reason.clone(),
+        display_volume: if record.display_volume > Decimal::ZERO {
+            record.display_volume.normalize().to_string()
+        } else {
+            String::new()
+        },
+        trailing_offset: record.trailing_offset.map(|offset| offset.to_string()).unwrap_or_default(),
+        group_id: record.group_id.map(|id| id.to_string()).unwrap_or_default(),
+        transitions: record.transitions
+            .iter()
+            .map(|change| orderbook::StatusChange {
+                status: status_to_proto(change.status),
+                price: limit_price(record.order_type, change.price),
+                stop_price: stop_price(record.order_type, change.stop_price),
+                open_volume: change.open_volume.normalize().to_string(),
+                timestamp: change.timestamp.clone(),
+            })
+            .collect(),
+    }
+}
+
+fn group_details(group: &OrderGroup) -> orderbook::OrderGroupDetails {
+    let group_type: orderbook::OrderGroupType = match group.group_type {
+        GroupType::Oco => orderbook::OrderGroupType::Oco,
+        GroupType::Bracket => orderbook::OrderGroupType::Bracket,
+    };
+    let status: orderbook::OrderGroupStatus = match group.status {
+        GroupStatus::Waiting => orderbook::OrderGroupStatus::Waiting,
+        GroupStatus::Active => orderbook::OrderGroupStatus::Active,
+        GroupStatus::Completed => orderbook::OrderGroupStatus::Completed,
+        GroupStatus::Canceled => orderbook::OrderGroupStatus::Canceled,
+    };
+    orderbook::OrderGroupDetails {
+        group_id: group.id.to_string(),
+        group_type: group_type.into(),
+        status: status.into(),
+        pair: group.pair.clone(),
+        volume: group.volume.normalize().to_string(),
+        entry_order_id: group.entry.map(|id| id.to_string()).unwrap_or_default(),
+        take_profit_order_id: group.take_profit.id.to_string(),
+        stop_loss_order_id: group.stop_loss.id.to_string(),
+        timestamp: group.timestamp.clone(),
+    }
+}
+
+fn execution(fill: &Execution) -> orderbook::Execution {
+    let liquidity: orderbook::Liquidity = match fill.liquidity {
+        Liquidity::Maker => orderbook::Liquidity::Maker,
+        Liquidity::Taker => orderbook::Liquidity::Taker,
+    };
+    orderbook::Execution {
+        trade_id: fill.id.to_string(),
+        pair: fill.pair.clone(),
+        order_id: fill.order_id.to_string(),
+        client_order_id: fill.client_order_id.clone(),
+        side: side_to_proto(fill.side),
+        price: fill.price.normalize().to_string(),
+        volume: fill.volume.normalize().to_string(),
+        fee: fill.fee.normalize().to_string(),
+        liquidity: liquidity.into(),
+        aggressor_side: side_to_proto(fill.aggressor_side),
+        taker_trader: fill.taker_trader.clone(),
+        taker_order_id: fill.taker_order_id.to_string(),
+        maker_trader: fill.maker_trader.clone(),
+        maker_order_id: fill.maker_order_id.to_string(),
+        timestamp: fill.timestamp.clone(),
+    }
+}
+
+// Market and stop (market) orders have no limit price
+fn limit_price(order_type: OrderType, price: Decimal) -> String {
+    if matches!(order_type, OrderType::Market | OrderType::Stop | OrderType::TrailingStop) {
+        String::new()
+    } else {
+        price.normalize().to_string()
+    }
+}
+
+fn stop_price(order_type: OrderType, stop_price: Decimal) -> String {
+    if order_type.is_stop() {
+        stop_price.normalize().to_string()
+    } else {
+        String::new()
+    }
+}
+
+fn price_level(level: &Level) -> PriceLevel {
+    PriceLevel {
+        price: level.price.normalize().to_string(),
+        volume: level.volume.normalize().to_string(),
+        order_count: level.order_count as u32,
+    }
+}
+
+// Publish level changes and executions since the last call to the subscription streams
+fn publish_market_data(service: &OrderBookService, pair: &str, book: &mut Book) {
+    // Sending only fails when nobody is subscribed
+    if let Some((sequence, changes)) = book.take_changes() {
+        let mut update: OrderBookUpdate = OrderBookUpdate {
+            pair: pair.to_string(),
+            sequence,
+            snapshot: false,
+            ..Default::default()
+        };
+        for (side, level) in changes {
+            if side == Side::Buy {
+                update.bids.push(price_level(&level));
+            } else {
+                update.asks.push(price_level(&level));
+            }
+        }
+        let _ = service.book_tx.send(update);
+        // During a call auction every book change moves the indicative price and volume
+        if book.auction().is_some() {
+            let _ = service.auction_tx.send(auction_update(pair, book));
+        }
+    }
+    for fill in book.take_fills() {
+        let _ = service.trade_tx.send(public_trade(&fill));
+    }
+}
+
+// Current call auction state of a pair (phase continuous and no indicative values outside auctions)
+fn auction_update(pair: &str, book: &Book) -> AuctionUpdate {
+    let Some(uncross_at) = book.auction() else {
+        return AuctionUpdate {
+            pair: pair.to_string(),
+            phase: orderbook::TradingPhase::Continuous as i32,
+            ..Default::default()
+        };
+    };
+    AuctionUpdate {
+        uncross_time: uncross_at.to_rfc3339(),
+        ..equilibrium_update(pair, orderbook::TradingPhase::Auction, book.equilibrium())
+    }
+}
+
+// Current trading phase of a pair, halts take precedence over the auction a halt may have started
+fn trading_status(pair: &str, book: &Book, reason: &str) -> TradingStatusUpdate {
+    let (phase, resume_at): (orderbook::TradingPhase, Option<DateTime<Utc>>) = if let Some(resume_at) = book.halted() {
+        (orderbook::TradingPhase::Halted, Some(resume_at))
+    } else if let Some(uncross_at) = book.auction() {
+        (orderbook::TradingPhase::Auction, Some(uncross_at))
+    } else {
+        (orderbook::TradingPhase::Continuous, None)
+    };
+    TradingStatusUpdate {
+        pair: pair.to_string(),
+        phase: phase as i32,
+        reason: reason.to_string(),
+        resume_time: resume_at.map(|time| time.to_rfc3339()).unwrap_or_default(),
+        timestamp: Utc::now().to_rfc3339(),
+    }
+}
+
+fn equilibrium_update(
+    pair: &str,
+    phase: orderbook::TradingPhase,
+    equilibrium: Option<Equilibrium>
+) -> AuctionUpdate {
+    let mut update: AuctionUpdate = AuctionUpdate {
+        pair: pair.to_string(),
+        phase: phase as i32,
+        indicative_volume: "0".to_string(),
+        surplus: "0".to_string(),
+        ..Default::default()
+    };
+    if let Some(equilibrium) = equilibrium {
+        update.indicative_price = equilibrium.price.normalize().to_string();
+        update.indicative_volume = equilibrium.volume.normalize().to_string();
+        update.surplus = equilibrium.surplus.normalize().to_string();
+    }
+    update
+}
+
+fn admin_response(message: String, canceled: &[Uuid]) -> AdminResponse {
+    AdminResponse {
+        message,
+        order_ids: canceled.iter().map(Uuid::to_string).collect(),
+    }
+}
+
+fn pair_status(
+    pair: &str,
+    book: &Book,
+    stop_books: &HashMap<String, Vec<PendingOrder>>,
+    instrument: &Instrument
+) -> PairStatus {
+    let status: TradingStatusUpdate = trading_status(pair, book, "");
+    let price = |price: Option<Decimal>| price.map(|price| price.normalize().to_string()).unwrap_or_default();
+    PairStatus {
+        pair: pair.to_string(),
+        phase: status.phase,
+        resume_time: status.resume_time,
+        resting_orders: book.len() as u32,
+        stop_orders: stop_books.get(pair).map_or(0, Vec::len) as u32,
+        best_bid: price(book.best_bid()),
+        best_ask: price(book.best_ask()),
+        last_price: price(book.last_price()),
+        sequence: book.sequence(),
+        rules: Some(instrument_rules(instrument)),
+    }
+}
+
+fn instrument_rules(instrument: &Instrument) -> InstrumentRules {
+    let decimal = |value: Decimal| value.normalize().to_string();
+    InstrumentRules {
+        price_scale: instrument.price_scale,
+        volume_scale: instrument.volume_scale,
+        tick_size: decimal(instrument.tick_size),
+        lot_size: decimal(instrument.lot_size),
+        min_volume: decimal(instrument.min_volume),
+        max_volume: decimal(instrument.max_volume),
+        max_price: decimal(instrument.max_price),
+        min_notional: decimal(instrument.min_notional),
+        maker_fee: decimal(instrument.maker_fee),
+        taker_fee: decimal(instrument.taker_fee),
+        price_band: decimal(instrument.price_band),
+        limit_collar: decimal(instrument.limit_collar),
+        halt_move: decimal(instrument.halt_move),
+        halt_window: instrument.halt_window,
+        halt_duration: instrument.halt_duration,
+        halt_mode: halt_mode_to_proto(instrument.halt_mode),
+    }
+}
+
+// Trading rules sent to the admin service; zero scales and halt times take the defaults
+fn instrument_from_proto(rules: &InstrumentRules) -> Result<Instrument, String> {
+    let defaults: Instrument = Instrument::default();
+    if rules.price_scale > Decimal::MAX_SCALE || rules.volume_scale > Decimal::MAX_SCALE {
+        return Err(format!("scales allow at most {} decimal places", Decimal::MAX_SCALE));
+    }
+    let or_default = |value: u64, default: u64| if value == 0 { default } else { value };
+    let decimal = |field: &str, value: &str| parse_decimal(field, value, Decimal::MAX_SCALE);
+    Ok(Instrument {
+        price_scale: if rules.price_scale == 0 { defaults.price_scale } else { rules.price_scale },
+        volume_scale: if rules.volume_scale == 0 { defaults.volume_scale } else { rules.volume_scale },
+        tick_size: decimal("tick_size", &rules.tick_size)?,
+        lot_size: decimal("lot_size", &rules.lot_size)?,
+        min_volume: decimal("min_volume", &rules.min_volume)?,
+        max_volume: decimal("max_volume", &rules.max_volume)?,
+        max_price: decimal("max_price", &rules.max_price)?,
+        min_notional: decimal("min_notional", &rules.min_notional)?,
+        maker_fee: decimal("maker_fee", &rules.maker_fee)?,
+        taker_fee: decimal("taker_fee", &rules.taker_fee)?,
+        price_band: decimal("price_band", &rules.price_band)?,
+        limit_collar: decimal("limit_collar", &rules.limit_collar)?,
+        halt_move: decimal("halt_move", &rules.halt_move)?,
+        halt_window: or_default(rules.halt_window, defaults.halt_window),
+        halt_duration: or_default(rules.halt_duration, defaults.halt_duration),
+        halt_mode: halt_mode_from_proto(rules.halt_mode)?,
+    })
+}
+
+fn public_trade(fill: &Fill) -> PublicTrade {
+    PublicTrade {
+        trade_id: fill.id.to_string(),
+        pair: fill.pair.clone(),
+        price: fill.price.normalize().to_string(),
+        volume: fill.volume.normalize().to_string(),
+        aggressor_side: side_to_proto(fill.aggressor_side),
+        maker_order_id: fill.maker_order_id.to_string(),
+        taker_order_id: fill.taker_order_id.to_string(),
+        timestamp: fill.timestamp.clone(),
+        maker_client_order_id: fill.maker_client_order_id.clone(),
+        taker_client_order_id: fill.taker_client_order_id.clone(),
+    }
+}
+
+// Proto enums are checked at the gRPC boundary: unspecified or unknown values are rejected, except
+// for time in force where unspecified means GTC
+fn side_from_proto(value: i32) -> Result<Side, String> {
+    match orderbook::Side::try_from(value) {
+        Ok(orderbook::Side::Buy) => Ok(Side::Buy),
+        Ok(orderbook::Side::Sell) => Ok(Side::Sell),
+        _ => Err(format!("Unknown side: {}", value)),
+    }
+}
+
+fn order_type_from_proto(value: i32) -> Result<OrderType, String> {
+    match orderbook::OrderType::try_from(value) {
+        Ok(orderbook::OrderType::Market) => Ok(OrderType::Market),
+        Ok(orderbook::OrderType::Limit) => Ok(OrderType::Limit),
+        Ok(orderbook::OrderType::Stop) => Ok(OrderType::Stop),
+        Ok(orderbook::OrderType::StopLimit) => Ok(OrderType::StopLimit),
+        Ok(orderbook::OrderType::TrailingStop) => Ok(OrderType::TrailingStop),
+        Ok(orderbook::OrderType::MarketToLimit) => Ok(OrderType::MarketToLimit),
+        Ok(orderbook::OrderType::PrimaryPeg) => Ok(OrderType::PrimaryPeg),
+        Ok(orderbook::OrderType::MidpointPeg) => Ok(OrderType::MidpointPeg),
+        _ => Err(format!("Unknown order type: {}", value)),
+    }
+}
+
+fn time_in_force_from_proto(value: i32) -> Result<TimeInForce, String> {
+    match orderbook::TimeInForce::try_from(value) {
+        Ok(orderbook::TimeInForce::Unspecified | orderbook::TimeInForce::Gtc) => Ok(TimeInForce::Gtc),
+        Ok(orderbook::TimeInForce::Ioc) => Ok(TimeInForce::Ioc),
+        Ok(orderbook::TimeInForce::Fok) => Ok(TimeInForce::Fok),
+        Ok(orderbook::TimeInForce::Gtd) => Ok(TimeInForce::Gtd),
+        Ok(orderbook::TimeInForce::Day) => Ok(TimeInForce::Day),
+        Err(_) => Err(format!("Unknown time in force: {}", value)),
+    }
+}
+
+fn post_only_from_proto(value: i32) -> Result<PostOnly, String> {
+    match orderbook::PostOnly::try_from(value) {
+        Ok(orderbook::PostOnly::Unspecified) => Ok(PostOnly::Off),
+        Ok(orderbook::PostOnly::Reject) => Ok(PostOnly::Reject),
+        Ok(orderbook::PostOnly::Reprice) => Ok(PostOnly::Reprice),
+        Err(_) => Err(format!("Unknown post-only mode: {}", value)),
+    }
+}
+
+fn halt_mode_from_proto(value: i32) -> Result<HaltMode, String> {
+    match orderbook::HaltMode::try_from(value) {
+        Ok(orderbook::HaltMode::Unspecified | orderbook::HaltMode::Pause) => Ok(HaltMode::Pause),
+        Ok(orderbook::HaltMode::Auction) => Ok(HaltMode::Auction),
+        Err(_) => Err(format!("Unknown halt mode: {}", value)),
+    }
+}
+
+fn group_type_from_proto(value: i32) -> Result<GroupType, String> {
+    match orderbook::OrderGroupType::try_from(value) {
+        Ok(orderbook::OrderGroupType::Oco) => Ok(GroupType::Oco),
+        Ok(orderbook::OrderGroupType::Bracket) => Ok(GroupType::Bracket),
+        _ => Err(format!("Unknown order group type: {}", value)),
+    }
+}
+
+// Unspecified means no self-trade prevention unless the trader has a default (see accept_order)
+fn self_trade_prevention_from_proto(value: i32) -> Result<SelfTradePrevention, String> {
+    match orderbook::SelfTradePrevention::try_from(value) {
+        Ok(orderbook::SelfTradePrevention::Unspecified | orderbook::SelfTradePrevention::None) => {
+            Ok(SelfTradePrevention::None)
+        }
+        Ok(orderbook::SelfTradePrevention::CancelNewest) => Ok(SelfTradePrevention::CancelNewest),
+        Ok(orderbook::SelfTradePrevention::CancelOldest) => Ok(SelfTradePrevention::CancelOldest),
+        Ok(orderbook::SelfTradePrevention::CancelBoth) => Ok(SelfTradePrevention::CancelBoth),
+        Ok(orderbook::SelfTradePrevention::DecrementAndCancel) => Ok(SelfTradePrevention::DecrementAndCancel),
+        Err(_) => Err(format!("Unknown self-trade prevention: {}", value)),
+    }
+}
+
+fn side_to_proto(side: Side) -> i32 {
+    let side: orderbook::Side = match side {
+        Side::Buy => orderbook::Side::Buy,
+        Side::Sell => orderbook::Side::Sell,
+    };
+    side.into()
+}
+
+fn halt_mode_to_proto(mode: HaltMode) -> i32 {
+    let mode: orderbook::HaltMode = match mode {
+        HaltMode::Pause => orderbook::HaltMode::Pause,
+        HaltMode::Auction => orderbook::HaltMode::Auction,
+    };
+    mode.into()
+}
+
+fn order_type_to_proto(order_type: OrderType) -> i32 {
+    let order_type: orderbook::OrderType = match order_type {
+        OrderType::Market => orderbook::OrderType::Market,
+        OrderType::Limit => orderbook::OrderType::Limit,
+        OrderType::Stop => orderbook::OrderType::Stop,
+        OrderType::StopLimit => orderbook::OrderType::StopLimit,
+        OrderType::TrailingStop => orderbook::OrderType::TrailingStop,
+        OrderType::MarketToLimit => orderbook::OrderType::MarketToLimit,
+        OrderType::PrimaryPeg => orderbook::OrderType::PrimaryPeg,
+        OrderType::MidpointPeg => orderbook::OrderType::MidpointPeg,
+    };
+    order_type.into()
+}
+
+fn status_to_proto(status: OrderStatus) -> i32 {
+    let status: orderbook::OrderStatus = match status {
+        OrderStatus::New => orderbook::OrderStatus::New,
+        OrderStatus::Pending => orderbook::OrderStatus::Pending,
+        OrderStatus::Open => orderbook::OrderStatus::Open,
+        OrderStatus::PartiallyFilled => orderbook::OrderStatus::PartiallyFilled,
+        OrderStatus::Filled => orderbook::OrderStatus::Filled,
+        OrderStatus::Canceled => orderbook::OrderStatus::Canceled,
+        OrderStatus::Rejected => orderbook::OrderStatus::Rejected,
+        OrderStatus::Expired => orderbook::OrderStatus::Expired,
+        OrderStatus::PendingReplace => orderbook::OrderStatus::PendingReplace,
+        OrderStatus::Replaced => orderbook::OrderStatus::Replaced,
+        OrderStatus::Triggered => orderbook::OrderStatus::Triggered,
+        OrderStatus::StpCanceled => orderbook::OrderStatus::StpCanceled,
+    };
+    status.into()
+}
+
+// Resolve the expiry of an incoming order from its time in force: GTD orders keep the given
+// RFC 3339 expire time, DAY orders expire at the next session close, others never expire
+fn resolve_time_in_force(
+    time_in_force: TimeInForce,
+    expire_time: &str,
+    session_close: NaiveTime,
+    now: DateTime<Utc>
+) -> Result<Option<DateTime<Utc>>, String> {
+    match time_in_force {
+        TimeInForce::Gtc | TimeInForce::Ioc | TimeInForce::Fok => Ok(None),
+        TimeInForce::Gtd => {
+            let expire_time: DateTime<Utc> = DateTime::parse_from_rfc3339(expire_time)
+                .map_err(|_| "GTD orders require an RFC 3339 expire_time".to_string())?
+                .with_timezone(&Utc);
+            if expire_time <= now {
+                return Err("expire_time must be in the future".to_string());
+            }
+            Ok(Some(expire_time))
+        }
+        TimeInForce::Day => {
+            let close_today: DateTime<Utc> = now.date_naive().and_time(session_close).and_utc();
+            if close_today > now {
+                Ok(Some(close_today))
+            } else {
+                Ok(Some(close_today + chrono::Duration::days(1)))
+            }
+        }
+    }
+}
+
+// Parse a decimal price or volume of a request: empty means zero, negative values and more decimal
+// places than the pair's scale are rejected
+fn parse_decimal(field: &str, value: &str, scale: u32) -> Result<Decimal, String> {
+    if value.trim().is_empty() {
+        return Ok(Decimal::ZERO);
+    }
+    let parsed: Decimal = Decimal::from_str(value.trim())
+        .map_err(|_| format!("Invalid {}: {}", field, value))?
+        .normalize();
+    if parsed < Decimal::ZERO {
+        return Err(format!("{} must not be negative", field));
+    }
+    if parsed.scale() > scale {
+        return Err(format!("{} allows at most {} decimal places", field, scale));
+    }
+    Ok(parsed)
+}
+
+// Parse the trailing offset of a request: empty means none, a trailing % makes it a percentage of
+// the reference price
+fn parse_trailing_offset(value: &str, scale: u32) -> Result<Option<TrailingOffset>, String> {
+    let value: &str = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let offset: TrailingOffset = match value.strip_suffix('%') {
+        Some(percent) => TrailingOffset::Percent(parse_decimal("trailing_offset", percent, scale)?),
+        None => TrailingOffset::Absolute(parse_decimal("trailing_offset", value, scale)?),
+    };
+    Ok(Some(offset))
+}
+
+// Convert an order request into the order processed by the engine
+fn parse_order(
+    request: &OrderRequest,
+    instrument: Instrument,
+    session_close: NaiveTime,
+    now: DateTime<Utc>
+) -> Result<NewOrder, String> {
+    let time_in_force: TimeInForce = time_in_force_from_proto(request.time_in_force)?;
+    Ok(NewOrder {
+        pair: request.pair.clone(),
+        trader: request.trader.clone(),
+        side: side_from_proto(request.side)?,
+        order_type: order_type_from_proto(request.order_type)?,
+        price: parse_decimal("price", &request.price, instrument.price_scale)?,
+        volume: parse_decimal("volume", &request.volume, instrument.volume_scale)?,
+        stop_price: parse_decimal("stop_price", &request.stop_price, instrument.price_scale)?,
+        trailing_offset: parse_trailing_offset(&request.trailing_offset, instrument.price_scale)?,
+        time_in_force,
+        expire_time: resolve_time_in_force(time_in_force, &request.expire_time, session_close, now)?,
+        client_order_id: request.client_order_id.clone(),
+        self_trade_prevention: self_trade_prevention_from_proto(request.self_trade_prevention)?,
+        post_only: post_only_from_proto(request.post_only)?,
+        reduce_only: request.reduce_only,
+        display_volume: parse_decimal("display_volume", &request.display_volume, instrument.volume_scale)?,
+        group_id: None, // assigned when the order is a leg of an order group
+    })
+}
+
+// Check an order against the trading rules of its pair; limit and stop prices must be on the tick
+// and the notional is taken at the limit price (stop price for stop orders; market, market-to-limit
+// and pegged orders have none, the engine sets their price)
+fn check_trading_rules(order: &NewOrder, instrument: &Instrument) -> Result<(), String> {
+    // The stop price of trailing stops is set by the engine from the offset
+    match (order.order_type, order.trailing_offset) {
+        (OrderType::TrailingStop, None) => {
+            return Err("trailing_stop orders require a trailing_offset".to_string());
+        }
+        (OrderType::TrailingStop, Some(offset)) => {
+            if order.stop_price > Decimal::ZERO {
+                return Err("trailing_stop orders take a trailing_offset instead of a stop_price".to_string());
+            }
+            match offset {
+                TrailingOffset::Absolute(offset) if offset <= Decimal::ZERO => {
+                    return Err("trailing_offset must be positive".to_string());
+                }
+                TrailingOffset::Absolute(offset)
+                    if instrument.tick_size > Decimal::ZERO && !(offset % instrument.tick_size).is_zero() => {
+                    return Err(format!("trailing_offset must be a multiple of the tick size {}", instrument.tick_size));
+                }
+                TrailingOffset::Percent(percent) if percent <= Decimal::ZERO || percent >= Decimal::ONE_HUNDRED => {
+                    return Err("trailing_offset percentage must be between 0 and 100".to_string());
+                }
+                _ => {}
+            }
+        }
+        (_, Some(_)) => {
+            return Err("trailing_offset requires a trailing_stop order".to_string());
+        }
+        (_, None) => {}
+    }
+    if order.post_only != PostOnly::Off {
+        if order.order_type != OrderType::Limit {
+            return Err("post_only requires a limit order".to_string());
+        }
+        if matches!(order.time_in_force, TimeInForce::Ioc | TimeInForce::Fok) {
+            return Err("post_only orders can't be ioc or fok".to_string());
+        }
+    }
+    if order.display_volume > Decimal::ZERO {
+        if order.order_type != OrderType::Limit {
+            return Err("display_volume requires a limit order".to_string());
+        }
+        if matches!(order.time_in_force, TimeInForce::Ioc | TimeInForce::Fok) {
+            return Err("iceberg orders can't be ioc or fok".to_string());
+        }
+        if order.display_volume >= order.volume {
+            return Err("display_volume must be below the volume".to_string());
+        }
+        if order.display_volume < instrument.min_volume {
+            return Err(format!("display_volume is below the minimum of {}", instrument.min_volume));
+        }
+        if instrument.lot_size > Decimal::ZERO && !(order.display_volume % instrument.lot_size).is_zero() {
+            return Err(format!("display_volume must be a multiple of the lot size {}", instrument.lot_size));
+        }
+    }
+    if order.volume <= Decimal::ZERO {
+        return Err("volume must be positive".to_string());
+    }
+    if instrument.lot_size > Decimal::ZERO && !(order.volume % instrument.lot_size).is_zero() {
+        return Err(format!("volume must be a multiple of the lot size {}", instrument.lot_size));
+    }
+    if order.volume < instrument.min_volume {
+        return Err(format!("volume is below the minimum of {}", instrument.min_volume));
+    }
+    if instrument.max_volume > Decimal::ZERO && order.volume > instrument.max_volume {
+        return Err(format!("volume is above the maximum of {}", instrument.max_volume));
+    }
+
+    let has_limit_price: bool = matches!(order.order_type, OrderType::Limit | OrderType::StopLimit);
+    let has_stop_price: bool = matches!(order.order_type, OrderType::Stop | OrderType::StopLimit);
+    let mut prices: Vec<(&str, Decimal)> = Vec::new();
+    if has_limit_price {
+        prices.push(("price", order.price));
+    }
+    if has_stop_price {
+        prices.push(("stop_price", order.stop_price));
+    }
+    for (field, price) in prices {
+        if price <= Decimal::ZERO {
+            return Err(format!("{} must be positive", field));
+        }
+        if instrument.tick_size > Decimal::ZERO && !(price % instrument.tick_size).is_zero() {
+            return Err(format!("{} must be a multiple of the tick size {}", field, instrument.tick_size));
+        }
+        if instrument.max_price > Decimal::ZERO && price > instrument.max_price {
+            return Err(format!("{} is above the maximum of {}", field, instrument.max_price));
+        }
+    }
+
+    let notional_price: Decimal = if has_limit_price {
+        order.price
+    } else if has_stop_price {
+        order.stop_price
+    } else {
+        Decimal::ZERO
+    };
+    if notional_price.is_zero() {
+        return Ok(());
+    }
+    check_notional(notional_price, order.volume, instrument)
+}
+
+// Value of an order at the given price against the minimum notional; values too large to compute are
+// rejected so no fill of the order can overflow
+fn check_notional(price: Decimal, volume: Decimal, instrument: &Instrument) -> Result<(), String> {
+    let notional: Decimal = price
+        .checked_mul(volume)
+        .ok_or_else(|| "order value is too large".to_string())?;
+    if notional < instrument.min_notional {
+        return Err(format!("order value is below the minimum notional of {}", instrument.min_notional));
+    }
+    Ok(())
+}
+
+// Order ledger record of an order placed by the trader (proves ownership of the order)
+fn placed_order<'a>(
+    trade_books: &'a HashMap<String, TradeBook>,
+    trader: &str,
+    order_id: Uuid
+) -> Option<&'a OrderRecord> {
+    trade_books.get(trader)?.order(&order_id)
+}
+
+// Order ledger record to update on a status change; orders not placed through the exchange (Kraken
+// liquidity) have none
+fn order_record<'a>(
+    trade_books: &'a mut HashMap<String, TradeBook>,
+    trader: &str,
+    order_id: Uuid
+) -> Option<&'a mut OrderRecord> {
+    trade_books.get_mut(trader)?.order_mut(&order_id)
+}
+
+// Function to persist the order book to a CSV file (for testing and development purposes)
+async fn persist_order_book(
+    order_books: &HashMap<String, Book>,
+    pair: &str,
+    include_timestamp: bool
+) -> Result<(), Box<dyn Error>> {
+    let config: Config = load_config().unwrap();
+
+    let timestamp: String = if include_timestamp {
+        format!("_{}", Utc::now().format("%Y%m%d%H%M%S%6f"))
+    } else {
+        String::new()
+    };
+
+    let file_path: String = format!(
+        "{}/{}_order_book{}.csv",
+        config.kraken.persist,
+        pair,
+        timestamp
+    );
+    let mut wtr: Writer<File> = Writer::from_writer(File::create(&file_path)?);
+
+    // The book is kept sorted (asks then bids, descending price), so it can be written as is
+    if let Some(book) = order_books.get(pair) {
+        for order in book.iter() {
+            wtr.serialize(order)?;
+        }
+        wtr.flush()?;
+    }
+    Ok(())
+}
+
+// Function to update order books in a loop (TODO: add error handling when not able to fetch order books, move sleep duration to config);
+// pairs added or removed through the admin service are picked up on the next round
+async fn update_order_books(service: Arc<OrderBookService>, offline_mode: bool) {
+    if offline_mode {
+        println!("Offline mode: Skipping API fetch.\n");
+        return;
+    }
+
+    loop {
+        let pairs: Vec<String> = service.pairs();
+        let fetches = pairs.iter().map(|pair| {
+            let pair: String = pair.to_string();
+            async move {
+                let book: Book = fetch_order_book(&pair).await.unwrap_or_default();
+                (pair, book)
+            }
+        });
+        let results: Vec<(String, Book)> = join_all(fetches).await;
+
+        {
+            let mut order_books: tokio::sync::MutexGuard<
+                HashMap<String, Book>
+            > = service.order_books.lock().await;
+            let mut trade_books: tokio::sync::MutexGuard<
+                HashMap<String, TradeBook>
+            > = service.trade_books.lock().await;
+            let mut stop_books: tokio::sync::MutexGuard<
+                HashMap<String, Vec<PendingOrder>>
+            > = service.stop_books.lock().await;
+
+            // Refresh keeps the last traded price and sequence, then pegged orders follow the new
+            // quotes, stops are re-checked against them and the changed levels are streamed to
+            // subscribers
+            for (pair, fresh) in results {
+                // The pair may have been removed while its book was fetched
+                let Some(book) = order_books.get_mut(&pair) else {
+                    continue;
+                };
+                book.refresh(fresh);
+                let instrument: Instrument = service.instrument(&pair).unwrap_or_default();
+                let stop_orders: &mut Vec<PendingOrder> = stop_books.entry(pair.clone()).or_default();
+                trigger_stop_orders(book, &pair, stop_orders, &mut trade_books, service.stop_trigger, &instrument);
+                check_volatility(&service, &pair, book, &instrument);
+                publish_market_data(&service, &pair, book);
+            }
+        }
+
+        // Persist the order book after updating
+        for pair in &pairs {
+            let new_order_books: tokio::sync::MutexGuard<
+                HashMap<String, Book>
+            > = service.order_books.lock().await;
+            if let Err(e) = persist_order_book(&new_order_books, pair, false).await {
+                eprintln!("Failed to persist order book: {}", e);
+            }
+        }
+
+        // Sleep for 10 seconds before fetching order books again (maybe use Tokio timer instead of sleep)
+        sleep(Duration::from_secs(10)).await;
+    }
+}
+
+// Helper function to parse orders from JSON array
+fn parse_orders(data: &Value, side: Side, timestamp: &str) -> Vec<Order> {
+    data.as_array()
+        .unwrap_or(&vec![])
+        .iter()
+        .map(|order| {
+            let price = Decimal::from_str(order[0].as_str().unwrap()).unwrap();
+            let volume = Decimal::from_str(order[1].as_str().unwrap()).unwrap();
+            Order {
+                id: Uuid::new_v4(),
+                trader: String::new(),
+                price: price.normalize(),
+                volume: volume.normalize(),
+                original_volume: volume.normalize(),
+                display_volume: Decimal::ZERO,
+                hidden_volume: Decimal::ZERO,
+                side,
+                timestamp: timestamp.to_string(),
+                order_type: OrderType::Limit,
+                expire_time: None,
+                client_order_id: String::new(),
+            }
+        })
+        .collect()
+}
+
+// Fetch the order book for a given trading pair from Kraken API and return it as a Book
+async fn fetch_order_book(pair: &str) -> Result<Book, reqwest::Error> {
+    let url: String = format!("{}/?pair={}", "https://api.kraken.com/0/public/Depth", pair);
+    let response: Value = reqwest::get(&url).await?.json::<Value>().await?;
+    let timestamp: String = Utc::now().to_rfc3339();
+
+    let asks: Vec<Order> = parse_orders(&response["result"][pair]["asks"], Side::Sell, &timestamp);
+    let bids: Vec<Order> = parse_orders(&response["result"][pair]["bids"], Side::Buy, &timestamp);
+
+    // Combine asks and bids into a single book (levels are kept sorted by price)
+    Ok(asks.into_iter().chain(bids).collect())
+}
+
+// Fetch initial order books for the given trading pairs in parallel
+async fn fetch_order_books(pairs: Vec<&str>) -> HashMap<String, Book> {
+    let fetches = pairs.iter().map(|pair| {
+        let pair: String = pair.to_string();
+        async move {
+            let book: Book = fetch_order_book(&pair).await.unwrap_or_default();
+            (pair, book)
+        }
+    });
+    let results: Vec<(String, Book)> = join_all(fetches).await;
+
+    results.into_iter().collect()
+}
+
+// Function to load order book from CSV files
+async fn load_order_book_from_csv(
+    file_paths: Vec<&str>
+) -> Result<HashMap<String, Book>, Box<dyn Error>> {
+    let mut order_books: HashMap<String, Book> = HashMap::new();
+
+    for file_path in file_paths {
+        let mut rdr: csv::Reader<File> = ReaderBuilder::new().from_path(file_path)?;
+        for result in rdr.deserialize::<Order>() {
+            match result {
+                Ok(order) => {
+                    let pair: String = file_path
+                        .split('_')
+                        .next()
+                        .unwrap_or("unknown")
+                        .to_string()
+                        .replace("data/offline/", "");
+                    info!("Loaded order for {}: {}", pair, order);
+                    order_books.entry(pair.clone()).or_default().insert(order);
+                }
+                Err(e) => {
+                    println!("Error deserializing order: {}", e);
+                }
+            }
+        }
+    }
+
+    Ok(order_books)
+}
+
+// Checks of reduce-only and post-only orders against the trader's position and the book at the time
+// the matching engine takes the order; post-only orders that would cross are rejected or repriced
+// one tick (smallest price step without a tick size) behind the best contra price
+fn apply_order_flags(
+    book: &Book,
+    position: Decimal,
+    order: &mut NewOrder,
+    instrument: &Instrument
+) -> Result<(), String> {
+    if order.reduce_only {
+        let reduces: bool = match order.side {
+            Side::Buy => position < Decimal::ZERO,
+            Side::Sell => position > Decimal::ZERO,
+        };
+        if !reduces {
+            return Err(format!("reduce-only order would increase the position of {}", position.normalize()));
+        }
+        if order.volume > position.abs() {
+            return Err(
+                format!(
+                    "reduce-only order volume {} exceeds the position of {}",
+                    order.volume.normalize(),
+                    position.normalize()
+                )
+            );
+        }
+    }
+
+    if order.post_only == PostOnly::Off {
+        return Ok(());
+    }
+    let best_contra: Option<Decimal> = match order.side {
+        Side::Buy => book.best_ask().filter(|ask| order.price >= *ask),
+        Side::Sell => book.best_bid().filter(|bid| order.price <= *bid),
+    };
+    let Some(best_contra) = best_contra else {
+        return Ok(());
+    };
+    if order.post_only == PostOnly::Reject {
+        return Err(
+            format!(
+                "post-only order at {} would take liquidity at {}",
+                order.price.normalize(),
+                best_contra.normalize()
+            )
+        );
+    }
+    let tick: Decimal = instrument.price_increment();
+    let price: Decimal = match order.side {
+        Side::Buy => best_contra - tick,
+        Side::Sell => best_contra + tick,
+    };
+    if price <= Decimal::ZERO {
+        return Err(format!("post-only order can't be repriced below {}", best_contra.normalize()));
+    }
+    println!("Post-only order repriced from {} to {}", order.price, price);
+    order.price = price;
+    Ok(())
+}
+
+// No orders are accepted while the pair is halted; orders that can't rest are not accepted during a
+// call auction, they would never execute
+fn check_trading_phase(book: &Book, order: &NewOrder) -> Result<(), String> {
+    if let Some(resume_at) = book.halted() {
+        return Err(format!("trading in {} is halted until {}", order.pair, resume_at.to_rfc3339()));
+    }
+    if book.auction().is_none() {
+        return Ok(());
+    }
+    if matches!(order.order_type, OrderType::Market | OrderType::MarketToLimit) || order.order_type.is_pegged() {
+        return Err(format!("{} orders are not accepted during the call auction", order.order_type));
+    }
+    if matches!(order.time_in_force, TimeInForce::Ioc | TimeInForce::Fok) && !order.order_type.is_stop() {
+        return Err("immediate-or-cancel and fill-or-kill orders are not accepted during the call auction".to_string());
+    }
+    Ok(())
+}
+
+// Price a pegged order follows: the best price of its own side (primary peg) or the midpoint of the
+// best bid and ask rounded onto the tick away from the contra side (midpoint peg); other pegged
+// orders are left out so pegs don't follow each other
+fn peg_price(book: &Book, order_type: OrderType, side: Side, instrument: &Instrument) -> Option<Decimal> {
+    match order_type {
+        OrderType::PrimaryPeg => book.best_unpegged(side),
+        OrderType::MidpointPeg => {
+            let mid: Decimal = (book.best_unpegged(Side::Buy)? + book.best_unpegged(Side::Sell)?) / Decimal::TWO;
+            let tick: Decimal = instrument.price_increment();
+            let price: Decimal = match side {
+                Side::Buy => (mid / tick).floor() * tick,
+                Side::Sell => (mid / tick).ceil() * tick,
+            };
+            (price > Decimal::ZERO).then(|| price.normalize())
+        }
+        _ => None,
+    }
+}
+
+// Market-to-limit orders take the best contra price as their limit price and pegged orders the
+// price of their peg; without such a price in the book they are rejected
+fn apply_order_price(book: &Book, order: &mut NewOrder, instrument: &Instrument) -> Result<(), String> {
+    let price: Option<Decimal> = match order.order_type {
+        OrderType::MarketToLimit if order.side == Side::Buy => book.best_ask(),
+        OrderType::MarketToLimit => book.best_bid(),
+        order_type if order_type.is_pegged() => peg_price(book, order_type, order.side, instrument),
+        _ => return Ok(()),
+    };
+    let Some(price) = price else {
+        return Err(
+            if order.order_type == OrderType::MarketToLimit {
+                "market_to_limit order has no liquidity to execute against".to_string()
+            } else {
+                format!("{} order has no price to follow in the book", order.order_type)
+            }
+        );
+    };
+    order.price = price;
+    // Checked like the price of a limit order on arrival
+    if instrument.max_price > Decimal::ZERO && price > instrument.max_price {
+        return Err(format!("price {} is above the maximum of {}", price, instrument.max_price));
+    }
+    check_notional(price, order.volume, instrument)
+}
+
+// Move pegged orders to the current price of their peg once the best bid or ask changed; a moved
+// order goes to the back of its new level and may match there, pegs without a price to follow stay
+// put
+fn reprice_pegged_orders(
+    book: &mut Book,
+    pair: &str,
+    trade_books: &mut HashMap<String, TradeBook>,
+    instrument: &Instrument
+) {
+    if !book.update_peg_quotes() {
+        return;
+    }
+    for order_id in book.pegged_orders() {
+        // A peg repriced before may have matched this one already
+        let Some(&Order { order_type, side, price: current, .. }) = book.get(&order_id) else {
+            continue;
+        };
+        let Some(price) = peg_price(book, order_type, side, instrument) else {
+            continue;
+        };
+        if price == current {
+            continue;
+        }
+        let Some(order) = book.remove(&order_id) else {
+            continue;
+        };
+        info!("Pegged order {} moved from {} to {}", order.id, order.price, price);
+        let self_trade_prevention: SelfTradePrevention = placed_order(trade_books, &order.trader, order.id)
+            .map(|record| record.self_trade_prevention)
+            .unwrap_or_default();
+        if let Some(record) = order_record(trade_books, &order.trader, order.id) {
+            record.reprice(price, Utc::now().to_rfc3339());
+        }
+        let repriced: NewOrder = NewOrder {
+            pair: pair.to_string(),
+            trader: order.trader.clone(),
+            side: order.side,
+            order_type: order.order_type,
+            price,
+            volume: order.total_volume(),
+            expire_time: order.expire_time,
+            client_order_id: order.client_order_id.clone(),
+            self_trade_prevention,
+            ..Default::default()
+        };
+        execute_order(book, trade_books, &repriced, order.id, instrument);
+    }
+}
+
+// Price the protections of an incoming order are measured from: the last trade, or the best contra
+// price before the pair has traded
+fn protection_reference(book: &Book, side: Side) -> Option<Decimal> {
+    book.last_price().or(match side {
+        Side::Buy => book.best_ask(),
+        Side::Sell => book.best_bid(),
+    })
+}
+
+// Market orders that would execute beyond the price band around the reference price are rejected as
+// a whole, limit (and market-to-limit) orders priced beyond the collar on the crossing side too (zero
+// disables either)
+fn check_price_protection(book: &Book, order: &NewOrder, instrument: &Instrument) -> Result<(), String> {
+    let Some(reference) = protection_reference(book, order.side) else {
+        return Ok(());
+    };
+    let beyond = |price: Decimal, percent: Decimal| -> bool {
+        let limit: Decimal = reference * percent / Decimal::ONE_HUNDRED;
+        match order.side {
+            Side::Buy => price > reference + limit,
+            Side::Sell => price < reference - limit,
+        }
+    };
+
+    if order.order_type == OrderType::Market && instrument.price_band > Decimal::ZERO {
+        // Walk the contra side as far as the order's volume would take it
+        let contra_orders: Box<dyn Iterator<Item = &Order>> = if order.side == Side::Buy {
+            Box::new(book.asks())
+        } else {
+            Box::new(book.bids())
+        };
+        let mut volume: Decimal = Decimal::ZERO;
+        for resting in contra_orders {
+            if beyond(resting.price, instrument.price_band) {
+                return Err(
+                    format!(
+                        "market order would execute at {}, beyond the {}% price band around {}",
+                        resting.price.normalize(),
+                        instrument.price_band.normalize(),
+                        reference.normalize()
+                    )
+                );
+            }
+            volume += resting.total_volume();
+            if volume >= order.volume {
+                break;
+            }
+        }
+    }
+
+    let has_limit_price: bool = matches!(order.order_type, OrderType::Limit | OrderType::MarketToLimit);
+    if has_limit_price && instrument.limit_collar > Decimal::ZERO && beyond(order.price, instrument.limit_collar) {
+        return Err(
+            format!(
+                "limit price {} is beyond the {}% collar around {}",
+                order.price.normalize(),
+                instrument.limit_collar.normalize(),
+                reference.normalize()
+            )
+        );
+    }
+    Ok(())
+}
+
+// Cancel volume of a resting order prevented from trading with its own trader's order; the hidden
+// reserve of an iceberg goes first, the displayed slice keeps its place
+fn stp_cancel_resting(
+    book: &mut Book,
+    trade_books: &mut HashMap<String, TradeBook>,
+    trader: &str,
+    order_id: Uuid,
+    volume: Decimal,
+    timestamp: &str
+) {
+    if volume.is_zero() {
+        return;
+    }
+    if let Some(order) = book.get_mut(&order_id) {
+        let from_reserve: Decimal = volume.min(order.hidden_volume);
+        order.hidden_volume -= from_reserve;
+        order.volume -= volume - from_reserve;
+        if order.volume <= Decimal::ZERO {
+            book.remove(&order_id);
+        }
+    }
+    if let Some(record) = order_record(trade_books, trader, order_id) {
+        record.stp_cancel(volume, timestamp.to_string());
+    }
+}
+
+// Whether matching the incoming order against a resting order would be prevented as a self-trade
+fn is_self_trade(incoming: &NewOrder, resting: &Order) -> bool {
+    incoming.self_trade_prevention != SelfTradePrevention::None &&
+        !resting.trader.is_empty() &&
+        resting.trader == incoming.trader
+}
+
+// Volumes canceled from the incoming and the resting order when they would trade with each other
+fn self_trade_cancellation(
+    mode: SelfTradePrevention,
+    incoming_volume: Decimal,
+    resting_volume: Decimal
+) -> (Decimal, Decimal) {
+    match mode {
+        SelfTradePrevention::None => (Decimal::ZERO, Decimal::ZERO),
+        SelfTradePrevention::CancelNewest => (incoming_volume, Decimal::ZERO),
+        SelfTradePrevention::CancelOldest => (Decimal::ZERO, resting_volume),
+        SelfTradePrevention::CancelBoth => (incoming_volume, resting_volume),
+        SelfTradePrevention::DecrementAndCancel => {
+            let decrement: Decimal = incoming_volume.min(resting_volume);
+            (decrement, decrement)
+        }
+    }
+}
+
+// Match an incoming order against the book in price-time priority, record executions and status
+// changes in the ledgers and rest the unmatched part of a limit order; returns the volume left
+// unmatched
+fn execute_order(
+    book: &mut Book,
+    trade_books: &mut HashMap<String, TradeBook>,
+    market_order: &NewOrder,
+    order_id: Uuid,
+    instrument: &Instrument
+) -> Decimal {
+    let pair: &String = &market_order.pair;
+    let mut remaining_volume: Decimal = market_order.volume;
+
+    // Match buy order with ask orders and sell order with bid orders
+    let contra_side: Side = market_order.side.opposite();
+    if market_order.order_type.is_stop() {
+        return remaining_volume;
+    }
+
+    // Limit orders only match while the contra price crosses the limit price, and during a call
+    // auction they only rest
+    let in_auction: bool = book.auction().is_some();
+    let crosses = |price: Decimal| -> bool {
+        match (market_order.order_type, contra_side) {
+            _ if in_auction => false,
+            (OrderType::Market, _) => true,
+            (_, Side::Sell) => market_order.price >= price,
+            _ => market_order.price <= price,
+        }
+    };
+
+    // Fill-or-kill: only match when the whole volume is available at acceptable prices
+    if market_order.time_in_force == TimeInForce::Fok {
+        let contra_orders: Box<dyn Iterator<Item = &Order>> = if contra_side == Side::Sell {
+            Box::new(book.asks())
+        } else {
+            Box::new(book.bids())
+        };
+        // Own orders never fill it when self-trade prevention is on, and both legs of an order group
+        // together fill no more than the group's volume
+        let mut available_volume: Decimal = Decimal::ZERO;
+        let mut group_capacity: HashMap<Uuid, Decimal> = HashMap::new();
+        for order in contra_orders
+            .take_while(|o| crosses(o.price))
+            .filter(|o| !is_self_trade(market_order, o)) {
+            let mut volume: Decimal = order.total_volume(); // hidden iceberg reserves can be taken too
+            if let Some((group_id, capacity)) = group_leg_capacity(trade_books, &order.trader, order.id) {
+                let capacity: &mut Decimal = group_capacity.entry(group_id).or_insert(capacity);
+                volume = volume.min(*capacity);
+                *capacity -= volume;
+            }
+            available_volume += volume;
+            if available_volume >= remaining_volume {
+                break;
+            }
+        }
+        if available_volume < remaining_volume {
+            println!("Fill-or-kill order could not be fully matched, available volume: {}", available_volume);
+            if let Some(record) = order_record(trade_books, &market_order.trader, order_id) {
+                record.close(OrderStatus::Canceled, Utc::now().to_rfc3339());
+            }
+            return remaining_volume;
+        }
+    }
+
+    while remaining_volume > Decimal::ZERO {
+        let Some(order) = book.best_mut(contra_side) else {
+            break;
+        };
+        if !crosses(order.price) {
+            break;
+        }
+
+        // An exit leg whose sibling used up the group volume is done straight away, so one incoming
+        // order can't fill both legs of an OCO pair
+        let capacity: Option<Decimal> = group_leg_capacity(trade_books, &order.trader, order.id).map(|(_, capacity)| capacity);
+        if capacity.is_some_and(|capacity| capacity.is_zero()) {
+            let (leg_id, leg_trader): (Uuid, String) = (order.id, order.trader.clone());
+            book.remove(&leg_id);
+            info!("Canceled group leg: {}", leg_id);
+            if let Some(record) = order_record(trade_books, &leg_trader, leg_id) {
+                record.close(OrderStatus::Canceled, Utc::now().to_rfc3339());
+            }
+            continue;
+        }
+
+        // Self-trade prevention cancels volume instead of matching it, then matching goes on with
+        // whatever is left
+        if is_self_trade(market_order, order) {
+            let (incoming_canceled, resting_canceled): (Decimal, Decimal) = self_trade_cancellation(
+                market_order.self_trade_prevention,
+                remaining_volume,
+                order.total_volume()
+            );
+            println!(
+                "Self-trade prevented ({:?}) against order {}: canceled incoming {}, resting {}",
+                market_order.self_trade_prevention,
+                order.id,
+                incoming_canceled,
+                resting_canceled
+            );
+            let resting_id: Uuid = order.id;
+            let timestamp: String = Utc::now().to_rfc3339();
+            remaining_volume -= incoming_canceled;
+            stp_cancel_resting(book, trade_books, &market_order.trader, resting_id, resting_canceled, &timestamp);
+            if !incoming_canceled.is_zero() {
+                if let Some(record) = order_record(trade_books, &market_order.trader, order_id) {
+                    record.stp_cancel(incoming_canceled, timestamp);
+                }
+            }
+            continue;
+        }
+
+        let matched_volume: Decimal = order.volume.min(remaining_volume).min(capacity.unwrap_or(Decimal::MAX));
+        println!(
+            "Matched order: price: {}, volume: {}, side: {}, timestamp: {}, order_type: {}, id: {}",
+            order.price,
+            order.volume,
+            order.side.book_side(),
+            order.timestamp,
+            order.order_type,
+            order.id
+        );
+        order.volume -= matched_volume;
+        remaining_volume -= matched_volume;
+        let trade_price: Decimal = order.price;
+
+        let filled: bool = order.volume <= Decimal::ZERO;
+        if filled {
+            println!("Order fully matched and removed: {:?}", order);
+        } else {
+            println!("Order partially matched, remaining volume updated: {:?}", order);
+        }
+
+        let fill: Fill = Fill {
+            id: Uuid::new_v4(),
+            pair: pair.clone(),
+            price: trade_price,
+            volume: matched_volume,
+            aggressor_side: market_order.side,
+            maker_order_id: order.id,
+            taker_order_id: order_id,
+            timestamp: Utc::now().to_rfc3339(),
+            maker_client_order_id: order.client_order_id.clone(),
+            taker_client_order_id: market_order.client_order_id.clone(),
+        };
+        let maker_trader: String = order.trader.clone();
+        if filled {
+            remove_filled_order(book, fill.maker_order_id, &fill.timestamp);
+        }
+        record_execution(trade_books, &fill, &market_order.trader, &maker_trader, instrument);
+        book.record_fill(fill);
+    }
+
+    if remaining_volume > Decimal::ZERO {
+        if market_order.order_type == OrderType::Market {
+            // The unmatched part of a market order is dropped
+            println!("Market order could not be fully matched, remaining volume: {}", remaining_volume);
+            if let Some(record) = order_record(trade_books, &market_order.trader, order_id) {
+                record.close(OrderStatus::Canceled, Utc::now().to_rfc3339());
+            }
+        } else if market_order.time_in_force == TimeInForce::Ioc {
+            // Immediate-or-cancel: the unmatched part never rests in the book
+            println!("Immediate-or-cancel order canceled, remaining volume: {}", remaining_volume);
+            if let Some(record) = order_record(trade_books, &market_order.trader, order_id) {
+                record.close(OrderStatus::Canceled, Utc::now().to_rfc3339());
+            }
+        } else {
+            // Iceberg orders show one slice and keep the rest as a hidden reserve
+            let displayed: Decimal = if market_order.display_volume > Decimal::ZERO {
+                remaining_volume.min(market_order.display_volume)
+            } else {
+                remaining_volume
+            };
+            // Market-to-limit orders rest as limit orders at the price they executed at, pegged
+            // orders keep following the book
+            let new_order: Order = Order {
+                id: order_id,
+                trader: market_order.trader.clone(),
+                price: market_order.price, // Limit order retains the specified price
+                volume: displayed,
+                original_volume: market_order.volume,
+                display_volume: market_order.display_volume,
+                hidden_volume: remaining_volume - displayed,
+                side: market_order.side,
+                timestamp: Utc::now().to_rfc3339(),
+                order_type: if market_order.order_type.is_pegged() { market_order.order_type } else { OrderType::Limit },
+                expire_time: market_order.expire_time,
+                client_order_id: market_order.client_order_id.clone(),
+            };
+            println!("Limit order added to order book: {:?}", new_order);
+            book.insert(new_order);
+
+            // Replaced orders get their status from replace_order
+            if let Some(record) = order_record(trade_books, &market_order.trader, order_id) {
+                if matches!(record.status, OrderStatus::New | OrderStatus::Triggered) {
+                    record.transition(OrderStatus::Open, Utc::now().to_rfc3339());
+                }
+            }
+        }
+    }
+
+    remaining_volume
+}
+
+// Group and volume an exit leg of an active order group may still fill: the group volume less the
+// fills of both exits; None for orders that aren't exit legs
+fn group_leg_capacity(
+    trade_books: &HashMap<String, TradeBook>,
+    trader: &str,
+    order_id: Uuid
+) -> Option<(Uuid, Decimal)> {
+    let trade_book: &TradeBook = trade_books.get(trader)?;
+    let group: &OrderGroup = trade_book.group(&trade_book.order(&order_id)?.group_id?)?;
+    if group.status != GroupStatus::Active || group.exits().iter().all(|leg| leg.id != order_id) {
+        return None;
+    }
+    let filled_volume: Decimal = group
+        .exits()
+        .iter()
+        .filter_map(|leg| trade_book.order(&leg.id))
+        .map(|record| record.filled_volume)
+        .sum();
+    Some((group.id, (group.volume - filled_volume).max(Decimal::ZERO)))
+}
+
+// Take a filled order out of the book; a filled iceberg slice is replenished from the reserve at the
+// back of its level instead
+fn remove_filled_order(book: &mut Book, order_id: Uuid, timestamp: &str) {
+    if let Some(mut iceberg) = book.remove(&order_id) {
+        if iceberg.replenish() {
+            println!("Iceberg order replenished: {:?}", iceberg);
+            iceberg.timestamp = timestamp.to_string();
+            book.insert(iceberg);
+        }
+    }
+}
+
+// Record an execution in the fills and order ledgers of both counterparties; orders without an owner
+// are Kraken liquidity and show up as the external counterparty only
+fn record_execution(
+    trade_books: &mut HashMap<String, TradeBook>,
+    fill: &Fill,
+    taker_trader: &str,
+    maker_trader: &str,
+    instrument: &Instrument
+) {
+    let counterparty = |trader: &str| -> String {
+        if trader.is_empty() { EXTERNAL_TRADER.to_string() } else { trader.to_string() }
+    };
+    let taker_execution: Execution = Execution {
+        id: fill.id,
+        pair: fill.pair.clone(),
+        order_id: fill.taker_order_id,
+        client_order_id: fill.taker_client_order_id.clone(),
+        side: fill.aggressor_side,
+        price: fill.price,
+        volume: fill.volume,
+        fee: fill_fee(fill, instrument.taker_fee),
+        liquidity: Liquidity::Taker,
+        aggressor_side: fill.aggressor_side,
+        taker_trader: counterparty(taker_trader),
+        taker_order_id: fill.taker_order_id,
+        maker_trader: counterparty(maker_trader),
+        maker_order_id: fill.maker_order_id,
+        timestamp: fill.timestamp.clone(),
+    };
+    if !maker_trader.is_empty() {
+        let maker_execution: Execution = Execution {
+            order_id: fill.maker_order_id,
+            client_order_id: fill.maker_client_order_id.clone(),
+            side: fill.aggressor_side.opposite(),
+            fee: fill_fee(fill, instrument.maker_fee),
+            liquidity: Liquidity::Maker,
+            ..taker_execution.clone()
+        };
+        trade_books.entry(maker_trader.to_string()).or_default().add_fill(maker_execution);
+    }
+    if !taker_trader.is_empty() {
+        trade_books.entry(taker_trader.to_string()).or_default().add_fill(taker_execution);
+    }
+
+    for (trader, order_id) in [(taker_trader, fill.taker_order_id), (maker_trader, fill.maker_order_id)] {
+        if let Some(record) = order_record(trade_books, trader, order_id) {
+            record.fill(fill.volume, fill.timestamp.clone());
+        }
+    }
 }
 
-// Function to persist the order book to a CSV file (for testing and development purposes)
-async fn persist_order_book(
-    order_books: &HashMap<String, Vec<Order>>,
-    pair: &str,
-    include_timestamp: bool,
-    sort_orders: bool
-) -> Result<(), Box<dyn Error>> {
-    let config: Config = load_config().unwrap();
-
-    let timestamp: String = if include_timestamp {
-        format!("_{}", Utc::now().format("%Y%m%d%H%M%S%6f").to_string())
-    } else {
-        String::new()
-    };
+// Fee of a fill at the given rate; the rate (below 1) is applied to the price first so the fee of any
+// fill the engine accepted can be computed, saturating instead of overflowing
+fn fill_fee(fill: &Fill, rate: Decimal) -> Decimal {
+    fill.price
+        .checked_mul(rate)
+        .and_then(|fee| fee.checked_mul(fill.volume))
+        .unwrap_or(Decimal::MAX)
+        .normalize()
+}
 
-    let file_path: String = format!(
-        "{}/{}_order_book{}.csv",
-        config.kraken.persist,
-        pair,
-        timestamp
-    );
-    let mut wtr: Writer<File> = Writer::from_writer(File::create(&file_path)?);
+// Reference price a stop order of the given side is triggered (and trailed) by
+fn reference_price(book: &Book, side: Side, stop_trigger: StopTrigger) -> Option<Decimal> {
+    match stop_trigger {
+        StopTrigger::LastTrade => book.last_price(),
+        StopTrigger::BestQuote if side == Side::Buy => book.best_ask(),
+        StopTrigger::BestQuote => book.best_bid(),
+    }
+}
 
-    if let Some(orders) = order_books.get(pair) {
-        let mut orders_to_write: Vec<Order> = orders.clone();
-
-        if sort_orders {
-            let mut asks: Vec<Order> = orders
-                .iter()
-                .filter(|o| o.side == "ask")
-                .cloned()
-                .collect::<Vec<Order>>();
-            let mut bids = orders
-                .iter()
-                .filter(|o| o.side == "bid")
-                .cloned()
-                .collect::<Vec<Order>>();
-            asks.sort_by(|a, b| b.price.cmp(&a.price));
-            bids.sort_by(|a, b| b.price.cmp(&a.price));
-            orders_to_write = Vec::new();
-            orders_to_write.extend(asks);
-            orders_to_write.extend(bids);
-        }
-
-        for order in orders_to_write {
-            wtr.serialize(order)?;
-        }
-        wtr.flush()?;
+// Check whether the reference price has reached the stop price of a stop order; trailing stops
+// without a stop price yet (no reference price so far) are not triggered
+fn is_stop_triggered(book: &Book, stop: &NewOrder, stop_trigger: StopTrigger) -> bool {
+    if stop.order_type == OrderType::TrailingStop && stop.stop_price.is_zero() {
+        return false;
+    }
+    match (reference_price(book, stop.side, stop_trigger), stop.side) {
+        (Some(price), Side::Buy) => price >= stop.stop_price,
+        (Some(price), Side::Sell) => price <= stop.stop_price,
+        (None, _) => false,
     }
-    Ok(())
 }
 
-// Function to update order books in a loop (TODO: add error handling when not able to fetch order books, move sleep duration to config)
-async fn update_order_books(service: Arc<OrderBookService>, pairs: Vec<&str>, offline_mode: bool) {
-    if offline_mode {
-        println!("Offline mode: Skipping API fetch.\n");
-        return;
+// New stop price of a trailing stop when the reference price moved in its favour: sell stops only
+// move up, buy stops only move down, rounded away from the reference price onto the tick
+fn trail_stop_price(
+    book: &Book,
+    stop: &NewOrder,
+    stop_trigger: StopTrigger,
+    instrument: &Instrument
+) -> Option<Decimal> {
+    let offset: TrailingOffset = stop.trailing_offset?;
+    let reference: Decimal = reference_price(book, stop.side, stop_trigger)?;
+    let tick: Decimal = instrument.price_increment();
+    let stop_price: Decimal = match stop.side {
+        Side::Sell => ((reference - offset.amount(reference)) / tick).floor() * tick,
+        Side::Buy => ((reference + offset.amount(reference)) / tick).ceil() * tick,
+    };
+    let moved: bool = match stop.side {
+        _ if stop.stop_price.is_zero() => true,
+        Side::Sell => stop_price > stop.stop_price,
+        Side::Buy => stop_price < stop.stop_price,
+    };
+    (moved && stop_price > Decimal::ZERO).then(|| stop_price.normalize())
+}
+
+// Move the stop prices of trailing stops after the reference price changed (trades or a book
+// refresh); a sweep moves the last price monotonically, so trailing on its final price is enough
+fn trail_stop_orders(
+    book: &Book,
+    stop_orders: &mut [PendingOrder],
+    trade_books: &mut HashMap<String, TradeBook>,
+    stop_trigger: StopTrigger,
+    instrument: &Instrument
+) {
+    for PendingOrder { id: order_id, request: stop } in stop_orders.iter_mut() {
+        let Some(stop_price) = trail_stop_price(book, stop, stop_trigger, instrument) else {
+            continue;
+        };
+        info!("Trailing stop {} moved from {} to {}", order_id, stop.stop_price, stop_price);
+        stop.stop_price = stop_price;
+        if let Some(record) = order_record(trade_books, &stop.trader, *order_id) {
+            record.trail(stop_price, Utc::now().to_rfc3339());
+        }
     }
+}
 
+// Release triggered stop orders into matching (stop and trailing stop as market, stop_limit as
+// limit order); repeat while the released orders move the reference price far enough to trigger
+// further stops, settling order groups, repricing pegged orders and trailing the stop prices before
+// each check. Stops and pegs wait while the pair is halted or in a call auction.
+fn trigger_stop_orders(
+    book: &mut Book,
+    pair: &str,
+    stop_orders: &mut Vec<PendingOrder>,
+    trade_books: &mut HashMap<String, TradeBook>,
+    stop_trigger: StopTrigger,
+    instrument: &Instrument
+) {
     loop {
-        let fetches = pairs.iter().map(|pair| {
-            let pair: String = pair.to_string();
-            async move {
-                let orders = fetch_order_book(&pair).await.unwrap_or_else(|_| vec![]);
-                (pair, orders)
-            }
-        });
-        let results: Vec<(String, Vec<Order>)> = join_all(fetches).await;
-
-        // Update order_books outside the loop to minimize lock time
-        let mut new_order_books: HashMap<String, Vec<Order>> = HashMap::new();
-        for (pair, orders) in results {
-            new_order_books.insert(pair, orders);
+        settle_order_groups(book, stop_orders, trade_books, stop_trigger, instrument);
+        if book.auction().is_some() || book.halted().is_some() {
+            break;
         }
+        reprice_pegged_orders(book, pair, trade_books, instrument);
+        trail_stop_orders(book, stop_orders, trade_books, stop_trigger, instrument);
+        let Some(pos) = stop_orders
+            .iter()
+            .position(|stop| is_stop_triggered(book, &stop.request, stop_trigger)) else {
+            break;
+        };
+        let PendingOrder { id: order_id, request: stop } = stop_orders.remove(pos);
+        println!("Stop order triggered at stop price {}: {}", stop.stop_price, order_id);
 
-        {
-            let mut order_books: tokio::sync::MutexGuard<
-                HashMap<String, Vec<Order>>
-            > = service.order_books.lock().await;
-            *order_books = new_order_books;
+        if let Some(record) = order_record(trade_books, &stop.trader, order_id) {
+            record.transition(OrderStatus::Triggered, Utc::now().to_rfc3339());
         }
 
-        // Persist the order book after updating
-        for pair in &pairs {
-            let new_order_books: tokio::sync::MutexGuard<
-                HashMap<String, Vec<Order>>
-            > = service.order_books.lock().await;
-            if let Err(e) = persist_order_book(&new_order_books, pair, false, false).await {
-                eprintln!("Failed to persist order book: {}", e);
+        let released: NewOrder = NewOrder {
+            order_type: if stop.order_type == OrderType::StopLimit { OrderType::Limit } else { OrderType::Market },
+            ..stop
+        };
+        // The released order gets the same price protection as one placed directly
+        if let Err(reason) = check_price_protection(book, &released, instrument) {
+            println!("Triggered stop order {} rejected: {}", order_id, reason);
+            if let Some(record) = order_record(trade_books, &released.trader, order_id) {
+                record.reject(&reason, Utc::now().to_rfc3339());
             }
+            continue;
+        }
+        execute_order(book, trade_books, &released, order_id, instrument);
+    }
+}
+
+// Check the post-only and reduce-only flags of a recorded order, then hand it to matching (stop
+// orders go to the trigger book); a failed check rejects the order in the ledger
+fn submit_order(
+    book: &mut Book,
+    stop_orders: &mut Vec<PendingOrder>,
+    trade_books: &mut HashMap<String, TradeBook>,
+    order_id: Uuid,
+    mut order: NewOrder,
+    stop_trigger: StopTrigger,
+    instrument: &Instrument
+) -> Result<(), String> {
+    let position: Decimal = trade_books
+        .get(&order.trader)
+        .map(|trade_book| trade_book.position(&order.pair))
+        .unwrap_or_default();
+    let flags_checked: Result<(), String> = check_trading_phase(book, &order)
+        .and_then(|()| apply_order_price(book, &mut order, instrument))
+        .and_then(|()| apply_order_flags(book, position, &mut order, instrument))
+        .and_then(|()| check_price_protection(book, &order, instrument));
+    if let Some(record) = order_record(trade_books, &order.trader, order_id) {
+        match &flags_checked {
+            Ok(()) => record.price = order.price, // set by the engine or repriced for post-only orders
+            Err(reason) => record.reject(reason, Utc::now().to_rfc3339()),
         }
+    }
+    flags_checked?;
 
-        // Sleep for 10 seconds before fetching order books again (maybe use Tokio timer instead of sleep)
-        sleep(Duration::from_secs(10)).await;
+    if order.order_type.is_stop() {
+        // Stop orders wait in the trigger book until the stop price is reached; trailing stops start
+        // at the offset from the current reference price
+        if let Some(stop_price) = trail_stop_price(book, &order, stop_trigger, instrument) {
+            order.stop_price = stop_price;
+        }
+        if let Some(record) = order_record(trade_books, &order.trader, order_id) {
+            record.stop_price = order.stop_price;
+            record.transition(OrderStatus::Pending, Utc::now().to_rfc3339());
+        }
+        stop_orders.push(PendingOrder { id: order_id, request: order });
+    } else {
+        execute_order(book, trade_books, &order, order_id, instrument);
     }
+    Ok(())
 }
 
-// Helper function to parse orders from JSON array
-fn parse_orders(data: &Value, side: &str, timestamp: &str) -> Vec<Order> {
-    data.as_array()
-        .unwrap_or(&vec![])
-        .iter()
-        .map(|order| {
-            let price = order[0].as_str().unwrap().parse::<f64>().unwrap();
-            let volume = order[1].as_str().unwrap().parse::<f64>().unwrap();
-            Order {
-                id: Uuid::new_v4(),
-                price: OrderedFloat(price),
-                volume: OrderedFloat(volume),
-                side: side.to_string(),
-                timestamp: timestamp.to_string(),
-                order_type: "limit".to_string(),
+// Keep the order groups of a pair consistent after matching, cancels and expiries; repeats while
+// placing legs changes the book
+fn settle_order_groups(
+    book: &mut Book,
+    stop_orders: &mut Vec<PendingOrder>,
+    trade_books: &mut HashMap<String, TradeBook>,
+    stop_trigger: StopTrigger,
+    instrument: &Instrument
+) {
+    loop {
+        let mut changed: bool = false;
+        for (trader, group_id) in book.working_groups() {
+            changed |= settle_order_group(book, stop_orders, trade_books, &trader, group_id, stop_trigger, instrument);
+            // Groups ended here or elsewhere (cancel-all, purge) leave the index
+            let working: bool = trade_books
+                .get(&trader)
+                .and_then(|trade_book| trade_book.group(&group_id))
+                .is_some_and(|group| group.is_working());
+            if !working {
+                book.end_group(&trader, group_id);
             }
-        })
-        .collect()
+        }
+        if !changed {
+            break;
+        }
+    }
 }
 
-// Fetch the order book for a given trading pair from Kraken API and return a vector of Order structs
-async fn fetch_order_book(pair: &str) -> Result<Vec<Order>, reqwest::Error> {
-    let url: String = format!("{}/?pair={}", "https://api.kraken.com/0/public/Depth", pair);
-    let response: Value = reqwest::get(&url).await?.json::<Value>().await?;
+// Bracket exits are placed with the entry's filled volume once the entry is done (canceled when it
+// never filled). Exit legs share the group volume: fills of one leg reduce the other, and once a
+// leg ends the other is canceled. Returns whether anything changed.
+fn settle_order_group(
+    book: &mut Book,
+    stop_orders: &mut Vec<PendingOrder>,
+    trade_books: &mut HashMap<String, TradeBook>,
+    trader: &str,
+    group_id: Uuid,
+    stop_trigger: StopTrigger,
+    instrument: &Instrument
+) -> bool {
+    let Some(group) = trade_books.get(trader).and_then(|trade_book| trade_book.group(&group_id)).cloned() else {
+        return false;
+    };
     let timestamp: String = Utc::now().to_rfc3339();
 
-    let asks: Vec<Order> = parse_orders(&response["result"][pair]["asks"], "ask", &timestamp);
-    let bids: Vec<Order> = parse_orders(&response["result"][pair]["bids"], "bid", &timestamp);
+    if group.status == GroupStatus::Waiting {
+        let Some(entry) = group.entry.and_then(|id| placed_order(trade_books, trader, id)).cloned() else {
+            return false;
+        };
+        if entry.open_volume > Decimal::ZERO {
+            return false;
+        }
+        let volume: Decimal = entry.filled_volume;
+        for leg in group.exits() {
+            if let Some(record) = order_record(trade_books, trader, leg.id) {
+                if volume.is_zero() {
+                    record.close(OrderStatus::Canceled, timestamp.clone());
+                } else {
+                    record.reduce(volume, timestamp.clone());
+                }
+            }
+        }
+        if let Some(group) = trade_books.get_mut(trader).and_then(|trade_book| trade_book.group_mut(&group_id)) {
+            group.volume = volume;
+            group.status = if volume.is_zero() { GroupStatus::Canceled } else { GroupStatus::Active };
+            info!("Bracket entry of group {} done, exits: {}", group.id, group.status);
+        }
+        return true;
+    }
 
-    // Combine asks and bids into a single vector of orders sorted by price
-    let mut orders: Vec<Order> = Vec::new();
-    orders.extend(asks);
-    orders.extend(bids);
+    let legs: Vec<(&GroupLeg, OrderRecord)> = group
+        .exits()
+        .into_iter()
+        .filter_map(|leg| Some((leg, placed_order(trade_books, trader, leg.id)?.clone())))
+        .collect();
+    let filled_volume: Decimal = legs
+        .iter()
+        .map(|(_, record)| record.filled_volume)
+        .sum();
+    let open_volume: Decimal = if legs.iter().any(|(_, record)| record.open_volume.is_zero()) {
+        Decimal::ZERO
+    } else {
+        group.volume - filled_volume
+    };
 
-    // Sort the orders by price
-    orders.sort_by(|a, b| b.price.cmp(&a.price));
+    // One change at a time, a placed leg may fill straight away
+    for (leg, record) in &legs {
+        if record.open_volume > open_volume {
+            reduce_group_leg(book, stop_orders, trade_books, trader, leg.id, open_volume);
+            return true;
+        }
+        if record.status == OrderStatus::New {
+            // Legs not placed yet go to matching with what is left of the group volume
+            let order: NewOrder = NewOrder { volume: record.open_volume, ..leg.order.clone() };
+            if let Err(reason) = submit_order(book, stop_orders, trade_books, leg.id, order, stop_trigger, instrument) {
+                info!("Rejected leg {} of group {}: {}", leg.id, group.id, reason);
+            }
+            return true;
+        }
+    }
 
-    Ok(orders)
+    if !legs.iter().all(|(_, record)| record.open_volume.is_zero()) {
+        return false;
+    }
+    if let Some(group) = trade_books.get_mut(trader).and_then(|trade_book| trade_book.group_mut(&group_id)) {
+        group.status = if filled_volume.is_zero() { GroupStatus::Canceled } else { GroupStatus::Completed };
+        info!("Order group {} {}", group.id, group.status);
+    }
+    true
 }
 
-// Fetch initial order books for the given trading pairs in parallel
-async fn fetch_order_books(pairs: Vec<&str>) -> HashMap<String, Vec<Order>> {
-    let fetches = pairs.iter().map(|pair| {
-        let pair: String = pair.to_string();
-        async move {
-            let orders = fetch_order_book(&pair).await.unwrap_or_else(|_| vec![]);
-            (pair, orders)
+// Cut an exit leg down to the open volume left in its group, canceling it at zero
+fn reduce_group_leg(
+    book: &mut Book,
+    stop_orders: &mut Vec<PendingOrder>,
+    trade_books: &mut HashMap<String, TradeBook>,
+    trader: &str,
+    order_id: Uuid,
+    open_volume: Decimal
+) {
+    let timestamp: String = Utc::now().to_rfc3339();
+    if open_volume.is_zero() {
+        book.remove(&order_id);
+        stop_orders.retain(|stop| stop.id != order_id);
+        info!("Canceled group leg: {}", order_id);
+        if let Some(record) = order_record(trade_books, trader, order_id) {
+            record.close(OrderStatus::Canceled, timestamp);
         }
-    });
-    let results: Vec<(String, Vec<Order>)> = join_all(fetches).await;
-
-    results.into_iter().collect()
+        return;
+    }
+    // An iceberg leg loses hidden reserve before displayed volume
+    if let Some(order) = book.get_mut(&order_id) {
+        order.volume = order.volume.min(open_volume);
+        order.hidden_volume = open_volume - order.volume;
+    }
+    if let Some(stop) = stop_orders.iter_mut().find(|stop| stop.id == order_id) {
+        stop.request.volume = open_volume;
+    }
+    info!("Reduced group leg {} to {}", order_id, open_volume);
+    if let Some(record) = order_record(trade_books, trader, order_id) {
+        record.reduce(open_volume, timestamp);
+    }
 }
 
-// Function to load order book from CSV files
-async fn load_order_book_from_csv(
-    file_paths: Vec<&str>
-) -> Result<HashMap<String, Vec<Order>>, Box<dyn Error>> {
-    let mut order_books: HashMap<String, Vec<Order>> = HashMap::new();
+// End the call auction of a pair: crossing orders execute in price-time priority at the single
+// equilibrium price, the one of each pair of orders that arrived later being the taker (whose
+// self-trade prevention mode applies); continuous trading resumes with the rest of the book. Returns the clearing price and executed volume.
+fn uncross_auction(
+    book: &mut Book,
+    trade_books: &mut HashMap<String, TradeBook>,
+    pair: &str,
+    instrument: &Instrument
+) -> Option<Equilibrium> {
+    let equilibrium: Option<Equilibrium> = book.equilibrium();
+    book.end_auction();
+    let equilibrium: Equilibrium = equilibrium?;
+
+    let arrival = |order: &Order| DateTime::parse_from_rfc3339(&order.timestamp).ok();
+    let mut remaining_volume: Decimal = equilibrium.volume;
+    while remaining_volume > Decimal::ZERO {
+        let (Some(bid), Some(ask)) = (book.best(Side::Buy).cloned(), book.best(Side::Sell).cloned()) else {
+            break;
+        };
+        if bid.price < equilibrium.price || ask.price > equilibrium.price {
+            break;
+        }
+        let (taker, maker): (Order, Order) = if arrival(&bid) > arrival(&ask) { (bid, ask) } else { (ask, bid) };
+
+        // Self-trade prevention with the taker's mode, as in continuous matching
+        let mode: SelfTradePrevention = placed_order(trade_books, &taker.trader, taker.id)
+            .map(|record| record.self_trade_prevention)
+            .unwrap_or_default();
+        if mode != SelfTradePrevention::None && !taker.trader.is_empty() && taker.trader == maker.trader {
+            let (taker_canceled, maker_canceled): (Decimal, Decimal) = self_trade_cancellation(
+                mode,
+                taker.total_volume(),
+                maker.total_volume()
+            );
+            println!(
+                "Self-trade prevented ({:?}) at the uncross between orders {} and {}: canceled {}, {}",
+                mode,
+                taker.id,
+                maker.id,
+                taker_canceled,
+                maker_canceled
+            );
+            let timestamp: String = Utc::now().to_rfc3339();
+            stp_cancel_resting(book, trade_books, &taker.trader, taker.id, taker_canceled, &timestamp);
+            stp_cancel_resting(book, trade_books, &maker.trader, maker.id, maker_canceled, &timestamp);
+            continue;
+        }
 
-    for file_path in file_paths {
-        let mut rdr: csv::Reader<File> = ReaderBuilder::new().from_path(file_path)?;
-        for result in rdr.deserialize::<Order>() {
-            match result {
-                Ok(order) => {
-                    let pair: String = file_path
-                        .split('_')
-                        .next()
-                        .unwrap_or("unknown")
-                        .to_string()
-                        .replace("data/offline/", "");
-                    info!("Loaded order for {}: {}", pair, order);
-                    order_books.entry(pair.clone()).or_insert_with(Vec::new).push(order);
-                }
-                Err(e) => {
-                    println!("Error deserializing order: {}", e);
+        let matched_volume: Decimal = taker.volume.min(maker.volume).min(remaining_volume);
+        remaining_volume -= matched_volume;
+
+        let fill: Fill = Fill {
+            id: Uuid::new_v4(),
+            pair: pair.to_string(),
+            price: equilibrium.price,
+            volume: matched_volume,
+            aggressor_side: taker.side,
+            maker_order_id: maker.id,
+            taker_order_id: taker.id,
+            timestamp: Utc::now().to_rfc3339(),
+            maker_client_order_id: maker.client_order_id.clone(),
+            taker_client_order_id: taker.client_order_id.clone(),
+        };
+        for order in [&taker, &maker] {
+            if order.volume > matched_volume {
+                if let Some(resting) = book.get_mut(&order.id) {
+                    resting.volume -= matched_volume;
                 }
+            } else {
+                remove_filled_order(book, order.id, &fill.timestamp);
             }
         }
+        record_execution(trade_books, &fill, &taker.trader, &maker.trader, instrument);
+        book.record_fill(fill);
     }
+    info!(
+        "Uncrossed call auction for {} at {}, volume: {}",
+        pair,
+        equilibrium.price,
+        equilibrium.volume - remaining_volume
+    );
+    Some(Equilibrium { volume: equilibrium.volume - remaining_volume, ..equilibrium })
+}
 
-    // Sort the orders within each order book
-    for orders in order_books.values_mut() {
-        orders.sort_by(|a, b| {
-            match (a.side.as_str(), b.side.as_str()) {
-                ("ask", "ask") => a.price.cmp(&b.price),
-                ("bid", "bid") => b.price.cmp(&a.price),
-                _ => std::cmp::Ordering::Equal,
+// Cancel every working order of the trader (all traders when None) in the pair (all pairs when None),
+// including bracket exits not placed yet; their working order groups end first so settling doesn't
+// place or resize legs. Returns the canceled order ids.
+fn cancel_open_orders(
+    order_books: &mut HashMap<String, Book>,
+    trade_books: &mut HashMap<String, TradeBook>,
+    stop_books: &mut HashMap<String, Vec<PendingOrder>>,
+    trader: Option<&str>,
+    pair: Option<&str>
+) -> Vec<Uuid> {
+    let timestamp: String = Utc::now().to_rfc3339();
+    let in_scope = |order_pair: &str| pair.is_none_or(|pair| pair == order_pair);
+    let mut canceled: Vec<Uuid> = Vec::new();
+    for (owner, trade_book) in trade_books.iter_mut() {
+        if trader.is_some_and(|trader| trader != owner) {
+            continue;
+        }
+
+        let groups: Vec<OrderGroup> = trade_book
+            .groups()
+            .iter()
+            .filter(|group| group.is_working() && in_scope(&group.pair))
+            .cloned()
+            .collect();
+        for group in groups {
+            let filled: bool = group.entry
+                .into_iter()
+                .chain(group.exits().map(|leg| leg.id))
+                .filter_map(|order_id| trade_book.order(&order_id))
+                .any(|record| record.filled_volume > Decimal::ZERO);
+            if let Some(group) = trade_book.group_mut(&group.id) {
+                group.status = if filled { GroupStatus::Completed } else { GroupStatus::Canceled };
+                info!("Order group {} {}", group.id, group.status);
             }
-        });
-    }
+        }
 
-    Ok(order_books)
+        let open: Vec<(Uuid, String)> = trade_book
+            .orders()
+            .iter()
+            .filter(|record| record.open_volume > Decimal::ZERO && in_scope(&record.pair))
+            .map(|record| (record.id, record.pair.clone()))
+            .collect();
+        for (order_id, order_pair) in open {
+            if let Some(book) = order_books.get_mut(&order_pair) {
+                book.remove(&order_id);
+            }
+            if let Some(stop_orders) = stop_books.get_mut(&order_pair) {
+                stop_orders.retain(|stop| stop.id != order_id);
+            }
+            if let Some(record) = trade_book.order_mut(&order_id) {
+                record.close(OrderStatus::Canceled, timestamp.clone());
+            }
+            info!("Canceled order for {}: {}", owner, order_id);
+            canceled.push(order_id);
+        }
+    }
+    canceled
 }
 
-// Function to process market orders and update the order book (core)
-async fn process_orders(service: Arc<OrderBookService>, mut rx: mpsc::Receiver<OrderRequest>) {
-    while let Some(market_order) = rx.recv().await {
-        let pair = market_order.pair.clone();
+// Remove resting and stop orders whose GTD/DAY expiry has passed and record them as expired
+fn remove_expired_orders(
+    book: &mut Book,
+    stop_orders: &mut Vec<PendingOrder>,
+    trade_books: &mut HashMap<String, TradeBook>,
+    now: DateTime<Utc>
+) {
+    let expired_ids: Vec<Uuid> = book
+        .iter()
+        .filter(|o| o.expire_time.is_some_and(|t| t <= now))
+        .map(|o| o.id)
+        .collect();
+    for order_id in expired_ids {
+        let Some(order) = book.remove(&order_id) else {
+            continue;
+        };
+        info!("Expired order: {}", order);
+        if let Some(record) = order_record(trade_books, &order.trader, order_id) {
+            record.close(OrderStatus::Expired, now.to_rfc3339());
+        }
+    }
 
-        let mut order_books: tokio::sync::MutexGuard<
-            HashMap<String, Vec<Order>>
-        > = service.order_books.lock().await;
-        let mut trade_books: tokio::sync::MutexGuard<
-            HashMap<String, Vec<Trade>>
-        > = service.trade_books.lock().await;
+    let (expired, waiting): (Vec<PendingOrder>, Vec<PendingOrder>) = stop_orders
+        .drain(..)
+        .partition(|stop| stop.request.expire_time.is_some_and(|t| t <= now));
+    *stop_orders = waiting;
+    for PendingOrder { id: order_id, request: stop } in expired {
+        info!("Expired stop order: {}", order_id);
+        if let Some(record) = order_record(trade_books, &stop.trader, order_id) {
+            record.close(OrderStatus::Expired, now.to_rfc3339());
+        }
+    }
+}
 
-        // Record trader in tradebook before processing the trade
+// Function to expire GTD/DAY orders in a loop (checked every second)
+async fn expire_orders(service: Arc<OrderBookService>) {
+    loop {
+        {
+            let mut order_books: tokio::sync::MutexGuard<
+                HashMap<String, Book>
+            > = service.order_books.lock().await;
+            let mut trade_books: tokio::sync::MutexGuard<
+                HashMap<String, TradeBook>
+            > = service.trade_books.lock().await;
+            let mut stop_books: tokio::sync::MutexGuard<
+                HashMap<String, Vec<PendingOrder>>
+            > = service.stop_books.lock().await;
+
+            let now: DateTime<Utc> = Utc::now();
+            for (pair, book) in order_books.iter_mut() {
+                let stop_orders: &mut Vec<PendingOrder> = stop_books.entry(pair.clone()).or_default();
+                remove_expired_orders(book, stop_orders, &mut trade_books, now);
+                // Expired group legs end their groups
+                let instrument: Instrument = service.instrument(pair).unwrap_or_default();
+                trigger_stop_orders(book, pair, stop_orders, &mut trade_books, service.stop_trigger, &instrument);
+                check_volatility(&service, pair, book, &instrument);
+                publish_market_data(&service, pair, book);
+            }
+        }
 
-        let trade: Trade = Trade {
-            id: Uuid::new_v4(),
-            trader: market_order.trader.clone(),
-            pair: market_order.pair,
-            side: market_order.side.clone(),
-            price: market_order.price.into(),
-            volume: market_order.volume.into(),
-            timestamp: Utc::now().to_rfc3339(),
-            order_type: market_order.order_type.clone(),
-            status: "new".to_string(), // First status of the trade
-        };
-        trade_books.entry(market_order.trader.clone()).or_insert_with(Vec::new).push(trade.clone());
+        sleep(Duration::from_secs(1)).await;
+    }
+}
 
-        if let Some(orders) = order_books.get_mut(&pair) {
-            let mut orders: Vec<Order> = orders.clone(); // Work with a local copy of the orders
-            let mut matched_orders: Vec<Order> = vec![];
-            let mut remaining_volume: OrderedFloat<f64> = OrderedFloat(market_order.volume);
-            let mut orders_to_remove: Vec<Order> = vec![];
+// Volatility circuit breaker: halt the pair for the instrument's halt duration when trade prices
+// within its window moved more than halt_move percent, pausing it or moving it into a call auction
+fn check_volatility(service: &OrderBookService, pair: &str, book: &mut Book, instrument: &Instrument) {
+    let now: DateTime<Utc> = Utc::now();
+    // Without a breaker nothing needs to be remembered
+    let window: i64 = if instrument.halt_move > Decimal::ZERO { instrument.halt_window as i64 } else { 0 };
+    let Some((low, high)) = book.price_range(now - chrono::Duration::seconds(window)) else {
+        return;
+    };
+    if instrument.halt_move.is_zero() || book.halted().is_some() || book.auction().is_some() {
+        return;
+    }
+    let moved: Decimal = (high - low) / low * Decimal::ONE_HUNDRED;
+    if moved <= instrument.halt_move {
+        return;
+    }
+    let reason: String = format!(
+        "price moved {}% ({} to {}) within {} seconds",
+        moved.round_dp(2).normalize(),
+        low.normalize(),
+        high.normalize(),
+        instrument.halt_window
+    );
+    let resume_at: DateTime<Utc> = now + chrono::Duration::seconds(instrument.halt_duration as i64);
+    halt_pair(service, pair, book, instrument.halt_mode, resume_at, &reason);
+}
 
-            println!("Processing order for trader: {}", market_order.trader);
+// Halt trading in a pair until the resume time, or collect orders in a call auction until then
+fn halt_pair(
+    service: &OrderBookService,
+    pair: &str,
+    book: &mut Book,
+    mode: HaltMode,
+    resume_at: DateTime<Utc>,
+    reason: &str
+) {
+    match mode {
+        HaltMode::Pause => book.halt(resume_at),
+        HaltMode::Auction => book.start_auction(resume_at),
+    }
+    book.clear_trade_prices();
+    info!("Trading in {} halted ({:?}) until {}: {}", pair, mode, resume_at, reason);
+    let _ = service.status_tx.send(trading_status(pair, book, reason));
+}
 
-            // Sorting for printing
-            orders.sort_by(|a, b| {
-                match (a.side.as_str(), b.side.as_str()) {
-                    ("ask", "ask") => b.price.cmp(&a.price), //descending order
-                    ("bid", "bid") => b.price.cmp(&a.price), //descending order
-                    _ => std::cmp::Ordering::Equal,
-                }
-            });
+// End a pause whose time is over with a re-opening call auction and uncross a call auction once the
+// uncross time is up; stops triggered by the clearing price are released and the results are
+// published on the streams
+fn update_trading_phase(
+    service: &OrderBookService,
+    pair: &str,
+    book: &mut Book,
+    stop_orders: &mut Vec<PendingOrder>,
+    trade_books: &mut HashMap<String, TradeBook>,
+    now: DateTime<Utc>
+) {
+    let instrument: Instrument = service.instrument(pair).unwrap_or_default();
+    if book.halted().is_some_and(|resume_at| resume_at <= now) {
+        // A paused pair re-opens through a call auction too, orders sent in the meantime meet at a
+        // single price instead of sweeping the book
+        let uncross_at: DateTime<Utc> = Utc::now() + service.auction_duration;
+        book.resume();
+        book.start_auction(uncross_at);
+        info!("Trading in {} re-opens with a call auction, uncross at {}", pair, uncross_at);
+        let _ = service.status_tx.send(trading_status(pair, book, ""));
+        let _ = service.auction_tx.send(auction_update(pair, book));
+        return;
+    } else if book.auction().is_some_and(|uncross_at| uncross_at <= now) {
+        let equilibrium: Option<Equilibrium> = uncross_auction(book, trade_books, pair, &instrument);
+        let _ = service.auction_tx.send(AuctionUpdate {
+            uncross_time: now.to_rfc3339(),
+            ..equilibrium_update(pair, orderbook::TradingPhase::Continuous, equilibrium)
+        });
+    } else {
+        return;
+    }
+    let _ = service.status_tx.send(trading_status(pair, book, ""));
+    trigger_stop_orders(book, pair, stop_orders, trade_books, service.stop_trigger, &instrument);
+    check_volatility(service, pair, book, &instrument);
+    publish_market_data(service, pair, book);
+}
 
-            println!("Orderbook status before processing trade: ----");
-            for order in orders.iter() {
-                println!("{}", order);
+// Function to resume halted pairs and uncross call auctions once their time is up (checked every
+// second)
+async fn update_trading_phases(service: Arc<OrderBookService>) {
+    loop {
+        {
+            let mut order_books: tokio::sync::MutexGuard<
+                HashMap<String, Book>
+            > = service.order_books.lock().await;
+            let mut trade_books: tokio::sync::MutexGuard<
+                HashMap<String, TradeBook>
+            > = service.trade_books.lock().await;
+            let mut stop_books: tokio::sync::MutexGuard<
+                HashMap<String, Vec<PendingOrder>>
+            > = service.stop_books.lock().await;
+
+            let now: DateTime<Utc> = Utc::now();
+            for (pair, book) in order_books.iter_mut() {
+                let stop_orders: &mut Vec<PendingOrder> = stop_books.entry(pair.clone()).or_default();
+                update_trading_phase(&service, pair, book, stop_orders, &mut trade_books, now);
             }
-            println!("----------------------------------------------\n");
+        }
 
-            // Sorting for matching
-            orders.sort_by(|a, b| {
-                match (a.side.as_str(), b.side.as_str()) {
-                    ("ask", "ask") => a.price.cmp(&b.price),
-                    ("bid", "bid") => b.price.cmp(&a.price),
-                    _ => std::cmp::Ordering::Equal,
-                }
-            });
+        sleep(Duration::from_secs(1)).await;
+    }
+}
 
-            for order in orders.iter_mut() {
-                let order_log: Order = order.clone();
-                if market_order.order_type == "market" {
-                    if
-                        (market_order.side == "buy" && order.side == "ask") || // Match buy order with ask order
-                        (market_order.side == "sell" && order.side == "bid")
-                    {
-                        // Match sell order with bid order
-                        let matched_volume: OrderedFloat<f64> = order.volume.min(remaining_volume);
-                        println!(
-                            "Matched order: price: {}, volume: {}, side: {}, timestamp: {}, order_type: {}, id: {}",
-                            order.price,
-                            order.volume,
-                            order.side,
-                            order.timestamp,
-                            order.order_type,
-                            order.id
-                        );
-                        matched_orders.push(Order {
-                            id: order.id,
-                            price: order.price,
-                            volume: matched_volume,
-                            side: order.side.clone(),
-                            timestamp: order.timestamp.clone(),
-                            order_type: order.order_type.clone(),
-                        });
-                        order.volume -= matched_volume;
-                        remaining_volume -= matched_volume;
-
-                        if order.volume <= OrderedFloat(0.0) {
-                            orders_to_remove.push(order.clone());
-                            println!("Order fully matched and removed: {:?}", order);
-
-                            //insert to tradebook
-                            let trade = Trade {
-                                id: order.id,
-                                trader: market_order.trader.clone(),
-                                pair: pair.clone(),
-                                side: order.side.clone(),
-                                price: order.price.into(),
-                                volume: order_log.volume.into(),
-                                timestamp: Utc::now().to_rfc3339(),
-                                order_type: order.order_type.clone(),
-                                status: "filled".to_string(),
-                            };
-                            trade_books
-                                .entry(market_order.trader.clone())
-                                .or_insert_with(Vec::new)
-                                .push(trade.clone());
-                        } else {
-                            println!("Order partially matched, remaining volume updated: {:?}",order);
-
-                            //insert to tradebook
-                            let trade = Trade {
-                                id: order.id,
-                                trader: market_order.trader.clone(),
-                                pair: pair.clone(),
-                                side: order.side.clone(),
-                                price: order.price.into(),
-                                volume: matched_volume, //order.volume.into(),
-                                timestamp: Utc::now().to_rfc3339(),
-                                order_type: order.order_type.clone(),
-                                status: "partially_filled".to_string(),
-                            };
-                            trade_books
-                                .entry(market_order.trader.clone())
-                                .or_insert_with(Vec::new)
-                                .push(trade.clone());
-                        }
-
-                        if remaining_volume <= OrderedFloat(0.0) {
-                            break;
-                        }
-                    }
-                } else if market_order.order_type == "limit" {
-                    // Handle limit order logic
-                    if
-                        (market_order.side == "buy" &&
-                            order.side == "ask" &&
-                            market_order.price >= order.price.into_inner()) ||
-                        (market_order.side == "sell" &&
-                            order.side == "bid" &&
-                            market_order.price <= order.price.into_inner())
-                    {
-                        let matched_volume: OrderedFloat<f64> = order.volume.min(remaining_volume);
-                        println!(
-                            "Matched order: price: {}, volume: {}, side: {}, timestamp: {}",
-                            order.price,
-                            order.volume,
-                            order.side,
-                            order.timestamp
-                        );
-                        matched_orders.push(Order {
-                            id: order.id,
-                            price: order.price,
-                            volume: matched_volume,
-                            side: order.side.clone(),
-                            timestamp: order.timestamp.clone(),
-                            order_type: order.order_type.clone(),
-                        });
-                        order.volume -= matched_volume;
-                        remaining_volume -= matched_volume;
-
-                        if order.volume <= OrderedFloat(0.0) {
-                            orders_to_remove.push(order.clone());
-                            println!("Order fully matched and removed: {:?}", order);
-
-                            //insert to tradebook
-                            let trade = Trade {
-                                id: order.id,
-                                trader: market_order.trader.clone(),
-                                pair: pair.clone(),
-                                side: order.side.clone(),
-                                price: order.price.into(),
-                                volume: order_log.volume.into(),
-                                timestamp: Utc::now().to_rfc3339(),
-                                order_type: order.order_type.clone(),
-                                status: "filled".to_string(),
-                            };
-                            trade_books
-                                .entry(market_order.trader.clone())
-                                .or_insert_with(Vec::new)
-                                .push(trade.clone());
-                        } else {
-                            println!("Order partially matched, remaining volume updated: {:?}", order);
-
-                            //insert to tradebook
-                            let trade = Trade {
-                                id: order.id,
-                                trader: market_order.trader.clone(),
-                                pair: pair.clone(),
-                                side: order.side.clone(),
-                                price: order.price.into(),
-                                volume: matched_volume, //order.volume.into(),
-                                timestamp: Utc::now().to_rfc3339(),
-                                order_type: order.order_type.clone(),
-                                status: "partially_filled".to_string(),
-                            };
-                            trade_books
-                                .entry(market_order.trader.clone())
-                                .or_insert_with(Vec::new)
-                                .push(trade.clone());
-                        }
-
-                        if remaining_volume <= OrderedFloat(0.0) {
-                            break;
-                        }
-                    }
-                }
-            }
+// Volume weighted average price of fills; when their total value is too large to compute, each price
+// is weighted by its share of the volume instead
+fn average_price(fills: &[Fill]) -> Option<Decimal> {
+    let fill_volume: Decimal = fills
+        .iter()
+        .try_fold(Decimal::ZERO, |total, fill| total.checked_add(fill.volume))?;
+    if fill_volume.is_zero() {
+        return None;
+    }
+    let notional: Option<Decimal> = fills
+        .iter()
+        .try_fold(Decimal::ZERO, |total, fill| total.checked_add(fill.price.checked_mul(fill.volume)?));
+    match notional {
+        Some(notional) => Some(notional / fill_volume),
+        None => Some(fills.iter().map(|fill| fill.price * (fill.volume / fill_volume)).sum()),
+    }
+}
 
-            for order in orders_to_remove {
-                if let Some(pos) = orders.iter().position(|x| *x == order) {
-                    orders.remove(pos);
-                }
-            }
+// Match one incoming order (stop orders go to the trigger book) and report what happened to it; the
+// first leg of an order group registers the group (its other legs are placed by settle_order_groups)
+async fn process_order(
+    service: &OrderBookService,
+    order_id: Uuid,
+    market_order: NewOrder,
+    group: Option<OrderGroup>
+) -> ExecutionReport {
+    let pair = market_order.pair.clone();
+    let trader: String = market_order.trader.clone();
+    let instrument: Instrument = service.instrument(&pair).unwrap_or_default();
+
+    let mut order_books: tokio::sync::MutexGuard<
+        HashMap<String, Book>
+    > = service.order_books.lock().await;
+    let mut trade_books: tokio::sync::MutexGuard<
+        HashMap<String, TradeBook>
+    > = service.trade_books.lock().await;
+    let mut stop_books: tokio::sync::MutexGuard<
+        HashMap<String, Vec<PendingOrder>>
+    > = service.stop_books.lock().await;
+
+    // Record the order in the trader's order ledger before processing it
+    let record: OrderRecord = OrderRecord::new(order_id, &market_order, Utc::now().to_rfc3339());
+    trade_books.entry(market_order.trader.clone()).or_default().add_order(record);
+
+    let mut report: ExecutionReport = ExecutionReport {
+        order_id,
+        status: OrderStatus::Rejected,
+        filled_volume: Decimal::ZERO,
+        average_price: None,
+        remaining_volume: market_order.volume,
+        stp_canceled_volume: Decimal::ZERO,
+        reject_reason: String::new(),
+        group_id: market_order.group_id,
+        fills: Vec::new(),
+    };
+    let Some(book) = order_books.get_mut(&pair) else {
+        println!("No order book for {}, order {} not processed", pair, order_id);
+        report.reject_reason = format!("no order book for {}", pair);
+        if let Some(record) = order_record(&mut trade_books, &market_order.trader, order_id) {
+            record.reject(&report.reject_reason, Utc::now().to_rfc3339());
+        }
+        return report;
+    };
 
-            if remaining_volume > OrderedFloat(0.0) {
-                if market_order.order_type == "market" {
-                    println!("Market order could not be fully matched, remaining volume: {}", remaining_volume);
-                } else if market_order.order_type == "limit" {
-                    let new_order = Order {
-                        id: trade.id,
-                        price: OrderedFloat(market_order.price), // Limit order retains the specified price
-                        volume: remaining_volume,
-                        side: if market_order.side == "buy" {
-                            "bid".to_string()
-                        } else {
-                            "ask".to_string()
-                        },
-                        timestamp: Utc::now().to_rfc3339(),
-                        order_type: "limit".to_string(),
-                    };
-                    orders.push(new_order.clone());
-                    println!("Limit order added to order book: {:?}", new_order);
+    println!("Processing order for trader: {}", market_order.trader);
 
-                    // JRO: TODO: aggregate order book by side and price
-                }
+    // The other legs of a group are recorded up front (as new) so fills of this leg can reduce them
+    if let Some(group) = group {
+        let trade_book: &mut TradeBook = trade_books.entry(trader.clone()).or_default();
+        for leg in group.exits() {
+            if leg.id != order_id {
+                trade_book.add_order(OrderRecord::new(leg.id, &leg.order, Utc::now().to_rfc3339()));
             }
+        }
+        book.add_group(&trader, group.id);
+        trade_book.add_group(group);
+    }
 
-            orders.sort_by(|a, b| {
-                match a.side.as_str().cmp(&b.side.as_str()) {
-                    std::cmp::Ordering::Equal =>
-                        match a.side.as_str() {
-                            "ask" => b.price.cmp(&a.price), //descending order
-                            "bid" => b.price.cmp(&a.price), //descending order
-                            _ => std::cmp::Ordering::Equal,
-                        }
-                    other => other,
-                }
-            });
+    println!("Orderbook status before processing trade: ----");
+    for order in book.iter() {
+        println!("{}", order);
+    }
+    println!("----------------------------------------------\n");
+
+    let stop_orders: &mut Vec<PendingOrder> = stop_books.entry(pair.clone()).or_default();
+    let submitted: Result<(), String> = submit_order(
+        book,
+        stop_orders,
+        &mut trade_books,
+        order_id,
+        market_order,
+        service.stop_trigger,
+        &instrument
+    );
+    if let Err(reason) = submitted {
+        info!("Rejected order {} for {}: {}", order_id, trader, reason);
+        report.reject_reason = reason;
+        // A rejected leg ends its group
+        settle_order_groups(book, stop_orders, &mut trade_books, service.stop_trigger, &instrument);
+        return report;
+    }
+    trigger_stop_orders(book, &pair, stop_orders, &mut trade_books, service.stop_trigger, &instrument);
 
-            println!("\nOrderbook status after processing trade: -----");
-            for order in orders.iter() {
-                println!("{}", order);
-            }
-            println!("----------------------------------------------\n");
+    // Fills of this order (also when it was a stop triggered straight away), taken before publishing;
+    // its resting remainder may have been hit as maker by stops it triggered itself
+    report.fills = book
+        .fills()
+        .iter()
+        .filter(|fill| fill.taker_order_id == order_id || fill.maker_order_id == order_id)
+        .cloned()
+        .collect();
+    // Status and volumes come from the order's ledger record, which saw every fill
+    if let Some(record) = placed_order(&trade_books, &trader, order_id) {
+        report.status = record.status;
+        report.filled_volume = record.filled_volume;
+        report.stp_canceled_volume = record.stp_canceled_volume;
+        report.remaining_volume = record.volume - record.filled_volume - record.stp_canceled_volume;
+    }
+    report.average_price = average_price(&report.fills);
+
+    // The order's trades may halt the pair
+    check_volatility(service, &pair, book, &instrument);
+    publish_market_data(service, &pair, book);
+
+    println!("\nOrderbook status after processing trade: -----");
+    for order in book.iter() {
+        println!("{}", order);
+    }
+    println!("----------------------------------------------\n");
+
+    report
+}
+
+// Function to process market orders and update the order book (core)
+async fn process_orders(service: Arc<OrderBookService>, mut rx: mpsc::Receiver<QueuedOrder>) {
+    while let Some(QueuedOrder { order: PendingOrder { id: order_id, request: market_order }, group, reply }) = rx.recv().await {
+        let pair = market_order.pair.clone();
+        let report: ExecutionReport = process_order(&service, order_id, market_order, group).await;
 
-            // Update the order book with the local copy
-            order_books.insert(pair.clone(), orders);
+        // The caller may have gone away in the meantime, the order is processed regardless
+        if let Some(reply) = reply {
+            let _ = reply.send(report);
+        }
 
-            // Persist the order book after processing the trade
-            if let Err(e) = persist_order_book(&order_books, &pair, true, true).await {
+        // Persist the order book after processing the trade
+        let order_books: tokio::sync::MutexGuard<
+            HashMap<String, Book>
+        > = service.order_books.lock().await;
+        if order_books.contains_key(&pair) {
+            if let Err(e) = persist_order_book(&order_books, &pair, true).await {
                 eprintln!("Failed to persist order book with timestamp: {}", e);
             }
         }
@@ -598,11 +3425,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let offline_mode: bool = args.contains(&"--offline".to_string());
 
     // Fetch initial order books when the server starts in offline mode
-    let initial_order_books: HashMap<String, Vec<Order>> = if offline_mode {
+    let initial_order_books: HashMap<String, Book> = if offline_mode {
         println!("Offline mode enabled: Loading order books from CSV files.");
         let paths: Vec<String> = config.kraken.offline.clone();
         let csv_file_paths: Vec<String> = paths.iter().map(|s| s.to_string()).collect();
-        let order_books: HashMap<String, Vec<Order>> = load_order_book_from_csv(
+        let order_books: HashMap<String, Book> = load_order_book_from_csv(
             csv_file_paths.iter().map(AsRef::as_ref).collect()
         ).await.unwrap_or_default();
         order_books
@@ -610,17 +3437,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         fetch_order_books(symbols.iter().map(AsRef::as_ref).collect()).await
     };
 
+    // Offline books come from snapshots, so every pair opens with a call auction instead of matching
+    // the first orders against a stale book
+    let mut initial_order_books: HashMap<String, Book> = initial_order_books;
+    if offline_mode {
+        let uncross_at: DateTime<Utc> = Utc::now() + chrono::Duration::seconds(config.kraken.auction_duration as i64);
+        for book in initial_order_books.values_mut() {
+            book.start_auction(uncross_at);
+        }
+        println!("Opening call auctions, uncross at {}", uncross_at);
+    }
+
     // Create the OrderBookService
+    let session_close: NaiveTime = config.kraken.session_close.parse().unwrap_or_else(|e| {
+        eprintln!("Invalid session_close in config, using midnight UTC: {}", e);
+        NaiveTime::MIN
+    });
     let order_book_service: Arc<OrderBookService> = Arc::new(OrderBookService {
-        order_books: Arc::new(Mutex::new(initial_order_books)),
-        order_tx,
-        trade_books: Arc::new(Mutex::new(HashMap::new())), //TODO: load from CSV (recovery/optional)
+        stop_trigger: config.kraken.stop_trigger,
+        session_close,
+        instruments: RwLock::new(config.kraken.instrument_registry()),
+        client_order_window: chrono::Duration::seconds(config.kraken.client_order_window as i64),
+        auction_duration: chrono::Duration::seconds(config.kraken.auction_duration as i64),
+        self_trade_prevention: config.kraken.self_trade_prevention.clone(),
+        ..OrderBookService::new(initial_order_books, order_tx)
     });
 
     // Clone the service for use in the spawned tasks
     let service_clone: Arc<OrderBookService> = Arc::clone(&order_book_service);
     tokio::spawn(async move {
-        update_order_books(service_clone, symbols.iter().map(AsRef::as_ref).collect(), offline_mode).await;
+        update_order_books(service_clone, offline_mode).await;
     });
 
     // Clone the service for use in the spawned tasks
@@ -629,10 +3475,31 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         process_orders(service_clone, order_rx).await;
     });
 
+    // Clone the service for use in the spawned tasks
+    let service_clone: Arc<OrderBookService> = Arc::clone(&order_book_service);
+    tokio::spawn(async move {
+        expire_orders(service_clone).await;
+    });
+
+    // Clone the service for use in the spawned tasks
+    let service_clone: Arc<OrderBookService> = Arc::clone(&order_book_service);
+    tokio::spawn(async move {
+        update_trading_phases(service_clone).await;
+    });
+
     info!("Exchange is listening on {}\n", addr);
 
+    let admin_service: AdminService = AdminService {
+        service: Arc::clone(&order_book_service),
+        offline_mode,
+        configured: config.kraken.instruments.clone(),
+    };
+
     // Start the server
-    Server::builder().add_service(OrderBookServer::new(order_book_service)).serve(addr).await?;
+    Server::builder()
+        .add_service(OrderBookServer::new(order_book_service))
+        .add_service(AdminServer::new(admin_service))
+        .serve(addr).await?;
 
     Ok(())
 }
@@ -2,7 +2,19 @@
 mod tests {
     use crate::*;
     use crate::fetch_order_book;
-    use crate::orderbook::{ OrderBookRequest, OrderRequest };
+    use crate::orderbook::{
+        CancelOrderRequest,
+        ClientOrderRequest,
+        DepthRequest,
+        GetOrderRequest,
+        OpenOrdersRequest,
+        OrderBookRequest,
+        OrderRequest,
+        ReplaceOrderRequest,
+        TradeStreamRequest,
+    };
+    use crate::models::model::models::KrakenConfig;
+    use rust_decimal_macros::dec;
 
     //Test the fetch_order_book function by fetching the order book for a trading pair
     #[tokio::test]
@@ -16,108 +28,408 @@ mod tests {
     #[tokio::test]
     async fn test_get_order_book() {
         let (order_tx, _order_rx) = mpsc::channel(100);
-        let order_books = Arc::new(Mutex::new(HashMap::new()));
-        let trade_books = Arc::new(Mutex::new(HashMap::new()));
-        let service = Arc::new(OrderBookService {
-            order_books: order_books.clone(),
-            order_tx,
-            trade_books,
-        });
+        let service = Arc::new(OrderBookService::new(HashMap::new(), order_tx));
+        let order_books = service.order_books.clone();
 
         let pair = "XXBTZUSD".to_string();
         let order = Order {
             id: Uuid::new_v4(),
-            price: OrderedFloat(50000.0),
-            volume: OrderedFloat(1.0),
-            side: "ask".to_string(),
+            trader: String::new(),
+            price: dec!(50000.0),
+            volume: dec!(1.0),
+            original_volume: dec!(1.0),
+            display_volume: Decimal::ZERO,
+            hidden_volume: Decimal::ZERO,
+            side: Side::Sell,
             timestamp: Utc::now().to_rfc3339(),
-            order_type: "limit".to_string(),
+            order_type: OrderType::Limit,
+            expire_time: None,
+            client_order_id: String::new(),
         };
 
-        order_books.lock().await.insert(pair.clone(), vec![order.clone()]);
+        order_books.lock().await.insert(pair.clone(), Book::from_iter(vec![order.clone()]));
 
         let request = Request::new(OrderBookRequest { pair: pair.clone() });
         let response = service.get_order_book(request).await.unwrap().into_inner();
 
         assert_eq!(response.orders.len(), 1);
-        assert_eq!(response.orders[0].price, 50000.0);
-        assert_eq!(response.orders[0].volume, 1.0);
+        assert_eq!(response.orders[0].price, "50000");
+        assert_eq!(response.orders[0].volume, "1");
     }
 
     #[tokio::test]
     async fn test_place_market_order() {
         let (order_tx, mut order_rx) = mpsc::channel(100);
-        let order_books = Arc::new(Mutex::new(HashMap::new()));
-        let trade_books = Arc::new(Mutex::new(HashMap::new()));
-        let service = Arc::new(OrderBookService {
-            order_books,
-            order_tx,
-            trade_books,
-        });
+        let initial: HashMap<String, Book> = HashMap::from([("XXBTZUSD".to_string(), Book::new())]);
+        let service = Arc::new(OrderBookService::new(initial, order_tx));
 
         let market_order = OrderRequest {
             trader: "trader1".to_string(),
             pair: "XXBTZUSD".to_string(),
-            price: 50000.0,
-            volume: 1.0,
-            side: "buy".to_string(),
-            order_type: "market".to_string(),
+            price: "50000.0".to_string(),
+            volume: "1.25".to_string(),
+            side: orderbook::Side::Buy.into(),
+            order_type: orderbook::OrderType::Market.into(),
+            fire_and_forget: true,
+            ..Default::default()
         };
 
         let request = Request::new(market_order.clone());
         let response = service.place_market_order(request).await.unwrap().into_inner();
 
-        assert_eq!(response.status, "new");
+        assert_eq!(response.status(), orderbook::OrderStatus::New);
         assert_eq!(response.message, "order registerted and is being processed");
 
-        let received_order = order_rx.recv().await.unwrap();
+        let QueuedOrder { order: PendingOrder { id, request: received_order }, reply, .. } = order_rx.recv().await.unwrap();
+        assert!(reply.is_none());
+        assert_eq!(response.order_id, id.to_string());
         assert_eq!(received_order.trader, market_order.trader);
         assert_eq!(received_order.pair, market_order.pair);
-        assert_eq!(received_order.price, market_order.price);
-        assert_eq!(received_order.volume, market_order.volume);
-        assert_eq!(received_order.side, market_order.side);
-        assert_eq!(received_order.order_type, market_order.order_type);
+        assert_eq!(received_order.price, dec!(50000));
+        assert_eq!(received_order.volume, dec!(1.25));
+        assert_eq!(received_order.side, Side::Buy);
+        assert_eq!(received_order.order_type, OrderType::Market);
     }
 
     #[tokio::test]
-    async fn test_get_trade_book() {
-        let (order_tx, _order_rx) = mpsc::channel(100);
-        let order_books = Arc::new(Mutex::new(HashMap::new()));
-        let trade_books = Arc::new(Mutex::new(HashMap::new()));
+    async fn test_place_order_trading_rules() {
+        let (order_tx, mut order_rx) = mpsc::channel(100);
+        let instrument = Instrument {
+            tick_size: dec!(0.5),
+            lot_size: dec!(0.01),
+            min_volume: dec!(0.01),
+            max_volume: dec!(10),
+            max_price: dec!(1000000),
+            min_notional: dec!(100),
+            ..Default::default()
+        };
         let service = Arc::new(OrderBookService {
-            order_books,
-            order_tx,
-            trade_books: trade_books.clone(),
+            instruments: RwLock::new(HashMap::from([("XXBTZUSD".to_string(), instrument), ("SUIUSD".to_string(), Instrument::default())])),
+            ..OrderBookService::new(HashMap::new(), order_tx)
         });
+        let order = |pair: &str, order_type: orderbook::OrderType, price: &str, volume: &str, stop_price: &str| {
+            Request::new(OrderRequest {
+                trader: "trader1".to_string(),
+                pair: pair.to_string(),
+                price: price.to_string(),
+                volume: volume.to_string(),
+                side: orderbook::Side::Buy.into(),
+                order_type: order_type.into(),
+                stop_price: stop_price.to_string(),
+                fire_and_forget: true,
+                ..Default::default()
+            })
+        };
+
+        let rejected = [
+            order("XETHZUSD", orderbook::OrderType::Limit, "3000", "1", ""), // unknown pair
+            order("XXBTZUSD", orderbook::OrderType::Limit, "65290.1", "1", ""), // off tick
+            order("XXBTZUSD", orderbook::OrderType::Limit, "65290", "0.015", ""), // off lot
+            order("XXBTZUSD", orderbook::OrderType::Market, "", "0", ""), // zero volume
+            order("XXBTZUSD", orderbook::OrderType::Market, "", "11", ""), // above max volume
+            order("XXBTZUSD", orderbook::OrderType::Limit, "5000", "0.01", ""), // below min notional
+            order("XXBTZUSD", orderbook::OrderType::Limit, "2000000", "1", ""), // above max price
+            order("XXBTZUSD", orderbook::OrderType::Stop, "", "1", "2000000"), // stop price above max price
+            order("SUIUSD", orderbook::OrderType::Limit, "100000000000000", "1000000000000000", ""), // order value overflows
+            order("XXBTZUSD", orderbook::OrderType::Limit, "", "1", ""), // limit without price
+            order("XXBTZUSD", orderbook::OrderType::Stop, "", "1", "") // stop without stop price
+        ];
+        let count = rejected.len();
+        for request in rejected {
+            let status = service.place_market_order(request).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+        let statuses: Vec<OrderStatus> = service.trade_books.lock().await["trader1"]
+            .orders()
+            .iter()
+            .map(|o| o.status)
+            .collect();
+        assert_eq!(statuses, vec![OrderStatus::Rejected; count]);
+        assert!(order_rx.try_recv().is_err());
+
+        // Market orders need no price, valid limit and stop orders are queued
+        service.place_market_order(order("XXBTZUSD", orderbook::OrderType::Market, "", "0.01", "")).await.unwrap();
+        service.place_market_order(order("XXBTZUSD", orderbook::OrderType::Limit, "65290.5", "0.02", "")).await.unwrap();
+        service.place_market_order(order("XXBTZUSD", orderbook::OrderType::Stop, "", "1", "65300")).await.unwrap();
+        for _ in 0..3 {
+            assert!(order_rx.try_recv().is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_place_order_execution_report() {
+        let (order_tx, mut order_rx) = mpsc::channel::<QueuedOrder>(100);
+        let initial: HashMap<String, Book> = HashMap::from([
+            ("XXBTZUSD".to_string(), vec![book_order(dec!(100), dec!(1), Side::Sell), book_order(dec!(101), dec!(1), Side::Sell)]
+                .into_iter()
+                .collect()),
+        ]);
+        let service = Arc::new(OrderBookService::new(initial, order_tx));
+
+        // Matching engine without persistence, replying like process_orders
+        let engine = Arc::clone(&service);
+        tokio::spawn(async move {
+            while let Some(QueuedOrder { order, reply, .. }) = order_rx.recv().await {
+                let report = process_order(&engine, order.id, order.request, None).await;
+                if let Some(reply) = reply {
+                    let _ = reply.send(report);
+                }
+            }
+        });
+        let order = |order_type: orderbook::OrderType, price: &str, volume: &str, time_in_force: orderbook::TimeInForce| {
+            Request::new(OrderRequest {
+                trader: "trader1".to_string(),
+                pair: "XXBTZUSD".to_string(),
+                price: price.to_string(),
+                volume: volume.to_string(),
+                side: orderbook::Side::Buy.into(),
+                order_type: order_type.into(),
+                time_in_force: time_in_force.into(),
+                ..Default::default()
+            })
+        };
+
+        // Sweeps both levels and reports fills with the volume weighted average price
+        let response = service.place_market_order(order(orderbook::OrderType::Market, "", "1.5", orderbook::TimeInForce::Gtc)).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::Filled);
+        let report = response.report.unwrap();
+        assert_eq!((report.filled_volume.as_str(), report.remaining_volume.as_str()), ("1.5", "0"));
+        assert_eq!(report.average_price, "100.33333333333333333333333333");
+        let fills: Vec<(String, String)> = report.fills.iter().map(|f| (f.price.clone(), f.volume.clone())).collect();
+        assert_eq!(fills, vec![("100".to_string(), "1".to_string()), ("101".to_string(), "0.5".to_string())]);
+        assert!(report.fills.iter().all(|f| f.taker_order_id == response.order_id));
+
+        // Remainder rests in the book
+        let response = service.place_market_order(order(orderbook::OrderType::Limit, "101", "1", orderbook::TimeInForce::Gtc)).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::PartiallyFilled);
+        assert_eq!(response.report.unwrap().remaining_volume, "0.5");
+
+        // Nothing crosses: resting untouched, or canceled straight away for ioc
+        let response = service.place_market_order(order(orderbook::OrderType::Limit, "99", "1", orderbook::TimeInForce::Gtc)).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::Open);
+        let response = service.place_market_order(order(orderbook::OrderType::Limit, "98", "1", orderbook::TimeInForce::Ioc)).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::Canceled);
+        assert_eq!(response.report.unwrap().average_price, "");
+
+        // Fire-and-forget only acknowledges the order
+        let mut request = order(orderbook::OrderType::Limit, "97", "1", orderbook::TimeInForce::Gtc);
+        request.get_mut().fire_and_forget = true;
+        let response = service.place_market_order(request).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::New);
+        assert!(response.report.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_execution_report_includes_maker_fills() {
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let initial: HashMap<String, Book> = HashMap::from([
+            ("XXBTZUSD".to_string(), vec![book_order(dec!(100), dec!(1), Side::Sell)].into_iter().collect()),
+        ]);
+        let service = Arc::new(OrderBookService::new(initial, order_tx));
+        let stop = NewOrder {
+            trader: "trader2".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            side: Side::Sell,
+            order_type: OrderType::Stop,
+            stop_price: dec!(100),
+            volume: dec!(1),
+            ..Default::default()
+        };
+        assert_eq!(process_order(&service, Uuid::new_v4(), stop, None).await.status, OrderStatus::Pending);
+
+        // The buy takes the ask at 100 and rests the rest at 101, where the stop it triggered fills it
+        let order_id = Uuid::new_v4();
+        let buy = NewOrder {
+            trader: "trader1".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            price: dec!(101),
+            volume: dec!(2),
+            ..Default::default()
+        };
+        let report = process_order(&service, order_id, buy, None).await;
+        assert_eq!((report.status, report.filled_volume, report.remaining_volume), (OrderStatus::Filled, dec!(2), dec!(0)));
+        assert_eq!(report.fills.len(), 2);
+        assert_eq!(report.fills[1].maker_order_id, order_id);
+        assert_eq!(report.average_price, Some(dec!(100.5)));
+    }
+
+    #[tokio::test]
+    async fn test_client_order_id_idempotency() {
+        let (order_tx, mut order_rx) = mpsc::channel::<QueuedOrder>(100);
+        let initial: HashMap<String, Book> = HashMap::from([
+            ("XXBTZUSD".to_string(), vec![book_order(dec!(100), dec!(2), Side::Sell)].into_iter().collect()),
+        ]);
+        let service = Arc::new(OrderBookService::new(initial, order_tx));
+        let engine = Arc::clone(&service);
+        tokio::spawn(async move {
+            while let Some(QueuedOrder { order, reply, .. }) = order_rx.recv().await {
+                let report = process_order(&engine, order.id, order.request, None).await;
+                if let Some(reply) = reply {
+                    let _ = reply.send(report);
+                }
+            }
+        });
+        let order = |trader: &str, client_order_id: &str| {
+            Request::new(OrderRequest {
+                trader: trader.to_string(),
+                pair: "XXBTZUSD".to_string(),
+                volume: "1".to_string(),
+                side: orderbook::Side::Buy.into(),
+                order_type: orderbook::OrderType::Market.into(),
+                client_order_id: client_order_id.to_string(),
+                ..Default::default()
+            })
+        };
+
+        // A retried submission returns the original response without trading again
+        let first = service.place_market_order(order("trader1", "bot-1")).await.unwrap().into_inner();
+        let retry = service.place_market_order(order("trader1", "bot-1")).await.unwrap().into_inner();
+        assert_eq!(first.status(), orderbook::OrderStatus::Filled);
+        assert_eq!(retry, first);
+        assert_eq!(service.order_books.lock().await["XXBTZUSD"].best(Side::Sell).unwrap().volume, dec!(1));
+
+        // The same id of another trader is a different order
+        let other = service.place_market_order(order("trader2", "bot-1")).await.unwrap().into_inner();
+        assert_ne!(other.order_id, first.order_id);
+        assert_eq!(other.report.unwrap().fills[0].taker_client_order_id, "bot-1");
+
+        let trade_books = service.trade_books.lock().await;
+        assert_eq!(trade_books["trader1"].orders().len(), 1);
+        assert!(trade_books["trader1"].fills().iter().all(|f| f.client_order_id == "bot-1"));
+        drop(trade_books);
+
+        let lookup = |client_order_id: &str| Request::new(ClientOrderRequest {
+            trader: "trader1".to_string(),
+            client_order_id: client_order_id.to_string(),
+        });
+        let found = service.get_order_by_client_id(lookup("bot-1")).await.unwrap().into_inner();
+        assert_eq!((found.status(), found.order_id), (orderbook::OrderStatus::Filled, first.order_id));
+        let status = service.get_order_by_client_id(lookup("bot-2")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_client_order_id_released_on_failure() {
+        let (order_tx, order_rx) = mpsc::channel::<QueuedOrder>(100);
+        let service = Arc::new(OrderBookService::new(HashMap::from([("XXBTZUSD".to_string(), Book::new())]), order_tx));
+        let order = || Request::new(OrderRequest {
+            trader: "trader1".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            volume: "1".to_string(),
+            price: "100".to_string(),
+            side: orderbook::Side::Buy.into(),
+            order_type: orderbook::OrderType::Limit.into(),
+            client_order_id: "bot-1".to_string(),
+            ..Default::default()
+        });
+
+        // Without a matching engine the order can't be queued, a retry is not answered as queued
+        drop(order_rx);
+        for _ in 0..2 {
+            let status = service.place_market_order(order()).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::Internal);
+        }
+        assert!(service.client_orders.lock().await.is_empty());
+
+        // Same when the engine drops the order without a report
+        let (order_tx, mut order_rx) = mpsc::channel::<QueuedOrder>(100);
+        let service = Arc::new(OrderBookService::new(HashMap::from([("XXBTZUSD".to_string(), Book::new())]), order_tx));
+        tokio::spawn(async move { while order_rx.recv().await.is_some() {} });
+        let status = service.place_market_order(order()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+        assert!(service.client_orders.lock().await.is_empty());
+    }
+
+    #[test]
+    fn test_instrument_registry() {
+        let config: KrakenConfig = serde_yaml::from_str(
+            r#"
+symbols: ["XXBTZUSD", "SUIUSD"]
+persist: "data"
+offline: []
+self_trade_prevention: { Rock: cancel_oldest }
+instruments:
+  XXBTZUSD: { price_scale: 1, tick_size: "0.1", lot_size: 0.0001, min_notional: 5, taker_fee: "0.0026", price_band: 5, halt_move: 10, halt_mode: auction }
+"#
+        ).unwrap();
+        let registry = config.instrument_registry();
+
+        assert_eq!(registry.len(), 2);
+        let xbt = registry["XXBTZUSD"];
+        assert_eq!((xbt.price_scale, xbt.volume_scale), (1, 8));
+        assert_eq!((xbt.tick_size, xbt.lot_size, xbt.min_notional), (dec!(0.1), dec!(0.0001), dec!(5)));
+        assert_eq!((xbt.maker_fee, xbt.taker_fee), (Decimal::ZERO, dec!(0.0026)));
+        assert_eq!((xbt.price_band, xbt.limit_collar, xbt.halt_move), (dec!(5), Decimal::ZERO, dec!(10)));
+        assert_eq!((xbt.halt_window, xbt.halt_duration, xbt.halt_mode), (60, 60, HaltMode::Auction));
+        assert_eq!(registry["SUIUSD"], Instrument::default());
+        assert_eq!(config.self_trade_prevention["Rock"], SelfTradePrevention::CancelOldest);
+    }
+
+    #[tokio::test]
+    async fn test_get_trade_book() {
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let service = Arc::new(OrderBookService::new(HashMap::new(), order_tx));
+        let trade_books = service.trade_books.clone();
 
         let trader = "trader1".to_string();
-        let trade = Trade {
-            id: Uuid::new_v4(),
-            trader: trader.clone(),
+        let order_id: Uuid = Uuid::new_v4();
+        let request: NewOrder = NewOrder {
             pair: "XXBTZUSD".to_string(),
-            price: OrderedFloat(50000.0),
-            volume: OrderedFloat(1.0),
-            side: "buy".to_string(),
-            timestamp: Utc::now().to_rfc3339(),
-            order_type: "market".to_string(),
-            status: "filled".to_string(),
+            trader: trader.clone(),
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            price: dec!(50000.0),
+            volume: dec!(1.0),
+            ..Default::default()
         };
+        let timestamp: String = Utc::now().to_rfc3339();
+        let mut record: OrderRecord = OrderRecord::new(order_id, &request, timestamp.clone());
+        record.fill(dec!(0.4), timestamp.clone());
+        let mut trade_book: TradeBook = TradeBook::new();
+        trade_book.add_order(record);
+        trade_book.add_fill(Execution {
+            id: Uuid::new_v4(),
+            pair: request.pair.clone(),
+            order_id,
+            client_order_id: String::new(),
+            side: Side::Buy,
+            price: dec!(50000.0),
+            volume: dec!(0.4),
+            fee: dec!(20),
+            liquidity: Liquidity::Taker,
+            aggressor_side: Side::Buy,
+            taker_trader: trader.clone(),
+            taker_order_id: order_id,
+            maker_trader: EXTERNAL_TRADER.to_string(),
+            maker_order_id: Uuid::new_v4(),
+            timestamp: timestamp.clone(),
+        });
 
-        trade_books.lock().await.insert(trader.clone(), vec![trade.clone()]);
+        trade_books.lock().await.insert(trader.clone(), trade_book);
 
         let request = Request::new(TradeBookRequest { trader: trader.clone() });
         let response = service.get_trade_book(request).await.unwrap().into_inner();
 
-        assert_eq!(response.trades.len(), 1);
-        assert_eq!(response.trades[0].id, trade.id.to_string());
-        assert_eq!(response.trades[0].trader, trade.trader);
-        assert_eq!(response.trades[0].pair, trade.pair);
-        assert_eq!(response.trades[0].price, trade.price.into_inner());
-        assert_eq!(response.trades[0].volume, trade.volume.into_inner());
-        assert_eq!(response.trades[0].side, trade.side);
-        assert_eq!(response.trades[0].timestamp, trade.timestamp);
-        assert_eq!(response.trades[0].order_type, trade.order_type);
-        assert_eq!(response.trades[0].status, trade.status);
+        // One order with its lifecycle, one execution
+        assert_eq!(response.orders.len(), 1);
+        let order = &response.orders[0];
+        assert_eq!((order.order_id.clone(), order.trader.as_str()), (order_id.to_string(), "trader1"));
+        assert_eq!((order.price.as_str(), order.original_volume.as_str()), ("50000", "1"));
+        assert_eq!((order.filled_volume.as_str(), order.remaining_volume.as_str()), ("0.4", "0.6"));
+        assert_eq!(order.order_type(), orderbook::OrderType::Limit);
+        assert_eq!(order.status(), orderbook::OrderStatus::PartiallyFilled);
+        let transitions: Vec<orderbook::OrderStatus> = order.transitions.iter().map(|t| t.status()).collect();
+        assert_eq!(transitions, vec![orderbook::OrderStatus::New, orderbook::OrderStatus::PartiallyFilled]);
+
+        assert_eq!(response.fills.len(), 1);
+        let fill = &response.fills[0];
+        assert_eq!(fill.order_id, order_id.to_string());
+        assert_eq!((fill.price.as_str(), fill.volume.as_str(), fill.fee.as_str()), ("50000", "0.4", "20"));
+        assert_eq!(fill.side(), orderbook::Side::Buy);
+        assert_eq!(fill.liquidity(), orderbook::Liquidity::Taker);
+        assert_eq!(fill.timestamp, timestamp);
     }
 
     #[tokio::test]
@@ -137,4 +449,1920 @@ mod tests {
         assert!(result.contains_key("XXBTZUSD"));
         assert!(result.contains_key("XETHZUSD"));
     }
-}
\ No newline at end of file
+
+    fn book_order(price: Decimal, volume: Decimal, side: Side) -> Order {
+        Order {
+            id: Uuid::new_v4(),
+            trader: String::new(),
+            price,
+            volume,
+            original_volume: volume,
+            display_volume: Decimal::ZERO,
+            hidden_volume: Decimal::ZERO,
+            side,
+            timestamp: Utc::now().to_rfc3339(),
+            order_type: OrderType::Limit,
+            expire_time: None,
+            client_order_id: String::new(),
+        }
+    }
+
+    // Record the order as the matching engine would and run it against the book without fees
+    fn execute(
+        book: &mut Book,
+        trade_books: &mut HashMap<String, TradeBook>,
+        order: &NewOrder,
+        order_id: Uuid
+    ) -> Decimal {
+        let record: OrderRecord = OrderRecord::new(order_id, order, Utc::now().to_rfc3339());
+        trade_books.entry(order.trader.clone()).or_default().add_order(record);
+        execute_order(book, trade_books, order, order_id, &Instrument::default())
+    }
+
+    #[test]
+    fn test_book_price_time_priority() {
+        let first = book_order(dec!(100.0), dec!(1.0), Side::Sell);
+        let second = book_order(dec!(100.0), dec!(2.0), Side::Sell);
+        let better = book_order(dec!(99.5), dec!(1.0), Side::Sell);
+        let bid = book_order(dec!(99.0), dec!(3.0), Side::Buy);
+        let mut book: Book = vec![first.clone(), second.clone(), better.clone(), bid.clone()]
+            .into_iter()
+            .collect();
+
+        assert_eq!(book.len(), 4);
+        assert_eq!(book.best_ask(), Some(dec!(99.5)));
+        assert_eq!(book.best_bid(), Some(dec!(99.0)));
+        assert_eq!(book.best(Side::Sell).unwrap().id, better.id);
+
+        // Same price level is served first in, first out
+        book.remove(&better.id);
+        assert_eq!(book.best(Side::Sell).unwrap().id, first.id);
+        assert_eq!(book.get(&second.id).unwrap().volume, dec!(2.0));
+
+        // Display order: asks then bids, both by descending price
+        let ids: Vec<Uuid> = book.iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![first.id, second.id, bid.id]);
+    }
+
+    #[test]
+    fn test_execute_order_matching() {
+        let mut book: Book = vec![
+            book_order(dec!(101.0), dec!(1.0), Side::Sell),
+            book_order(dec!(100.0), dec!(0.5), Side::Sell),
+            book_order(dec!(99.0), dec!(2.0), Side::Buy)
+        ]
+            .into_iter()
+            .collect();
+        let mut trade_books: HashMap<String, TradeBook> = HashMap::new();
+
+        // Limit buy at 100.5 takes the 100.0 level and rests the remainder as a bid
+        let limit_order = NewOrder {
+            trader: "trader1".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            price: dec!(100.5),
+            volume: dec!(1.5),
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            ..Default::default()
+        };
+        let order_id = Uuid::new_v4();
+        let remaining = execute(&mut book, &mut trade_books, &limit_order, order_id);
+        assert_eq!(remaining, dec!(1.0));
+        assert_eq!(book.best_bid(), Some(dec!(100.5)));
+        assert_eq!(book.get(&order_id).unwrap().volume, dec!(1.0));
+        assert_eq!(trade_books["trader1"].fills().len(), 1);
+        assert_eq!(trade_books["trader1"].order(&order_id).unwrap().status, OrderStatus::PartiallyFilled);
+
+        // Market sell sweeps the bids best price first
+        let market_order = NewOrder {
+            trader: "trader2".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            volume: dec!(2.0),
+            side: Side::Sell,
+            order_type: OrderType::Market,
+            ..Default::default()
+        };
+        let sell_id = Uuid::new_v4();
+        let remaining = execute(&mut book, &mut trade_books, &market_order, sell_id);
+        assert_eq!(remaining, dec!(0.0));
+        let fills: Vec<Decimal> = trade_books["trader2"].fills().iter().map(|f| f.price).collect();
+        assert_eq!(fills, vec![dec!(100.5), dec!(99.0)]);
+        assert_eq!(trade_books["trader2"].order(&sell_id).unwrap().status, OrderStatus::Filled);
+
+        // The resting bid of trader1 was the maker of the first fill
+        let bid = trade_books["trader1"].order(&order_id).unwrap();
+        assert_eq!((bid.status, bid.filled_volume, bid.open_volume), (OrderStatus::Filled, dec!(1.5), dec!(0.0)));
+        assert_eq!(book.best_bid(), Some(dec!(99.0)));
+        assert_eq!(book.len(), 2);
+    }
+
+    #[test]
+    fn test_fills_ledger() {
+        let mut book: Book = vec![book_order(dec!(100.0), dec!(1.0), Side::Sell), book_order(dec!(101.0), dec!(1.0), Side::Sell)]
+            .into_iter()
+            .collect();
+        let mut trade_books: HashMap<String, TradeBook> = HashMap::new();
+        let instrument = Instrument { taker_fee: dec!(0.002), ..Default::default() };
+        let buy = NewOrder {
+            trader: "trader1".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            volume: dec!(1.5),
+            side: Side::Buy,
+            order_type: OrderType::Market,
+            client_order_id: "pnl-1".to_string(),
+            ..Default::default()
+        };
+        let order_id = Uuid::new_v4();
+        let record: OrderRecord = OrderRecord::new(order_id, &buy, Utc::now().to_rfc3339());
+        trade_books.entry("trader1".to_string()).or_default().add_order(record);
+        let maker_ids: Vec<Uuid> = book.asks().map(|o| o.id).collect();
+        execute_order(&mut book, &mut trade_books, &buy, order_id, &instrument);
+
+        // One execution per fill under the taker's own order id and side, not the maker's
+        let fills = trade_books["trader1"].fills();
+        assert_eq!(fills.len(), 2);
+        assert!(fills.iter().all(|f| {
+            f.order_id == order_id && f.taker_order_id == order_id && f.client_order_id == "pnl-1" &&
+            f.side == Side::Buy && f.aggressor_side == Side::Buy &&
+            f.liquidity == Liquidity::Taker && f.taker_trader == "trader1" && f.maker_trader == EXTERNAL_TRADER
+        }));
+        assert!(!trade_books.contains_key(EXTERNAL_TRADER));
+        let maker_orders: Vec<Uuid> = fills.iter().map(|f| f.maker_order_id).collect();
+        assert_eq!(maker_orders, maker_ids);
+        let fees: Vec<(Decimal, Decimal, Decimal)> = fills.iter().map(|f| (f.price, f.volume, f.fee)).collect();
+        assert_eq!(fees, vec![(dec!(100.0), dec!(1.0), dec!(0.2)), (dec!(101.0), dec!(0.5), dec!(0.101))]);
+
+        // A single order record with both fills in its lifecycle
+        let orders = trade_books["trader1"].orders();
+        assert_eq!(orders.len(), 1);
+        let statuses: Vec<(OrderStatus, Decimal)> = orders[0].transitions.iter().map(|t| (t.status, t.open_volume)).collect();
+        assert_eq!(statuses, vec![
+            (OrderStatus::New, dec!(1.5)),
+            (OrderStatus::PartiallyFilled, dec!(0.5)),
+            (OrderStatus::Filled, dec!(0.0))
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_fills_for_both_counterparties() {
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let instrument = Instrument { maker_fee: dec!(0.001), taker_fee: dec!(0.002), ..Default::default() };
+        let service = Arc::new(OrderBookService {
+            instruments: RwLock::new(HashMap::from([("XXBTZUSD".to_string(), instrument)])),
+            ..OrderBookService::new(HashMap::new(), order_tx)
+        });
+
+        // Desk A rests an ask, desk B lifts it and then the remaining Kraken ask
+        let ask = place_order(&service, "deskA", Side::Sell, OrderType::Limit, dec!(100.0), dec!(1.0)).await;
+        service.order_books.lock().await.get_mut("XXBTZUSD").unwrap().insert(book_order(dec!(101.0), dec!(1.0), Side::Sell));
+        let bid = Uuid::new_v4();
+        process_order(&service, bid, NewOrder {
+            trader: "deskB".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            volume: dec!(1.5),
+            side: Side::Buy,
+            order_type: OrderType::Market,
+            ..Default::default()
+        }, None).await;
+
+        let trade_books = service.trade_books.lock().await;
+        let maker = trade_books["deskA"].fills();
+        assert_eq!(maker.len(), 1);
+        assert_eq!((maker[0].order_id, maker[0].side, maker[0].liquidity), (ask, Side::Sell, Liquidity::Maker));
+        assert_eq!((maker[0].price, maker[0].volume, maker[0].fee), (dec!(100.0), dec!(1.0), dec!(0.1)));
+        assert_eq!((maker[0].maker_trader.as_str(), maker[0].taker_trader.as_str()), ("deskA", "deskB"));
+        assert_eq!(maker[0].aggressor_side, Side::Buy);
+        assert_eq!(trade_books["deskA"].order(&ask).unwrap().status, OrderStatus::Filled);
+
+        // The taker sees the same trade ids, the Kraken fill against the external counterparty
+        let taker = trade_books["deskB"].fills();
+        assert_eq!(taker[0].id, maker[0].id);
+        let counterparties: Vec<(&str, Liquidity, Decimal)> = taker
+            .iter()
+            .map(|f| (f.maker_trader.as_str(), f.liquidity, f.fee))
+            .collect();
+        assert_eq!(counterparties, vec![("deskA", Liquidity::Taker, dec!(0.2)), (EXTERNAL_TRADER, Liquidity::Taker, dec!(0.101))]);
+        assert!(taker.iter().all(|f| f.order_id == bid && f.side == Side::Buy));
+        assert!(!trade_books.contains_key(EXTERNAL_TRADER));
+    }
+
+    #[test]
+    fn test_self_trade_prevention_modes() {
+        // trader1's ask at 100.0 ahead of Kraken liquidity at 101.0
+        let setup = || {
+            let mut book: Book = vec![book_order(dec!(101.0), dec!(1.0), Side::Sell)].into_iter().collect();
+            let mut trade_books: HashMap<String, TradeBook> = HashMap::new();
+            let ask = NewOrder {
+                trader: "trader1".to_string(),
+                pair: "XXBTZUSD".to_string(),
+                price: dec!(100.0),
+                volume: dec!(1.0),
+                side: Side::Sell,
+                order_type: OrderType::Limit,
+                ..Default::default()
+            };
+            let ask_id = Uuid::new_v4();
+            execute(&mut book, &mut trade_books, &ask, ask_id);
+            (book, trade_books, ask_id)
+        };
+        let buy = |self_trade_prevention: SelfTradePrevention, volume: Decimal| NewOrder {
+            trader: "trader1".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            price: dec!(101.0),
+            volume,
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            time_in_force: TimeInForce::Ioc,
+            self_trade_prevention,
+            ..Default::default()
+        };
+        let state = |trade_books: &HashMap<String, TradeBook>, id: &Uuid| {
+            let record = trade_books["trader1"].order(id).unwrap();
+            (record.status, record.filled_volume, record.stp_canceled_volume)
+        };
+
+        // Cancel newest: the incoming order is canceled, nothing trades
+        let (mut book, mut trade_books, ask_id) = setup();
+        let buy_id = Uuid::new_v4();
+        execute(&mut book, &mut trade_books, &buy(SelfTradePrevention::CancelNewest, dec!(1.5)), buy_id);
+        assert_eq!(state(&trade_books, &buy_id), (OrderStatus::StpCanceled, dec!(0), dec!(1.5)));
+        assert_eq!(book.get(&ask_id).unwrap().volume, dec!(1.0));
+        assert!(book.fills().is_empty());
+
+        // Cancel oldest: the resting order goes and matching continues with the next level
+        let (mut book, mut trade_books, ask_id) = setup();
+        let buy_id = Uuid::new_v4();
+        execute(&mut book, &mut trade_books, &buy(SelfTradePrevention::CancelOldest, dec!(1.5)), buy_id);
+        assert_eq!(state(&trade_books, &ask_id), (OrderStatus::StpCanceled, dec!(0), dec!(1.0)));
+        assert_eq!(state(&trade_books, &buy_id), (OrderStatus::Canceled, dec!(1.0), dec!(0)));
+        assert_eq!(book.get(&ask_id), None);
+        assert_eq!(book.last_price(), Some(dec!(101.0)));
+
+        // Cancel both
+        let (mut book, mut trade_books, ask_id) = setup();
+        let buy_id = Uuid::new_v4();
+        execute(&mut book, &mut trade_books, &buy(SelfTradePrevention::CancelBoth, dec!(1.5)), buy_id);
+        assert_eq!(state(&trade_books, &ask_id), (OrderStatus::StpCanceled, dec!(0), dec!(1.0)));
+        assert_eq!(state(&trade_books, &buy_id), (OrderStatus::StpCanceled, dec!(0), dec!(1.5)));
+        assert_eq!(book.len(), 1);
+
+        // Decrement and cancel: the smaller incoming order is canceled, the resting one reduced
+        let (mut book, mut trade_books, ask_id) = setup();
+        let buy_id = Uuid::new_v4();
+        execute(&mut book, &mut trade_books, &buy(SelfTradePrevention::DecrementAndCancel, dec!(0.4)), buy_id);
+        assert_eq!(state(&trade_books, &buy_id), (OrderStatus::StpCanceled, dec!(0), dec!(0.4)));
+        assert_eq!(state(&trade_books, &ask_id), (OrderStatus::Open, dec!(0), dec!(0.4)));
+        assert_eq!(trade_books["trader1"].order(&ask_id).unwrap().open_volume, dec!(0.6));
+        assert_eq!(book.get(&ask_id).unwrap().volume, dec!(0.6));
+
+        // Without prevention the trader trades with themselves
+        let (mut book, mut trade_books, ask_id) = setup();
+        execute(&mut book, &mut trade_books, &buy(SelfTradePrevention::None, dec!(1.0)), Uuid::new_v4());
+        assert_eq!(state(&trade_books, &ask_id), (OrderStatus::Filled, dec!(1.0), dec!(0)));
+    }
+
+    #[tokio::test]
+    async fn test_self_trade_prevention_report() {
+        let (order_tx, mut order_rx) = mpsc::channel::<QueuedOrder>(100);
+        let initial: HashMap<String, Book> = HashMap::from([("XXBTZUSD".to_string(), Book::new())]);
+        let service = Arc::new(OrderBookService {
+            self_trade_prevention: HashMap::from([("trader1".to_string(), SelfTradePrevention::CancelNewest)]),
+            ..OrderBookService::new(initial, order_tx)
+        });
+        let engine = Arc::clone(&service);
+        tokio::spawn(async move {
+            while let Some(QueuedOrder { order, reply, .. }) = order_rx.recv().await {
+                let report = process_order(&engine, order.id, order.request, None).await;
+                if let Some(reply) = reply {
+                    let _ = reply.send(report);
+                }
+            }
+        });
+        let order = |side: orderbook::Side, self_trade_prevention: orderbook::SelfTradePrevention| {
+            Request::new(OrderRequest {
+                trader: "trader1".to_string(),
+                pair: "XXBTZUSD".to_string(),
+                price: "100".to_string(),
+                volume: "1".to_string(),
+                side: side.into(),
+                order_type: orderbook::OrderType::Limit.into(),
+                self_trade_prevention: self_trade_prevention.into(),
+                ..Default::default()
+            })
+        };
+        service.place_market_order(order(orderbook::Side::Sell, orderbook::SelfTradePrevention::Unspecified)).await.unwrap();
+
+        // The trader's configured mode applies when the order has none
+        let response = service
+            .place_market_order(order(orderbook::Side::Buy, orderbook::SelfTradePrevention::Unspecified)).await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::StpCanceled);
+        let report = response.report.unwrap();
+        assert_eq!((report.stp_canceled_volume.as_str(), report.remaining_volume.as_str()), ("1", "0"));
+        let details = service
+            .get_order(Request::new(GetOrderRequest { order_id: response.order_id })).await
+            .unwrap()
+            .into_inner();
+        assert_eq!((details.status(), details.stp_canceled_volume.as_str()), (orderbook::OrderStatus::StpCanceled, "1"));
+
+        // An explicit mode on the order overrides it
+        let response = service
+            .place_market_order(order(orderbook::Side::Buy, orderbook::SelfTradePrevention::None)).await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::Filled);
+        assert_eq!(response.report.unwrap().stp_canceled_volume, "0");
+    }
+
+    #[tokio::test]
+    async fn test_post_only_and_reduce_only() {
+        let (order_tx, mut order_rx) = mpsc::channel::<QueuedOrder>(100);
+        let initial: HashMap<String, Book> = HashMap::from([
+            ("XXBTZUSD".to_string(), vec![book_order(dec!(100.0), dec!(5.0), Side::Sell), book_order(dec!(99.0), dec!(5.0), Side::Buy)]
+                .into_iter()
+                .collect()),
+        ]);
+        let service = Arc::new(OrderBookService {
+            instruments: RwLock::new(HashMap::from([("XXBTZUSD".to_string(), Instrument { tick_size: dec!(0.5), ..Default::default() })])),
+            ..OrderBookService::new(initial, order_tx)
+        });
+        let engine = Arc::clone(&service);
+        tokio::spawn(async move {
+            while let Some(QueuedOrder { order, reply, .. }) = order_rx.recv().await {
+                let report = process_order(&engine, order.id, order.request, None).await;
+                if let Some(reply) = reply {
+                    let _ = reply.send(report);
+                }
+            }
+        });
+        let order = |trader: &str, side: orderbook::Side, order_type: orderbook::OrderType, price: &str, volume: &str| OrderRequest {
+            trader: trader.to_string(),
+            pair: "XXBTZUSD".to_string(),
+            price: price.to_string(),
+            volume: volume.to_string(),
+            side: side.into(),
+            order_type: order_type.into(),
+            ..Default::default()
+        };
+        let post_only = |price: &str, post_only: orderbook::PostOnly| OrderRequest {
+            post_only: post_only.into(),
+            ..order("maker", orderbook::Side::Buy, orderbook::OrderType::Limit, price, "1")
+        };
+        let place = |request: OrderRequest| {
+            let service = Arc::clone(&service);
+            async move { service.place_market_order(Request::new(request)).await }
+        };
+
+        // Crossing post-only orders are rejected with the reason, or rest one tick behind the best ask
+        let response = place(post_only("100", orderbook::PostOnly::Reject)).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::Rejected);
+        assert_eq!(response.report.unwrap().reject_reason, "post-only order at 100 would take liquidity at 100");
+        let details = service.get_order(Request::new(GetOrderRequest { order_id: response.order_id })).await.unwrap().into_inner();
+        assert_eq!((details.status(), details.reject_reason.is_empty()), (orderbook::OrderStatus::Rejected, false));
+        let response = place(post_only("100.5", orderbook::PostOnly::Reprice)).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::Open);
+        let repriced: Uuid = Uuid::parse_str(&response.order_id).unwrap();
+        assert_eq!(service.order_books.lock().await["XXBTZUSD"].get(&repriced).unwrap().price, dec!(99.5));
+        let details = service.get_order(Request::new(GetOrderRequest { order_id: response.order_id })).await.unwrap().into_inner();
+        assert_eq!(details.price, "99.5");
+
+        // Only resting limit orders can be post-only
+        let status = place(OrderRequest { post_only: orderbook::PostOnly::Reject.into(), ..order("maker", orderbook::Side::Buy, orderbook::OrderType::Market, "", "1") })
+            .await
+            .unwrap_err();
+        assert_eq!(status.message(), "post_only requires a limit order");
+        let status = place(OrderRequest { time_in_force: orderbook::TimeInForce::Ioc.into(), ..post_only("98", orderbook::PostOnly::Reject) })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        // Replacing a post-only order across the book fails and leaves it resting
+        let response = place(post_only("98", orderbook::PostOnly::Reject)).await.unwrap().into_inner();
+        let status = service.replace_order(Request::new(ReplaceOrderRequest {
+            order_id: response.order_id.clone(),
+            trader: "maker".to_string(),
+            price: "100".to_string(),
+            volume: String::new(),
+        })).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let resting: Uuid = Uuid::parse_str(&response.order_id).unwrap();
+        assert_eq!(service.order_books.lock().await["XXBTZUSD"].get(&resting).unwrap().price, dec!(98));
+
+        // Reduce-only orders need a position on the other side, at most its size
+        let reduce_only = |side: orderbook::Side, volume: &str| OrderRequest {
+            reduce_only: true,
+            ..order("desk", side, orderbook::OrderType::Limit, "101", volume)
+        };
+        let response = place(reduce_only(orderbook::Side::Sell, "1")).await.unwrap().into_inner();
+        assert_eq!(response.report.unwrap().reject_reason, "reduce-only order would increase the position of 0");
+        place(order("desk", orderbook::Side::Buy, orderbook::OrderType::Market, "", "1.5")).await.unwrap();
+        assert_eq!(service.trade_books.lock().await["desk"].position("XXBTZUSD"), dec!(1.5));
+        let response = place(reduce_only(orderbook::Side::Sell, "2")).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::Rejected);
+        assert_eq!(response.message, "order rejected: reduce-only order volume 2 exceeds the position of 1.5");
+        let response = place(OrderRequest { price: "102".to_string(), ..reduce_only(orderbook::Side::Buy, "1") }).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::Rejected);
+        let response = place(reduce_only(orderbook::Side::Sell, "1.5")).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::Open);
+    }
+
+    #[tokio::test]
+    async fn test_iceberg_orders() {
+        let (order_tx, mut order_rx) = mpsc::channel::<QueuedOrder>(100);
+        let initial: HashMap<String, Book> = HashMap::from([("XXBTZUSD".to_string(), Book::new())]);
+        let service = Arc::new(OrderBookService::new(initial, order_tx));
+        let engine = Arc::clone(&service);
+        tokio::spawn(async move {
+            while let Some(QueuedOrder { order, reply, .. }) = order_rx.recv().await {
+                let report = process_order(&engine, order.id, order.request, None).await;
+                if let Some(reply) = reply {
+                    let _ = reply.send(report);
+                }
+            }
+        });
+        let order = |trader: &str, side: orderbook::Side, order_type: orderbook::OrderType, volume: &str, display_volume: &str| {
+            Request::new(OrderRequest {
+                trader: trader.to_string(),
+                pair: "XXBTZUSD".to_string(),
+                price: if order_type == orderbook::OrderType::Limit { "100".to_string() } else { String::new() },
+                volume: volume.to_string(),
+                side: side.into(),
+                order_type: order_type.into(),
+                display_volume: display_volume.to_string(),
+                ..Default::default()
+            })
+        };
+        let depth = || {
+            let service = Arc::clone(&service);
+            async move {
+                let request = Request::new(DepthRequest { pair: "XXBTZUSD".to_string(), depth: 0, group: String::new() });
+                let asks = service.get_depth(request).await.unwrap().into_inner().asks;
+                (asks[0].volume.clone(), asks[0].order_count)
+            }
+        };
+
+        // Only the displayed slice of the iceberg is visible, ahead of the other trader's ask
+        let iceberg = service
+            .place_market_order(order("algo", orderbook::Side::Sell, orderbook::OrderType::Limit, "5", "1")).await
+            .unwrap()
+            .into_inner()
+            .order_id;
+        let other = service
+            .place_market_order(order("other", orderbook::Side::Sell, orderbook::OrderType::Limit, "1", "")).await
+            .unwrap()
+            .into_inner()
+            .order_id;
+        assert_eq!(depth().await, ("2".to_string(), 2));
+        let visible = service.get_order_book(Request::new(OrderBookRequest { pair: "XXBTZUSD".to_string() })).await.unwrap().into_inner();
+        let volumes: Vec<&str> = visible.orders.iter().map(|o| o.volume.as_str()).collect();
+        assert_eq!(volumes, vec!["1", "1"]);
+
+        // A filled slice is replenished at the back of the level, behind the other ask
+        let response = service
+            .place_market_order(order("taker", orderbook::Side::Buy, orderbook::OrderType::Market, "1.5", "")).await
+            .unwrap()
+            .into_inner();
+        let makers: Vec<String> = response.report.unwrap().fills.iter().map(|f| f.maker_order_id.clone()).collect();
+        assert_eq!(makers, vec![iceberg.clone(), other.clone()]);
+        assert_eq!(depth().await, ("1.5".to_string(), 2));
+        let details = service.get_order(Request::new(GetOrderRequest { order_id: iceberg.clone() })).await.unwrap().into_inner();
+        assert_eq!((details.remaining_volume.as_str(), details.display_volume.as_str()), ("4", "1"));
+
+        // The hidden reserve is taken slice by slice once the level is reached
+        let response = service
+            .place_market_order(order("taker", orderbook::Side::Buy, orderbook::OrderType::Market, "10", "")).await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.report.unwrap().filled_volume, "4.5");
+        let details = service.get_order(Request::new(GetOrderRequest { order_id: iceberg })).await.unwrap().into_inner();
+        assert_eq!(details.status(), orderbook::OrderStatus::Filled);
+
+        // Canceling an iceberg cancels its reserve too
+        let response = service
+            .place_market_order(order("algo", orderbook::Side::Sell, orderbook::OrderType::Limit, "3", "1")).await
+            .unwrap()
+            .into_inner();
+        let response = service.cancel_order(Request::new(CancelOrderRequest {
+            order_id: response.order_id,
+            trader: "algo".to_string(),
+        })).await.unwrap().into_inner();
+        assert_eq!(response.message, "order canceled, remaining volume: 3");
+
+        // Icebergs are resting limit orders with a reserve
+        for (order_type, volume, display_volume) in [(orderbook::OrderType::Market, "3", "1"), (orderbook::OrderType::Limit, "1", "1")] {
+            let status = service
+                .place_market_order(order("algo", orderbook::Side::Sell, order_type, volume, display_volume)).await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn test_exact_decimal_fills() {
+        let ask = book_order(dec!(65294.5), dec!(1.537), Side::Sell);
+        let mut book: Book = vec![ask.clone()].into_iter().collect();
+        let mut trade_books: HashMap<String, TradeBook> = HashMap::new();
+
+        // Partial fills leave exactly the difference (no float artifacts like 1.1369999999999991)
+        for _ in 0..4 {
+            let market_order = NewOrder {
+                trader: "trader1".to_string(),
+                pair: "XXBTZUSD".to_string(),
+                volume: dec!(0.1),
+                side: Side::Buy,
+                order_type: OrderType::Market,
+                ..Default::default()
+            };
+            execute(&mut book, &mut trade_books, &market_order, Uuid::new_v4());
+        }
+        assert_eq!(book.get(&ask.id).unwrap().volume.to_string(), "1.137");
+        let filled: Decimal = trade_books["trader1"].fills().iter().map(|f| f.volume).sum();
+        assert_eq!(filled, dec!(0.4));
+    }
+
+    #[test]
+    fn test_parse_order_scale() {
+        let instrument = Instrument { price_scale: 1, volume_scale: 3, ..Default::default() };
+        let request = |price: &str, volume: &str| OrderRequest {
+            pair: "XXBTZUSD".to_string(),
+            price: price.to_string(),
+            volume: volume.to_string(),
+            side: orderbook::Side::Buy.into(),
+            order_type: orderbook::OrderType::Limit.into(),
+            ..Default::default()
+        };
+        let parse = |request: &OrderRequest| parse_order(request, instrument, NaiveTime::MIN, Utc::now());
+
+        let order = parse(&request("65290.10", "0.010")).unwrap();
+        assert_eq!((order.price.to_string(), order.volume.to_string()), ("65290.1".to_string(), "0.01".to_string()));
+        assert_eq!(order.stop_price, Decimal::ZERO);
+
+        // More decimal places than the pair allows, negative or malformed values are rejected
+        assert!(parse(&request("65290.15", "1")).is_err());
+        assert!(parse(&request("65290.1", "0.0001")).is_err());
+        assert!(parse(&request("65290.1", "-1")).is_err());
+        assert!(parse(&request("65,290.1", "1")).is_err());
+
+        // Unknown or missing enum values are rejected rather than defaulted
+        assert!(parse(&OrderRequest { side: 7, ..request("65290.1", "1") }).is_err());
+        assert!(parse(&OrderRequest { side: 0, ..request("65290.1", "1") }).is_err());
+        assert!(parse(&OrderRequest { order_type: 9, ..request("65290.1", "1") }).is_err());
+        assert!(parse(&OrderRequest { time_in_force: 42, ..request("65290.1", "1") }).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_order() {
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let service = Arc::new(OrderBookService::new(HashMap::new(), order_tx));
+        let order_books = service.order_books.clone();
+        let trade_books = service.trade_books.clone();
+
+        // Resting limit order placed by trader1
+        let order_id = place_order(&service, "trader1", Side::Buy, OrderType::Limit, dec!(49000.0), dec!(1.0)).await;
+
+        // Another trader cannot cancel it
+        let request = Request::new(CancelOrderRequest {
+            order_id: order_id.to_string(),
+            trader: "trader2".to_string(),
+        });
+        let status = service.cancel_order(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        let request = Request::new(CancelOrderRequest {
+            order_id: order_id.to_string(),
+            trader: "trader1".to_string(),
+        });
+        let response = service.cancel_order(request).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::Canceled);
+        assert_eq!(response.order_id, order_id.to_string());
+        assert!(order_books.lock().await["XXBTZUSD"].get(&order_id).is_none());
+
+        let trade_book = trade_books.lock().await["trader1"].clone();
+        let canceled = trade_book.order(&order_id).unwrap();
+        assert_eq!(canceled.status, OrderStatus::Canceled);
+        assert_eq!((canceled.volume, canceled.open_volume), (dec!(1.0), dec!(0.0)));
+        assert_eq!(canceled.transitions.last().unwrap().status, OrderStatus::Canceled);
+
+        // Canceling twice fails as the order is no longer in the book
+        let request = Request::new(CancelOrderRequest {
+            order_id: order_id.to_string(),
+            trader: "trader1".to_string(),
+        });
+        let status = service.cancel_order(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    }
+
+    // Record the order as the matching engine would and run it against the book
+    async fn place_order(
+        service: &Arc<OrderBookService>,
+        trader: &str,
+        side: Side,
+        order_type: OrderType,
+        price: Decimal,
+        volume: Decimal
+    ) -> Uuid {
+        let order_id = Uuid::new_v4();
+        let request = NewOrder {
+            trader: trader.to_string(),
+            pair: "XXBTZUSD".to_string(),
+            price,
+            volume,
+            side,
+            order_type,
+            ..Default::default()
+        };
+        let mut order_books = service.order_books.lock().await;
+        let mut trade_books = service.trade_books.lock().await;
+        let book = order_books.entry(request.pair.clone()).or_default();
+        execute(book, &mut trade_books, &request, order_id);
+        order_id
+    }
+
+    #[tokio::test]
+    async fn test_order_lookup() {
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let service = Arc::new(OrderBookService::new(HashMap::new(), order_tx));
+
+        // Partially filled resting order, an untouched one and a fully filled one
+        let partial = place_order(&service, "trader1", Side::Sell, OrderType::Limit, dec!(101.0), dec!(2.0)).await;
+        let open = place_order(&service, "trader1", Side::Buy, OrderType::Limit, dec!(99.0), dec!(1.0)).await;
+        let filled = place_order(&service, "trader2", Side::Buy, OrderType::Limit, dec!(101.0), dec!(0.5)).await;
+        let canceled = place_order(&service, "trader1", Side::Buy, OrderType::Limit, dec!(98.0), dec!(1.0)).await;
+        service.cancel_order(Request::new(CancelOrderRequest {
+            order_id: canceled.to_string(),
+            trader: "trader1".to_string(),
+        })).await.unwrap();
+
+        let get = |order_id: Uuid| {
+            let service = Arc::clone(&service);
+            async move {
+                service.get_order(Request::new(GetOrderRequest { order_id: order_id.to_string() })).await
+            }
+        };
+        let details = get(partial).await.unwrap().into_inner();
+        assert_eq!(details.status(), orderbook::OrderStatus::PartiallyFilled);
+        assert_eq!((details.trader.as_str(), details.side(), details.price.as_str()), ("trader1", orderbook::Side::Sell, "101"));
+        assert_eq!((details.original_volume.as_str(), details.remaining_volume.as_str()), ("2", "1.5"));
+        assert_eq!(get(open).await.unwrap().into_inner().status(), orderbook::OrderStatus::Open);
+        let details = get(filled).await.unwrap().into_inner();
+        assert_eq!((details.status(), details.filled_volume.as_str()), (orderbook::OrderStatus::Filled, "0.5"));
+        let details = get(canceled).await.unwrap().into_inner();
+        assert_eq!((details.status(), details.remaining_volume.as_str()), (orderbook::OrderStatus::Canceled, "0"));
+        assert_eq!(details.transitions.last().unwrap().status(), orderbook::OrderStatus::Canceled);
+        assert_eq!(get(Uuid::new_v4()).await.unwrap_err().code(), tonic::Code::NotFound);
+
+        // Stop orders waiting for their trigger are listed as pending
+        let stop = Uuid::new_v4();
+        process_order(&service, stop, NewOrder {
+            trader: "trader1".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            volume: dec!(1.0),
+            side: Side::Sell,
+            order_type: OrderType::Stop,
+            stop_price: dec!(95.0),
+            ..Default::default()
+        }, None).await;
+        let list = |trader: &str, pair: &str| Request::new(OpenOrdersRequest {
+            trader: trader.to_string(),
+            pair: pair.to_string(),
+        });
+        let orders = service.list_open_orders(list("trader1", "")).await.unwrap().into_inner().orders;
+        let listed: Vec<(String, orderbook::OrderStatus)> = orders.iter().map(|o| (o.order_id.clone(), o.status())).collect();
+        assert_eq!(listed, vec![
+            (partial.to_string(), orderbook::OrderStatus::PartiallyFilled),
+            (open.to_string(), orderbook::OrderStatus::Open),
+            (stop.to_string(), orderbook::OrderStatus::Pending)
+        ]);
+        assert_eq!(orders[2].stop_price, "95");
+        assert!(service.list_open_orders(list("trader2", "XXBTZUSD")).await.unwrap().into_inner().orders.is_empty());
+        assert_eq!(service.list_open_orders(list("trader1", "XETHZUSD")).await.unwrap_err().code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_replace_order_priority() {
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let service = Arc::new(OrderBookService::new(HashMap::new(), order_tx));
+
+        let first = place_order(&service, "trader1", Side::Buy, OrderType::Limit, dec!(100.0), dec!(1.0)).await;
+        let second = place_order(&service, "trader2", Side::Buy, OrderType::Limit, dec!(100.0), dec!(1.0)).await;
+        place_order(&service, "trader3", Side::Sell, OrderType::Limit, dec!(105.0), dec!(1.0)).await;
+
+        let replace = |price: &str, volume: &str| Request::new(ReplaceOrderRequest {
+            order_id: first.to_string(),
+            trader: "trader1".to_string(),
+            price: price.to_string(),
+            volume: volume.to_string(),
+        });
+
+        // Reducing volume keeps the place in the queue
+        let response = service.replace_order(replace("", "0.5")).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::Replaced);
+        {
+            let order_books = service.order_books.lock().await;
+            let best = order_books["XXBTZUSD"].best(Side::Buy).unwrap();
+            assert_eq!((best.id, best.volume), (first, dec!(0.5)));
+        }
+
+        // Increasing volume loses priority
+        service.replace_order(replace("0", "2.0")).await.unwrap();
+        assert_eq!(service.order_books.lock().await["XXBTZUSD"].best(Side::Buy).unwrap().id, second);
+
+        // A new price that crosses the book matches straight away
+        let response = service.replace_order(replace("105.0", "")).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::Replaced);
+        let order_books = service.order_books.lock().await;
+        assert_eq!(order_books["XXBTZUSD"].get(&first).unwrap().volume, dec!(1.0));
+        assert_eq!(order_books["XXBTZUSD"].best_ask(), None);
+
+        let trade_books = service.trade_books.lock().await;
+        let record = trade_books["trader1"].order(&first).unwrap();
+        let statuses: Vec<OrderStatus> = record.transitions.iter().map(|t| t.status).collect();
+        assert_eq!(statuses, vec![
+            OrderStatus::New, OrderStatus::Open,
+            OrderStatus::PendingReplace, OrderStatus::Replaced,
+            OrderStatus::PendingReplace, OrderStatus::Replaced,
+            OrderStatus::PendingReplace, OrderStatus::PartiallyFilled, OrderStatus::Replaced
+        ]);
+        let volumes: Vec<Decimal> = record.transitions.iter().map(|t| t.open_volume).collect();
+        assert_eq!(volumes[3..].to_vec(), vec![dec!(0.5), dec!(2.0), dec!(2.0), dec!(2.0), dec!(1.0), dec!(1.0)]);
+        assert_eq!((record.price, record.volume, record.filled_volume), (dec!(105.0), dec!(2.0), dec!(1.0)));
+    }
+
+    #[test]
+    fn test_stop_orders_trigger() {
+        let mut book: Book = vec![
+            book_order(dec!(100.0), dec!(1.0), Side::Sell),
+            book_order(dec!(101.0), dec!(1.0), Side::Sell),
+            book_order(dec!(99.0), dec!(1.0), Side::Buy),
+            book_order(dec!(98.0), dec!(1.0), Side::Buy)
+        ]
+            .into_iter()
+            .collect();
+        let mut trade_books: HashMap<String, TradeBook> = HashMap::new();
+        let stop_request = |side: Side, order_type: OrderType, price: Decimal, stop_price: Decimal| NewOrder {
+            trader: "trader1".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            price,
+            volume: dec!(0.5),
+            side,
+            order_type,
+            stop_price,
+            ..Default::default()
+        };
+        let buy_stop = Uuid::new_v4();
+        let sell_stop = Uuid::new_v4();
+        let mut stop_orders = vec![
+            PendingOrder { id: buy_stop, request: stop_request(Side::Buy, OrderType::Stop, Decimal::ZERO, dec!(100.5)) },
+            PendingOrder { id: sell_stop, request: stop_request(Side::Sell, OrderType::StopLimit, dec!(98.5), dec!(99.0)) }
+        ];
+        for stop in stop_orders.iter() {
+            let record: OrderRecord = OrderRecord::new(stop.id, &stop.request, Utc::now().to_rfc3339());
+            trade_books.entry(stop.request.trader.clone()).or_default().add_order(record);
+        }
+
+        // Nothing traded yet, so nothing can trigger on the last trade price
+        trigger_stop_orders(&mut book, "XXBTZUSD", &mut stop_orders, &mut trade_books, StopTrigger::LastTrade, &Instrument::default());
+        assert_eq!(stop_orders.len(), 2);
+
+        // On best quotes the sell stop triggers (best bid 99.0) and rests as a limit at 98.5
+        // after taking the 99.0 bid, while the buy stop waits for the ask to reach 100.5
+        trigger_stop_orders(&mut book, "XXBTZUSD", &mut stop_orders, &mut trade_books, StopTrigger::BestQuote, &Instrument::default());
+        assert_eq!(stop_orders.len(), 1);
+        assert_eq!(book.last_price(), Some(dec!(99.0)));
+        assert_eq!(book.get(&sell_stop), None);
+
+        // A buy lifting the 100.0 level makes the last trade 101.0 and fires the buy stop
+        let buyer = NewOrder {
+            trader: "trader2".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            volume: dec!(1.5),
+            side: Side::Buy,
+            order_type: OrderType::Market,
+            ..Default::default()
+        };
+        execute(&mut book, &mut trade_books, &buyer, Uuid::new_v4());
+        trigger_stop_orders(&mut book, "XXBTZUSD", &mut stop_orders, &mut trade_books, StopTrigger::LastTrade, &Instrument::default());
+        assert!(stop_orders.is_empty());
+        assert_eq!(book.best_ask(), None);
+
+        // Both stops were triggered, then the released orders matched
+        let transitions = |id: &Uuid| -> Vec<OrderStatus> {
+            trade_books["trader1"].order(id).unwrap().transitions.iter().map(|t| t.status).collect()
+        };
+        assert_eq!(transitions(&sell_stop), vec![OrderStatus::New, OrderStatus::Triggered, OrderStatus::Filled]);
+        assert_eq!(transitions(&buy_stop), vec![OrderStatus::New, OrderStatus::Triggered, OrderStatus::Filled]);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stop_order() {
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let service = Arc::new(OrderBookService::new(HashMap::new(), order_tx));
+
+        // Stop order parked in the trigger book by the matching engine
+        service.order_books.lock().await.insert("XXBTZUSD".to_string(), Book::new());
+        let order_id = Uuid::new_v4();
+        process_order(&service, order_id, NewOrder {
+            trader: "trader1".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            volume: dec!(1.0),
+            side: Side::Sell,
+            order_type: OrderType::Stop,
+            stop_price: dec!(95.0),
+            ..Default::default()
+        }, None).await;
+        assert_eq!(service.stop_books.lock().await["XXBTZUSD"].len(), 1);
+
+        let request = Request::new(CancelOrderRequest {
+            order_id: order_id.to_string(),
+            trader: "trader1".to_string(),
+        });
+        let response = service.cancel_order(request).await.unwrap().into_inner();
+        assert_eq!(response.status(), orderbook::OrderStatus::Canceled);
+        assert!(service.stop_books.lock().await["XXBTZUSD"].is_empty());
+        assert_eq!(service.trade_books.lock().await["trader1"].order(&order_id).unwrap().status, OrderStatus::Canceled);
+    }
+
+    #[tokio::test]
+    async fn test_trailing_stop_orders() {
+        let book: Book = vec![
+            book_order(dec!(100.0), dec!(5.0), Side::Sell),
+            book_order(dec!(101.0), dec!(5.0), Side::Sell),
+            book_order(dec!(99.0), dec!(2.0), Side::Buy)
+        ]
+            .into_iter()
+            .collect();
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let service = Arc::new(OrderBookService::new(HashMap::from([("XXBTZUSD".to_string(), book)]), order_tx));
+        let order = |trader: &str, side: Side, order_type: OrderType, volume: Decimal| NewOrder {
+            trader: trader.to_string(),
+            pair: "XXBTZUSD".to_string(),
+            volume,
+            side,
+            order_type,
+            trailing_offset: (order_type == OrderType::TrailingStop).then_some(TrailingOffset::Absolute(dec!(2))),
+            ..Default::default()
+        };
+
+        // The stop price starts at the offset below the last trade and only moves up with it
+        process_order(&service, Uuid::new_v4(), order("trader2", Side::Buy, OrderType::Market, dec!(1.0)), None).await;
+        let stop_id = Uuid::new_v4();
+        let report = process_order(&service, stop_id, order("trader1", Side::Sell, OrderType::TrailingStop, dec!(0.5)), None).await;
+        assert_eq!(report.status, OrderStatus::Pending);
+        process_order(&service, Uuid::new_v4(), order("trader2", Side::Buy, OrderType::Market, dec!(5.0)), None).await;
+        assert_eq!(service.trade_books.lock().await["trader1"].order(&stop_id).unwrap().stop_price, dec!(99));
+
+        // The trigger price changes are part of the order lookup
+        let details = service
+            .get_order(Request::new(GetOrderRequest { order_id: stop_id.to_string() })).await
+            .unwrap()
+            .into_inner();
+        assert_eq!((details.stop_price.as_str(), details.trailing_offset.as_str()), ("99", "2"));
+        let stop_prices: Vec<&str> = details.transitions.iter().map(|t| t.stop_price.as_str()).collect();
+        assert_eq!(stop_prices, vec!["0", "98", "99"]);
+
+        // A trade at the stop price fires it as a market order, with the trigger price in the ledger
+        process_order(&service, Uuid::new_v4(), order("trader2", Side::Sell, OrderType::Market, dec!(1.0)), None).await;
+        assert!(service.stop_books.lock().await["XXBTZUSD"].is_empty());
+        let trade_books = service.trade_books.lock().await;
+        let record = trade_books["trader1"].order(&stop_id).unwrap();
+        let fired: Vec<(OrderStatus, Decimal)> = record.transitions[3..].iter().map(|t| (t.status, t.stop_price)).collect();
+        assert_eq!(fired, vec![(OrderStatus::Triggered, dec!(99)), (OrderStatus::Filled, dec!(99))]);
+        assert_eq!(trade_books["trader1"].fills()[0].price, dec!(99.0));
+        drop(trade_books);
+
+        // Percentage offsets on best quotes follow Kraken refreshes, rounded onto the tick
+        let mut book: Book = vec![book_order(dec!(200.0), dec!(1.0), Side::Sell)].into_iter().collect();
+        let mut trade_books: HashMap<String, TradeBook> = HashMap::new();
+        let instrument = Instrument { tick_size: dec!(0.5), ..Default::default() };
+        let buy_stop = NewOrder {
+            trailing_offset: Some(TrailingOffset::Percent(dec!(1))),
+            ..order("trader1", Side::Buy, OrderType::TrailingStop, dec!(0.5))
+        };
+        let buy_id = Uuid::new_v4();
+        trade_books.entry("trader1".to_string()).or_default().add_order(OrderRecord::new(buy_id, &buy_stop, Utc::now().to_rfc3339()));
+        let mut stop_orders = vec![PendingOrder { id: buy_id, request: buy_stop }];
+        trigger_stop_orders(&mut book, "XXBTZUSD", &mut stop_orders, &mut trade_books, StopTrigger::BestQuote, &instrument);
+        assert_eq!(stop_orders[0].request.stop_price, dec!(202));
+        for (ask, stop_price) in [(dec!(150.0), dec!(151.5)), (dec!(151.0), dec!(151.5))] {
+            book.refresh(vec![book_order(ask, dec!(1.0), Side::Sell)].into_iter().collect());
+            trigger_stop_orders(&mut book, "XXBTZUSD", &mut stop_orders, &mut trade_books, StopTrigger::BestQuote, &instrument);
+            assert_eq!(stop_orders[0].request.stop_price, stop_price);
+        }
+        book.refresh(vec![book_order(dec!(152.0), dec!(1.0), Side::Sell)].into_iter().collect());
+        trigger_stop_orders(&mut book, "XXBTZUSD", &mut stop_orders, &mut trade_books, StopTrigger::BestQuote, &instrument);
+        assert!(stop_orders.is_empty());
+        assert_eq!(trade_books["trader1"].order(&buy_id).unwrap().status, OrderStatus::Filled);
+
+        // The offset replaces the stop price and only applies to trailing stops
+        let trailing = |order_type: orderbook::OrderType, stop_price: &str, trailing_offset: &str| Request::new(OrderRequest {
+            trader: "trader1".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            volume: "1".to_string(),
+            side: orderbook::Side::Sell.into(),
+            order_type: order_type.into(),
+            price: "100".to_string(),
+            stop_price: stop_price.to_string(),
+            trailing_offset: trailing_offset.to_string(),
+            ..Default::default()
+        });
+        for request in [
+            trailing(orderbook::OrderType::TrailingStop, "", ""),
+            trailing(orderbook::OrderType::TrailingStop, "95", "2"),
+            trailing(orderbook::OrderType::TrailingStop, "", "100%"),
+            trailing(orderbook::OrderType::Limit, "", "2"),
+        ] {
+            assert_eq!(service.place_market_order(request).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_order_groups() {
+        let book: Book = vec![
+            book_order(dec!(105.0), dec!(1.0), Side::Sell),
+            book_order(dec!(99.0), dec!(1.0), Side::Buy),
+            book_order(dec!(89.0), dec!(5.0), Side::Buy)
+        ]
+            .into_iter()
+            .collect();
+        let (order_tx, mut order_rx) = mpsc::channel::<QueuedOrder>(100);
+        let service = Arc::new(OrderBookService::new(HashMap::from([("XXBTZUSD".to_string(), book)]), order_tx));
+        let engine = Arc::clone(&service);
+        tokio::spawn(async move {
+            while let Some(QueuedOrder { order, group, reply }) = order_rx.recv().await {
+                let report = process_order(&engine, order.id, order.request, group).await;
+                if let Some(reply) = reply {
+                    let _ = reply.send(report);
+                }
+            }
+        });
+        let leg = |trader: &str, side: orderbook::Side, order_type: orderbook::OrderType, price: &str, volume: &str| OrderRequest {
+            trader: trader.to_string(),
+            pair: "XXBTZUSD".to_string(),
+            side: side.into(),
+            order_type: order_type.into(),
+            price: if order_type == orderbook::OrderType::Limit { price.to_string() } else { String::new() },
+            stop_price: if order_type == orderbook::OrderType::Stop { price.to_string() } else { String::new() },
+            volume: volume.to_string(),
+            ..Default::default()
+        };
+        let market = |side: orderbook::Side, volume: &str| Request::new(leg("taker", side, orderbook::OrderType::Market, "", volume));
+        let statuses = |orders: &[OrderDetails]| -> Vec<(orderbook::OrderStatus, String)> {
+            orders.iter().map(|o| (o.status(), o.remaining_volume.clone())).collect()
+        };
+
+        // OCO: the take-profit rests, the stop-loss waits for its trigger, both carry the group id
+        let response = service.place_order_group(Request::new(OrderGroupRequest {
+            group_type: orderbook::OrderGroupType::Oco.into(),
+            entry: None,
+            take_profit: Some(leg("rock", orderbook::Side::Sell, orderbook::OrderType::Limit, "110", "2")),
+            stop_loss: Some(leg("rock", orderbook::Side::Sell, orderbook::OrderType::Stop, "90", "2")),
+        })).await.unwrap().into_inner();
+        let oco_id = response.group_id.clone();
+        let oco = response.response.unwrap();
+        assert_eq!(oco.status(), orderbook::OrderStatus::Open);
+        assert_eq!(oco.report.unwrap().group_id, oco_id);
+        assert_eq!(statuses(&response.orders), vec![
+            (orderbook::OrderStatus::Open, "2".to_string()),
+            (orderbook::OrderStatus::Pending, "2".to_string())
+        ]);
+        assert!(response.orders.iter().all(|o| o.group_id == oco_id));
+        let (take_profit, stop_loss) = (response.orders[0].order_id.clone(), response.orders[1].order_id.clone());
+
+        // A partial fill of the take-profit reduces the stop-loss by the same volume
+        service.place_market_order(market(orderbook::Side::Buy, "2")).await.unwrap();
+        let details = |order_id: &str| {
+            let service = Arc::clone(&service);
+            let order_id = order_id.to_string();
+            async move { service.get_order(Request::new(GetOrderRequest { order_id })).await.unwrap().into_inner() }
+        };
+        let reduced = details(&stop_loss).await;
+        assert_eq!((reduced.remaining_volume.as_str(), reduced.original_volume.as_str()), ("1", "1"));
+        assert_eq!(service.stop_books.lock().await["XXBTZUSD"][0].request.volume, dec!(1));
+
+        // The stop-loss fires on the drop through 90 and its fill cancels the rest of the take-profit
+        service.place_market_order(market(orderbook::Side::Sell, "2")).await.unwrap();
+        assert_eq!(details(&stop_loss).await.status(), orderbook::OrderStatus::Filled);
+        let canceled = details(&take_profit).await;
+        assert_eq!((canceled.status(), canceled.filled_volume.as_str()), (orderbook::OrderStatus::Canceled, "1"));
+        assert_eq!(service.order_books.lock().await["XXBTZUSD"].best_ask(), None);
+
+        // Bracket: the exits wait (as new) until the entry is done
+        let response = service.place_order_group(Request::new(OrderGroupRequest {
+            group_type: orderbook::OrderGroupType::Bracket.into(),
+            entry: Some(leg("algo", orderbook::Side::Buy, orderbook::OrderType::Limit, "100", "2")),
+            take_profit: Some(leg("algo", orderbook::Side::Sell, orderbook::OrderType::Limit, "120", "")),
+            stop_loss: Some(leg("algo", orderbook::Side::Sell, orderbook::OrderType::Stop, "80", "")),
+        })).await.unwrap().into_inner();
+        let bracket_id = response.group_id.clone();
+        assert_eq!(statuses(&response.orders), vec![
+            (orderbook::OrderStatus::Open, "2".to_string()),
+            (orderbook::OrderStatus::New, "2".to_string()),
+            (orderbook::OrderStatus::New, "2".to_string())
+        ]);
+        let entry = response.orders[0].order_id.clone();
+        let open_orders = service.list_open_orders(Request::new(OpenOrdersRequest {
+            trader: "algo".to_string(),
+            pair: String::new(),
+        })).await.unwrap().into_inner();
+        assert_eq!(open_orders.orders.len(), 1);
+
+        // Canceling the partially filled entry places the exits with the filled volume
+        service.place_market_order(market(orderbook::Side::Sell, "0.5")).await.unwrap();
+        service.cancel_order(Request::new(CancelOrderRequest { order_id: entry, trader: "algo".to_string() })).await.unwrap();
+        let trade_book = service.get_trade_book(Request::new(TradeBookRequest { trader: "algo".to_string() })).await.unwrap().into_inner();
+        assert_eq!(statuses(&trade_book.orders[1..]), vec![
+            (orderbook::OrderStatus::Open, "0.5".to_string()),
+            (orderbook::OrderStatus::Pending, "0.5".to_string())
+        ]);
+        let group = &trade_book.groups[0];
+        assert_eq!((group.group_id.as_str(), group.status(), group.volume.as_str()), (bracket_id.as_str(), orderbook::OrderGroupStatus::Active, "0.5"));
+
+        // Canceling one exit cancels the other and ends the group
+        service.cancel_order(Request::new(CancelOrderRequest {
+            order_id: group.take_profit_order_id.clone(),
+            trader: "algo".to_string(),
+        })).await.unwrap();
+        assert_eq!(details(&group.stop_loss_order_id).await.status(), orderbook::OrderStatus::Canceled);
+        let trade_book = service.get_trade_book(Request::new(TradeBookRequest { trader: "algo".to_string() })).await.unwrap().into_inner();
+        assert_eq!(trade_book.groups[0].status(), orderbook::OrderGroupStatus::Canceled);
+        let trade_book = service.get_trade_book(Request::new(TradeBookRequest { trader: "rock".to_string() })).await.unwrap().into_inner();
+        assert_eq!(trade_book.groups[0].status(), orderbook::OrderGroupStatus::Completed);
+        // Ended groups leave the pair's index of working groups
+        assert!(service.order_books.lock().await["XXBTZUSD"].working_groups().is_empty());
+
+        // Legs must fit together
+        for (take_profit, stop_loss) in [
+            (leg("rock", orderbook::Side::Sell, orderbook::OrderType::Limit, "110", "2"), leg("rock", orderbook::Side::Sell, orderbook::OrderType::Stop, "90", "1")),
+            (leg("rock", orderbook::Side::Sell, orderbook::OrderType::Market, "", "2"), leg("rock", orderbook::Side::Sell, orderbook::OrderType::Stop, "90", "2")),
+            (leg("rock", orderbook::Side::Sell, orderbook::OrderType::Limit, "110", "2"), leg("rock", orderbook::Side::Buy, orderbook::OrderType::Stop, "90", "2")),
+        ] {
+            let status = service.place_order_group(Request::new(OrderGroupRequest {
+                group_type: orderbook::OrderGroupType::Oco.into(),
+                entry: None,
+                take_profit: Some(take_profit),
+                stop_loss: Some(stop_loss),
+            })).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oco_legs_crossed_by_one_order() {
+        let book: Book = vec![book_order(dec!(95.0), dec!(1.0), Side::Buy)].into_iter().collect();
+        let (order_tx, mut order_rx) = mpsc::channel::<QueuedOrder>(100);
+        let service = Arc::new(OrderBookService::new(HashMap::from([("XXBTZUSD".to_string(), book)]), order_tx));
+        let engine = Arc::clone(&service);
+        tokio::spawn(async move {
+            while let Some(QueuedOrder { order, group, reply }) = order_rx.recv().await {
+                let report = process_order(&engine, order.id, order.request, group).await;
+                if let Some(reply) = reply {
+                    let _ = reply.send(report);
+                }
+            }
+        });
+        let order = |trader: &str, side: orderbook::Side, order_type: orderbook::OrderType, price: &str, stop_price: &str, volume: &str| OrderRequest {
+            trader: trader.to_string(),
+            pair: "XXBTZUSD".to_string(),
+            side: side.into(),
+            order_type: order_type.into(),
+            price: price.to_string(),
+            stop_price: stop_price.to_string(),
+            volume: volume.to_string(),
+            ..Default::default()
+        };
+
+        // Take-profit at 110 and a stop-limit that triggers at 95 and rests at 94
+        let response = service.place_order_group(Request::new(OrderGroupRequest {
+            group_type: orderbook::OrderGroupType::Oco.into(),
+            entry: None,
+            take_profit: Some(order("rock", orderbook::Side::Sell, orderbook::OrderType::Limit, "110", "", "1")),
+            stop_loss: Some(order("rock", orderbook::Side::Sell, orderbook::OrderType::StopLimit, "94", "95", "1")),
+        })).await.unwrap().into_inner();
+        let (take_profit, stop_loss) = (response.orders[0].order_id.clone(), response.orders[1].order_id.clone());
+        service.place_market_order(Request::new(order("taker", orderbook::Side::Sell, orderbook::OrderType::Market, "", "", "1"))).await.unwrap();
+        assert_eq!(service.order_books.lock().await["XXBTZUSD"].best_ask(), Some(dec!(94)));
+
+        // One buy crossing both legs fills the group volume once
+        let response = service.place_market_order(Request::new(order("taker", orderbook::Side::Buy, orderbook::OrderType::Limit, "120", "", "5"))).await.unwrap().into_inner();
+        assert_eq!(response.report.unwrap().filled_volume, "1");
+        let details = |order_id: &str| {
+            let service = Arc::clone(&service);
+            let order_id = order_id.to_string();
+            async move { service.get_order(Request::new(GetOrderRequest { order_id })).await.unwrap().into_inner() }
+        };
+        assert_eq!(details(&stop_loss).await.status(), orderbook::OrderStatus::Filled);
+        let canceled = details(&take_profit).await;
+        assert_eq!((canceled.status(), canceled.filled_volume.as_str()), (orderbook::OrderStatus::Canceled, "0"));
+        let trade_books = service.trade_books.lock().await;
+        assert_eq!(trade_books["rock"].position("XXBTZUSD"), dec!(-1));
+        assert_eq!(trade_books["rock"].groups()[0].status, GroupStatus::Completed);
+    }
+
+    #[test]
+    fn test_time_in_force_matching() {
+        let mut book: Book = vec![book_order(dec!(100.0), dec!(1.0), Side::Sell), book_order(dec!(101.0), dec!(1.0), Side::Sell)]
+            .into_iter()
+            .collect();
+        let mut trade_books: HashMap<String, TradeBook> = HashMap::new();
+        let limit_buy = |time_in_force: TimeInForce, price: Decimal, volume: Decimal| NewOrder {
+            trader: "trader1".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            price,
+            volume,
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            time_in_force,
+            ..Default::default()
+        };
+
+        // Fill-or-kill needing more than is available up to the limit price does not trade at all
+        let fok_id = Uuid::new_v4();
+        let remaining = execute(&mut book, &mut trade_books, &limit_buy(TimeInForce::Fok, dec!(100.5), dec!(1.5)), fok_id);
+        assert_eq!(remaining, dec!(1.5));
+        assert_eq!(book.len(), 2);
+        assert_eq!(trade_books["trader1"].order(&fok_id).unwrap().status, OrderStatus::Canceled);
+
+        // Immediate-or-cancel takes what crosses and drops the remainder instead of resting it
+        let ioc_id = Uuid::new_v4();
+        let remaining = execute(&mut book, &mut trade_books, &limit_buy(TimeInForce::Ioc, dec!(100.5), dec!(1.5)), ioc_id);
+        assert_eq!(remaining, dec!(0.5));
+        assert_eq!(book.get(&ioc_id), None);
+        assert_eq!(book.best_bid(), None);
+        let ioc = trade_books["trader1"].order(&ioc_id).unwrap();
+        let statuses: Vec<OrderStatus> = ioc.transitions.iter().map(|t| t.status).collect();
+        assert_eq!(statuses, vec![OrderStatus::New, OrderStatus::PartiallyFilled, OrderStatus::Canceled]);
+        assert_eq!((ioc.filled_volume, ioc.open_volume), (dec!(1.0), dec!(0.0)));
+
+        // Fill-or-kill with enough liquidity across levels trades completely
+        let remaining = execute(&mut book, &mut trade_books, &limit_buy(TimeInForce::Fok, dec!(101.0), dec!(1.0)), Uuid::new_v4());
+        assert_eq!(remaining, dec!(0.0));
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_time_in_force() {
+        let now = DateTime::parse_from_rfc3339("2024-06-19T21:00:00Z").unwrap().with_timezone(&Utc);
+        let session_close = NaiveTime::from_hms_opt(22, 0, 0).unwrap();
+        let resolve = |time_in_force: TimeInForce, expire_time: &str, session_close: NaiveTime| {
+            resolve_time_in_force(time_in_force, expire_time, session_close, now)
+        };
+
+        // Only GTD and DAY orders carry an expiry, a stray expire_time is ignored otherwise
+        assert_eq!(resolve(TimeInForce::Gtc, "2024-06-20T00:00:00Z", session_close), Ok(None));
+        assert_eq!(time_in_force_from_proto(0), Ok(TimeInForce::Gtc));
+
+        let day = resolve(TimeInForce::Day, "", session_close).unwrap().unwrap();
+        assert_eq!(day.to_rfc3339(), "2024-06-19T22:00:00+00:00");
+
+        // After the session close DAY orders run until the next day's close
+        let late_day = resolve(TimeInForce::Day, "", NaiveTime::from_hms_opt(20, 0, 0).unwrap()).unwrap().unwrap();
+        assert_eq!(late_day.to_rfc3339(), "2024-06-20T20:00:00+00:00");
+
+        assert!(resolve(TimeInForce::Gtd, "", session_close).is_err());
+        assert!(resolve(TimeInForce::Gtd, "2024-06-19T20:00:00Z", session_close).is_err());
+        assert!(time_in_force_from_proto(99).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_remove_expired_orders() {
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let service = Arc::new(OrderBookService::new(HashMap::new(), order_tx));
+        let now = Utc::now();
+
+        let gtd = place_order(&service, "trader1", Side::Buy, OrderType::Limit, dec!(100.0), dec!(1.0)).await;
+        let gtc = place_order(&service, "trader1", Side::Buy, OrderType::Limit, dec!(99.0), dec!(1.0)).await;
+        let mut order_books = service.order_books.lock().await;
+        let mut trade_books = service.trade_books.lock().await;
+        let book = order_books.get_mut("XXBTZUSD").unwrap();
+        book.get_mut(&gtd).unwrap().expire_time = Some(now - chrono::Duration::seconds(1));
+
+        let stop_id = Uuid::new_v4();
+        let mut stop_orders = vec![PendingOrder {
+            id: stop_id,
+            request: NewOrder {
+                trader: "trader2".to_string(),
+                pair: "XXBTZUSD".to_string(),
+                volume: dec!(1.0),
+                side: Side::Sell,
+                order_type: OrderType::Stop,
+                stop_price: dec!(95.0),
+                time_in_force: TimeInForce::Day,
+                expire_time: Some(now - chrono::Duration::seconds(1)),
+                ..Default::default()
+            },
+        }];
+        let record: OrderRecord = OrderRecord::new(stop_id, &stop_orders[0].request, now.to_rfc3339());
+        trade_books.entry("trader2".to_string()).or_default().add_order(record);
+
+        remove_expired_orders(book, &mut stop_orders, &mut trade_books, now);
+        assert_eq!(book.get(&gtd), None);
+        assert!(book.get(&gtc).is_some());
+        assert!(stop_orders.is_empty());
+
+        let expired = trade_books["trader1"].order(&gtd).unwrap();
+        assert_eq!((expired.status, expired.open_volume), (OrderStatus::Expired, dec!(0.0)));
+        assert_eq!(trade_books["trader1"].order(&gtc).unwrap().status, OrderStatus::Open);
+        assert_eq!(trade_books["trader2"].order(&stop_id).unwrap().status, OrderStatus::Expired);
+    }
+
+    #[test]
+    fn test_book_level_changes() {
+        let ask = book_order(dec!(100.0), dec!(1.0), Side::Sell);
+        let mut book: Book = vec![ask.clone(), book_order(dec!(100.0), dec!(2.0), Side::Sell), book_order(dec!(99.0), dec!(1.0), Side::Buy)]
+            .into_iter()
+            .collect();
+        let (sequence, changes) = book.take_changes().unwrap();
+        assert_eq!(sequence, 1);
+        assert_eq!(changes.len(), 2);
+        assert!(book.take_changes().is_none());
+
+        // Only the touched level is reported, with its aggregated state
+        book.remove(&ask.id);
+        let (sequence, changes) = book.take_changes().unwrap();
+        assert_eq!(sequence, 2);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].0, Side::Sell);
+        assert_eq!((changes[0].1.volume, changes[0].1.order_count), (dec!(2.0), 1));
+
+        // A refresh reports removed, new and changed levels but not unchanged ones
+        let fresh: Book = vec![book_order(dec!(100.0), dec!(2.0), Side::Sell), book_order(dec!(98.0), dec!(1.0), Side::Buy)]
+            .into_iter()
+            .collect();
+        book.refresh(fresh);
+        let (sequence, changes) = book.take_changes().unwrap();
+        assert_eq!(sequence, 3);
+        let levels: Vec<(Side, Decimal, Decimal)> = changes
+            .into_iter()
+            .map(|(side, level)| (side, level.price, level.volume))
+            .collect();
+        assert_eq!(levels, vec![(Side::Buy, dec!(98.0), dec!(1.0)), (Side::Buy, dec!(99.0), Decimal::ZERO)]);
+    }
+
+    #[test]
+    fn test_refresh_keeps_owned_orders() {
+        let owned = Order { trader: "trader1".to_string(), ..book_order(dec!(100.0), dec!(1.0), Side::Sell) };
+        let mut book: Book = vec![owned.clone(), book_order(dec!(100.0), dec!(2.0), Side::Sell), book_order(dec!(101.0), dec!(1.0), Side::Sell)]
+            .into_iter()
+            .collect();
+        book.take_changes();
+
+        // Kraken liquidity is replaced, the trader's order stays behind the fetched orders of its level
+        let fresh_ask = book_order(dec!(100.0), dec!(3.0), Side::Sell);
+        book.refresh(vec![fresh_ask.clone()].into_iter().collect());
+        let ids: Vec<Uuid> = book.iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![fresh_ask.id, owned.id]);
+        assert_eq!(book.depth(Side::Sell)[0].volume, dec!(4.0));
+        let (_, changes) = book.take_changes().unwrap();
+        assert_eq!(changes.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_order_book() {
+        use futures::StreamExt;
+
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let initial: HashMap<String, Book> = HashMap::from([
+            ("XXBTZUSD".to_string(), vec![book_order(dec!(100.0), dec!(1.0), Side::Sell), book_order(dec!(99.0), dec!(2.0), Side::Buy)]
+                .into_iter()
+                .collect()),
+        ]);
+        let service = Arc::new(OrderBookService::new(initial, order_tx));
+        {
+            let mut order_books = service.order_books.lock().await;
+            publish_market_data(&service, "XXBTZUSD", order_books.get_mut("XXBTZUSD").unwrap());
+        }
+
+        let request = Request::new(OrderBookRequest { pair: "XXBTZUSD".to_string() });
+        let mut stream = service.subscribe_order_book(request).await.unwrap().into_inner();
+
+        let snapshot = stream.next().await.unwrap().unwrap();
+        assert!(snapshot.snapshot);
+        assert_eq!(snapshot.sequence, 1);
+        assert_eq!((snapshot.asks[0].price.as_str(), snapshot.asks[0].volume.as_str()), ("100", "1"));
+        assert_eq!((snapshot.bids[0].price.as_str(), snapshot.bids[0].volume.as_str()), ("99", "2"));
+
+        // A match empties the ask level and is streamed as the next sequence
+        place_order(&service, "trader1", Side::Buy, OrderType::Market, dec!(0.0), dec!(1.0)).await;
+        {
+            let mut order_books = service.order_books.lock().await;
+            publish_market_data(&service, "XXBTZUSD", order_books.get_mut("XXBTZUSD").unwrap());
+        }
+        let update = stream.next().await.unwrap().unwrap();
+        assert!(!update.snapshot);
+        assert_eq!(update.sequence, 2);
+        assert!(update.bids.is_empty());
+        assert_eq!((update.asks[0].price.as_str(), update.asks[0].volume.as_str()), ("100", "0"));
+
+        let request = Request::new(OrderBookRequest { pair: "UNKNOWN".to_string() });
+        assert!(service.subscribe_order_book(request).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_trades() {
+        use futures::StreamExt;
+
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let service = Arc::new(OrderBookService::new(HashMap::new(), order_tx));
+        let maker = place_order(&service, "trader1", Side::Sell, OrderType::Limit, dec!(100.0), dec!(1.0)).await;
+
+        let request = Request::new(TradeStreamRequest { pair: "XXBTZUSD".to_string() });
+        let mut stream = service.subscribe_trades(request).await.unwrap().into_inner();
+
+        let taker = place_order(&service, "trader2", Side::Buy, OrderType::Market, dec!(0.0), dec!(0.4)).await;
+        {
+            let mut order_books = service.order_books.lock().await;
+            publish_market_data(&service, "XXBTZUSD", order_books.get_mut("XXBTZUSD").unwrap());
+        }
+
+        let trade = stream.next().await.unwrap().unwrap();
+        assert_eq!(trade.pair, "XXBTZUSD");
+        assert_eq!((trade.price.as_str(), trade.volume.as_str()), ("100", "0.4"));
+        assert_eq!(trade.aggressor_side(), orderbook::Side::Buy);
+        assert_eq!(trade.maker_order_id, maker.to_string());
+        assert_eq!(trade.taker_order_id, taker.to_string());
+        assert!(Uuid::parse_str(&trade.trade_id).is_ok());
+
+        let request = Request::new(TradeStreamRequest { pair: "UNKNOWN".to_string() });
+        assert!(service.subscribe_trades(request).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_call_auction() {
+        use futures::StreamExt;
+
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let mut book: Book = vec![book_order(dec!(103.0), dec!(1.0), Side::Sell)].into_iter().collect();
+        book.start_auction(Utc::now() + chrono::Duration::hours(1));
+        let service = Arc::new(OrderBookService::new(HashMap::from([("XXBTZUSD".to_string(), book)]), order_tx));
+
+        let request = Request::new(OrderBookRequest { pair: "XXBTZUSD".to_string() });
+        let mut stream = service.subscribe_auction(request).await.unwrap().into_inner();
+        let current = stream.next().await.unwrap().unwrap();
+        assert_eq!(current.phase(), orderbook::TradingPhase::Auction);
+        assert!(current.indicative_price.is_empty());
+
+        let order = |trader: &str, side: Side, order_type: OrderType, price: Decimal, volume: Decimal| NewOrder {
+            trader: trader.to_string(),
+            pair: "XXBTZUSD".to_string(),
+            price,
+            volume,
+            side,
+            order_type,
+            ..Default::default()
+        };
+
+        // Crossing orders rest without matching, the indicative price follows every change
+        let mut order_ids: Vec<Uuid> = Vec::new();
+        for (trader, side, price, volume) in [
+            ("seller", Side::Sell, dec!(100), dec!(3)),
+            ("buyer1", Side::Buy, dec!(102), dec!(2)),
+            ("buyer2", Side::Buy, dec!(101), dec!(2)),
+            ("seller", Side::Sell, dec!(101), dec!(1)),
+        ] {
+            let order_id = Uuid::new_v4();
+            let report = process_order(&service, order_id, order(trader, side, OrderType::Limit, price, volume), None).await;
+            assert_eq!(report.status, OrderStatus::Open);
+            assert!(report.fills.is_empty());
+            order_ids.push(order_id);
+        }
+        let mut update = stream.next().await.unwrap().unwrap();
+        assert!(update.indicative_price.is_empty());
+        for _ in 0..3 {
+            update = stream.next().await.unwrap().unwrap();
+        }
+        // 101 executes 4 with nothing left over, 100 only 3 and 102 only 2
+        assert_eq!(
+            (update.indicative_price.as_str(), update.indicative_volume.as_str(), update.surplus.as_str()),
+            ("101", "4", "0")
+        );
+
+        // Orders that can't rest are rejected
+        let report = process_order(&service, Uuid::new_v4(), order("buyer1", Side::Buy, OrderType::Market, dec!(0), dec!(1)), None).await;
+        assert_eq!(report.status, OrderStatus::Rejected);
+        assert_eq!(report.reject_reason, "market orders are not accepted during the call auction");
+
+        // Everything crossing executes at the single clearing price, the later order being the taker
+        {
+            let mut order_books = service.order_books.lock().await;
+            let mut trade_books = service.trade_books.lock().await;
+            let book = order_books.get_mut("XXBTZUSD").unwrap();
+            let equilibrium = uncross_auction(book, &mut trade_books, "XXBTZUSD", &Instrument::default()).unwrap();
+            assert_eq!((equilibrium.price, equilibrium.volume), (dec!(101), dec!(4)));
+            assert!(book.auction().is_none());
+            let fills: Vec<(Uuid, Uuid, Decimal, Decimal)> = book
+                .fills()
+                .iter()
+                .map(|fill| (fill.taker_order_id, fill.maker_order_id, fill.price, fill.volume))
+                .collect();
+            assert_eq!(fills, vec![
+                (order_ids[1], order_ids[0], dec!(101), dec!(2)),
+                (order_ids[2], order_ids[0], dec!(101), dec!(1)),
+                (order_ids[3], order_ids[2], dec!(101), dec!(1)),
+            ]);
+            assert_eq!(book.best(Side::Sell).unwrap().price, dec!(103));
+            assert!(book.best(Side::Buy).is_none());
+            for (trader, order_id) in [("seller", order_ids[0]), ("buyer1", order_ids[1]), ("buyer2", order_ids[2]), ("seller", order_ids[3])] {
+                assert_eq!(trade_books[trader].order(&order_id).unwrap().status, OrderStatus::Filled);
+            }
+            assert_eq!(trade_books["seller"].position("XXBTZUSD"), dec!(-4));
+        }
+
+        // Continuous trading resumes
+        let report = process_order(&service, Uuid::new_v4(), order("buyer1", Side::Buy, OrderType::Market, dec!(0), dec!(1)), None).await;
+        assert_eq!(report.status, OrderStatus::Filled);
+        assert_eq!(report.average_price, Some(dec!(103)));
+    }
+
+    #[tokio::test]
+    async fn test_call_auction_self_trade_prevention() {
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let mut book: Book = Book::new();
+        book.start_auction(Utc::now() + chrono::Duration::hours(1));
+        let service = Arc::new(OrderBookService::new(HashMap::from([("XXBTZUSD".to_string(), book)]), order_tx));
+
+        let order = |trader: &str, side: Side, price: Decimal, self_trade_prevention: SelfTradePrevention| NewOrder {
+            trader: trader.to_string(),
+            pair: "XXBTZUSD".to_string(),
+            price,
+            volume: dec!(2),
+            side,
+            order_type: OrderType::Limit,
+            self_trade_prevention,
+            ..Default::default()
+        };
+
+        // The trader's own bid and ask cross, the later one cancels itself instead of trading
+        let mut order_ids: Vec<Uuid> = Vec::new();
+        for (trader, side, price, self_trade_prevention) in [
+            ("trader1", Side::Sell, dec!(100), SelfTradePrevention::None),
+            ("trader2", Side::Sell, dec!(101), SelfTradePrevention::None),
+            ("trader1", Side::Buy, dec!(101), SelfTradePrevention::CancelNewest),
+        ] {
+            let order_id = Uuid::new_v4();
+            let report = process_order(&service, order_id, order(trader, side, price, self_trade_prevention), None).await;
+            assert_eq!(report.status, OrderStatus::Open);
+            order_ids.push(order_id);
+        }
+
+        let mut order_books = service.order_books.lock().await;
+        let mut trade_books = service.trade_books.lock().await;
+        let book = order_books.get_mut("XXBTZUSD").unwrap();
+        uncross_auction(book, &mut trade_books, "XXBTZUSD", &Instrument::default()).unwrap();
+        assert!(book.fills().is_empty());
+        assert!(book.best(Side::Buy).is_none());
+        assert_eq!(book.best(Side::Sell).unwrap().id, order_ids[0]);
+        let record = trade_books["trader1"].order(&order_ids[2]).unwrap();
+        assert_eq!((record.status, record.stp_canceled_volume), (OrderStatus::StpCanceled, dec!(2)));
+        assert_eq!(trade_books["trader1"].order(&order_ids[0]).unwrap().status, OrderStatus::Open);
+        assert_eq!(trade_books["trader2"].order(&order_ids[1]).unwrap().status, OrderStatus::Open);
+    }
+
+    #[tokio::test]
+    async fn test_price_protection_and_halts() {
+        use futures::StreamExt;
+
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let levels = || -> Book {
+            vec![
+                book_order(dec!(110.0), dec!(1.0), Side::Sell),
+                book_order(dec!(104.0), dec!(1.0), Side::Sell),
+                book_order(dec!(100.0), dec!(1.0), Side::Sell),
+                book_order(dec!(99.0), dec!(1.0), Side::Buy),
+            ]
+                .into_iter()
+                .collect()
+        };
+        let instrument = Instrument {
+            price_band: dec!(5),
+            limit_collar: dec!(10),
+            halt_move: dec!(3),
+            halt_duration: 3600,
+            ..Default::default()
+        };
+        let service = Arc::new(OrderBookService {
+            instruments: RwLock::new(HashMap::from([
+                ("XXBTZUSD".to_string(), instrument),
+                ("XETHZUSD".to_string(), Instrument { halt_mode: HaltMode::Auction, ..instrument }),
+            ])),
+            ..OrderBookService::new(HashMap::from([("XXBTZUSD".to_string(), levels()), ("XETHZUSD".to_string(), levels())]), order_tx)
+        });
+        let order = |pair: &str, side: Side, order_type: OrderType, price: Decimal, volume: Decimal| NewOrder {
+            trader: "trader1".to_string(),
+            pair: pair.to_string(),
+            price,
+            volume,
+            side,
+            order_type,
+            ..Default::default()
+        };
+
+        let request = Request::new(OrderBookRequest { pair: "XXBTZUSD".to_string() });
+        let mut stream = service.subscribe_trading_status(request).await.unwrap().into_inner();
+        assert_eq!(stream.next().await.unwrap().unwrap().phase(), orderbook::TradingPhase::Continuous);
+
+        // Before the first trade the best contra price is the reference
+        let report = process_order(&service, Uuid::new_v4(), order("XXBTZUSD", Side::Buy, OrderType::Market, dec!(0), dec!(3)), None).await;
+        assert_eq!(report.status, OrderStatus::Rejected);
+        assert_eq!(report.reject_reason, "market order would execute at 110, beyond the 5% price band around 100");
+        let report = process_order(&service, Uuid::new_v4(), order("XXBTZUSD", Side::Sell, OrderType::Limit, dec!(89), dec!(1)), None).await;
+        assert_eq!(report.status, OrderStatus::Rejected);
+        assert_eq!(report.reject_reason, "limit price 89 is beyond the 10% collar around 99");
+
+        // Trading from 100 to 104 is a 4% move, the pair is paused
+        let report = process_order(&service, Uuid::new_v4(), order("XXBTZUSD", Side::Buy, OrderType::Market, dec!(0), dec!(2)), None).await;
+        assert_eq!(report.status, OrderStatus::Filled);
+        let halt = stream.next().await.unwrap().unwrap();
+        assert_eq!(halt.phase(), orderbook::TradingPhase::Halted);
+        assert_eq!(halt.reason, "price moved 4% (100 to 104) within 60 seconds");
+        assert!(!halt.resume_time.is_empty());
+        let report = process_order(&service, Uuid::new_v4(), order("XXBTZUSD", Side::Buy, OrderType::Limit, dec!(100), dec!(1)), None).await;
+        assert_eq!(report.status, OrderStatus::Rejected);
+        assert!(report.reject_reason.starts_with("trading in XXBTZUSD is halted until"));
+
+        // Once the pause is over the pair re-opens with a call auction
+        let update_phase = || {
+            let service = Arc::clone(&service);
+            async move {
+                let mut order_books = service.order_books.lock().await;
+                let mut trade_books = service.trade_books.lock().await;
+                let mut stop_orders: Vec<PendingOrder> = Vec::new();
+                let book = order_books.get_mut("XXBTZUSD").unwrap();
+                update_trading_phase(&service, "XXBTZUSD", book, &mut stop_orders, &mut trade_books, Utc::now() + chrono::Duration::hours(2));
+            }
+        };
+        update_phase().await;
+        let reopening = stream.next().await.unwrap().unwrap();
+        assert_eq!(reopening.phase(), orderbook::TradingPhase::Auction);
+        assert!(service.order_books.lock().await["XXBTZUSD"].halted().is_none());
+
+        // Crossing orders rest until the uncross, then trade at a single price (of the prices executing
+        // the most volume, 105 is the closest to the last trade at 104)
+        let crossing = [
+            (Side::Buy, dec!(106), dec!(1)),
+            (Side::Buy, dec!(105), dec!(1)),
+            (Side::Sell, dec!(101), dec!(2)),
+        ];
+        for (side, price, volume) in crossing {
+            let report = process_order(&service, Uuid::new_v4(), order("XXBTZUSD", side, OrderType::Limit, price, volume), None).await;
+            assert_eq!((report.status, report.filled_volume), (OrderStatus::Open, dec!(0)));
+        }
+        update_phase().await;
+        let resume = stream.next().await.unwrap().unwrap();
+        assert_eq!((resume.phase(), resume.resume_time.as_str()), (orderbook::TradingPhase::Continuous, ""));
+        {
+            let order_books = service.order_books.lock().await;
+            let book = &order_books["XXBTZUSD"];
+            assert_eq!((book.best_bid(), book.best_ask(), book.last_price()), (Some(dec!(99)), Some(dec!(110)), Some(dec!(105))));
+            let trade_books = service.trade_books.lock().await;
+            let fills = trade_books["trader1"].fills();
+            assert_eq!(fills.len(), 6);
+            assert!(fills[2..].iter().all(|fill| fill.price == dec!(105)));
+        }
+        let report = process_order(&service, Uuid::new_v4(), order("XXBTZUSD", Side::Buy, OrderType::Limit, dec!(100), dec!(1)), None).await;
+        assert_eq!(report.status, OrderStatus::Open);
+
+        // In auction mode the halted pair collects orders for a re-opening auction instead
+        process_order(&service, Uuid::new_v4(), order("XETHZUSD", Side::Buy, OrderType::Market, dec!(0), dec!(2)), None).await;
+        let order_books = service.order_books.lock().await;
+        assert!(order_books["XETHZUSD"].halted().is_none());
+        assert!(order_books["XETHZUSD"].auction().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_triggered_stop_price_protection() {
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let levels: Book = vec![
+            book_order(dec!(200.0), dec!(5.0), Side::Sell),
+            book_order(dec!(100.0), dec!(2.0), Side::Sell),
+            book_order(dec!(98.0), dec!(1.0), Side::Buy),
+        ]
+            .into_iter()
+            .collect();
+        let service = Arc::new(OrderBookService {
+            instruments: RwLock::new(HashMap::from([
+                ("XXBTZUSD".to_string(), Instrument { price_band: dec!(5), limit_collar: dec!(10), ..Default::default() }),
+            ])),
+            ..OrderBookService::new(HashMap::from([("XXBTZUSD".to_string(), levels)]), order_tx)
+        });
+        let order = |order_type: OrderType, price: Decimal, stop_price: Decimal, volume: Decimal| NewOrder {
+            trader: "trader1".to_string(),
+            pair: "XXBTZUSD".to_string(),
+            price,
+            stop_price,
+            volume,
+            side: Side::Buy,
+            order_type,
+            ..Default::default()
+        };
+
+        // The last trade at 100 becomes the reference
+        let report = process_order(&service, Uuid::new_v4(), order(OrderType::Market, dec!(0), dec!(0), dec!(1)), None).await;
+        assert_eq!(report.status, OrderStatus::Filled);
+
+        // Released stops would sweep up to 200 or are priced beyond the collar, they are rejected
+        for (order_type, price, reason) in [
+            (OrderType::Stop, dec!(0), "market order would execute at 200, beyond the 5% price band around 100"),
+            (OrderType::StopLimit, dec!(120), "limit price 120 is beyond the 10% collar around 100"),
+        ] {
+            let order_id = Uuid::new_v4();
+            process_order(&service, order_id, order(order_type, price, dec!(99), dec!(5)), None).await;
+            let trade_books = service.trade_books.lock().await;
+            let record = trade_books["trader1"].order(&order_id).unwrap();
+            assert_eq!((record.status, record.filled_volume, record.reject_reason.as_str()), (OrderStatus::Rejected, dec!(0), reason));
+        }
+        let order_books = service.order_books.lock().await;
+        assert_eq!(order_books["XXBTZUSD"].best_ask(), Some(dec!(100)));
+        assert_eq!(service.trade_books.lock().await["trader1"].fills().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_market_to_limit_and_pegged_orders() {
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let levels: Book = vec![
+            book_order(dec!(102.0), dec!(1.0), Side::Sell),
+            book_order(dec!(101.0), dec!(1.0), Side::Sell),
+            book_order(dec!(99.0), dec!(1.0), Side::Buy),
+            book_order(dec!(98.0), dec!(1.0), Side::Buy),
+        ]
+            .into_iter()
+            .collect();
+        let instrument = Instrument { tick_size: dec!(1), ..Default::default() };
+        let service = Arc::new(OrderBookService {
+            instruments: RwLock::new(HashMap::from([
+                ("XXBTZUSD".to_string(), instrument),
+                ("XETHZUSD".to_string(), instrument),
+            ])),
+            ..OrderBookService::new(HashMap::from([("XXBTZUSD".to_string(), levels), ("XETHZUSD".to_string(), Book::new())]), order_tx)
+        });
+        let order = |trader: &str, pair: &str, side: Side, order_type: OrderType| NewOrder {
+            trader: trader.to_string(),
+            pair: pair.to_string(),
+            volume: dec!(1),
+            side,
+            order_type,
+            ..Default::default()
+        };
+        let resting = |order_id: Uuid| {
+            let service = Arc::clone(&service);
+            async move {
+                let order_books = service.order_books.lock().await;
+                order_books["XXBTZUSD"].get(&order_id).map(|o| (o.price, o.order_type))
+            }
+        };
+
+        // Nothing to execute against or to follow
+        let report = process_order(&service, Uuid::new_v4(), order("trader1", "XETHZUSD", Side::Buy, OrderType::MarketToLimit), None).await;
+        assert_eq!(report.reject_reason, "market_to_limit order has no liquidity to execute against");
+        let report = process_order(&service, Uuid::new_v4(), order("trader1", "XETHZUSD", Side::Sell, OrderType::PrimaryPeg), None).await;
+        assert_eq!(report.reject_reason, "primary_peg order has no price to follow in the book");
+
+        // Market-to-limit takes the best ask only and rests the rest as a limit order at that price
+        let mtl_id = Uuid::new_v4();
+        let report = process_order(&service, mtl_id, NewOrder { volume: dec!(1.5), ..order("trader1", "XXBTZUSD", Side::Buy, OrderType::MarketToLimit) }, None).await;
+        assert_eq!((report.status, report.filled_volume, report.remaining_volume), (OrderStatus::PartiallyFilled, dec!(1), dec!(0.5)));
+        assert_eq!(resting(mtl_id).await, Some((dec!(101), OrderType::Limit)));
+
+        // Pegs rest at the best ask and at the midpoint of 101 and 102, rounded down for a buy
+        let primary_id = Uuid::new_v4();
+        process_order(&service, primary_id, order("trader2", "XXBTZUSD", Side::Sell, OrderType::PrimaryPeg), None).await;
+        assert_eq!(resting(primary_id).await, Some((dec!(102), OrderType::PrimaryPeg)));
+        let midpoint_id = Uuid::new_v4();
+        process_order(&service, midpoint_id, order("trader3", "XXBTZUSD", Side::Buy, OrderType::MidpointPeg), None).await;
+        assert_eq!(resting(midpoint_id).await, Some((dec!(101), OrderType::MidpointPeg)));
+
+        // Canceling the best bid moves the midpoint peg to 100
+        service.cancel_order(Request::new(CancelOrderRequest {
+            order_id: mtl_id.to_string(),
+            trader: "trader1".to_string(),
+        })).await.unwrap();
+        assert_eq!(resting(midpoint_id).await, Some((dec!(100), OrderType::MidpointPeg)));
+
+        // Only the volume of a pegged order can be replaced
+        let status = service.replace_order(Request::new(ReplaceOrderRequest {
+            order_id: midpoint_id.to_string(),
+            trader: "trader3".to_string(),
+            price: "95".to_string(),
+            volume: String::new(),
+        })).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        // Both pegs follow a book refresh
+        {
+            let mut order_books = service.order_books.lock().await;
+            let mut trade_books = service.trade_books.lock().await;
+            let book = order_books.get_mut("XXBTZUSD").unwrap();
+            let fresh: Book = vec![book_order(dec!(105.0), dec!(1.0), Side::Sell), book_order(dec!(97.0), dec!(1.0), Side::Buy)]
+                .into_iter()
+                .collect();
+            book.refresh(fresh);
+            trigger_stop_orders(book, "XXBTZUSD", &mut Vec::new(), &mut trade_books, service.stop_trigger, &instrument);
+            assert_eq!(book.get(&primary_id).map(|o| o.price), Some(dec!(105)));
+            assert_eq!(book.get(&midpoint_id).map(|o| o.price), Some(dec!(101)));
+            let record = trade_books["trader2"].order(&primary_id).unwrap();
+            assert_eq!((record.price, record.status), (dec!(105), OrderStatus::Open));
+            assert_eq!(record.transitions.iter().map(|t| t.price).collect::<Vec<_>>(), vec![dec!(0), dec!(102), dec!(105)]);
+
+            // The book tracks its pegs, and unchanged quotes leave them alone
+            assert_eq!(book.pegged_orders().len(), 2);
+            assert_eq!(book.best_unpegged(Side::Sell), Some(dec!(105)));
+            assert!(!book.update_peg_quotes());
+            book.remove(&primary_id);
+            assert_eq!(book.pegged_orders(), vec![midpoint_id]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_engine_priced_order_value() {
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let levels: Book = vec![
+            book_order(dec!(3000000000.0), dec!(1.0), Side::Sell),
+            book_order(dec!(1000000000.0), dec!(1.0), Side::Buy),
+        ]
+            .into_iter()
+            .collect();
+        let service = Arc::new(OrderBookService::new(HashMap::from([("XXBTZUSD".to_string(), levels)]), order_tx));
+        let order = |trader: &str, side: Side, order_type: OrderType, price: Decimal, volume: Decimal| NewOrder {
+            trader: trader.to_string(),
+            pair: "XXBTZUSD".to_string(),
+            price,
+            volume,
+            side,
+            order_type,
+            ..Default::default()
+        };
+
+        // The value of a peg is checked once the engine has priced it
+        let report = process_order(&service, Uuid::new_v4(), order("trader1", Side::Buy, OrderType::PrimaryPeg, dec!(0), dec!(1e20)), None).await;
+        assert_eq!((report.status, report.reject_reason.as_str()), (OrderStatus::Rejected, "order value is too large"));
+
+        // A peg accepted at 1e9 follows the bid to 2e9, matching it can't overflow the fee or average
+        let peg_id = Uuid::new_v4();
+        let report = process_order(&service, peg_id, order("trader1", Side::Buy, OrderType::PrimaryPeg, dec!(0), dec!(7e19)), None).await;
+        assert_eq!(report.status, OrderStatus::Open);
+        process_order(&service, Uuid::new_v4(), order("trader2", Side::Buy, OrderType::Limit, dec!(2000000000), dec!(1)), None).await;
+        assert_eq!(service.order_books.lock().await["XXBTZUSD"].get(&peg_id).unwrap().price, dec!(2000000000));
+        let report = process_order(&service, Uuid::new_v4(), order("trader3", Side::Sell, OrderType::Market, dec!(0), dec!(7e19)), None).await;
+        assert_eq!((report.status, report.fills.len()), (OrderStatus::Filled, 2));
+        assert_eq!(report.average_price, Some(dec!(2000000000)));
+    }
+
+    #[tokio::test]
+    async fn test_admin_service() {
+        use futures::StreamExt;
+
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let initial: HashMap<String, Book> = HashMap::from([
+            ("XXBTZUSD".to_string(), vec![book_order(dec!(100.0), dec!(1.0), Side::Sell)].into_iter().collect()),
+        ]);
+        let service = Arc::new(OrderBookService::new(initial, order_tx));
+        let admin = AdminService {
+            service: Arc::clone(&service),
+            offline_mode: true,
+            configured: HashMap::from([("SUIUSD".to_string(), Instrument { tick_size: dec!(0.001), ..Default::default() })]),
+        };
+        let order = |trader: &str, order_type: OrderType, side: Side, price: Decimal| NewOrder {
+            trader: trader.to_string(),
+            pair: "XXBTZUSD".to_string(),
+            price,
+            stop_price: if order_type == OrderType::Stop { price } else { Decimal::ZERO },
+            volume: dec!(1),
+            side,
+            order_type,
+            ..Default::default()
+        };
+        let limit_id = Uuid::new_v4();
+        process_order(&service, limit_id, order("trader1", OrderType::Limit, Side::Buy, dec!(99)), None).await;
+        let stop_id = Uuid::new_v4();
+        process_order(&service, stop_id, order("trader1", OrderType::Stop, Side::Sell, dec!(90)), None).await;
+        process_order(&service, Uuid::new_v4(), order("trader2", OrderType::Limit, Side::Buy, dec!(98)), None).await;
+
+        let status = admin.get_engine_status(Request::new(EngineStatusRequest {})).await.unwrap().into_inner();
+        assert_eq!((status.traders, status.queued_orders, status.offline), (2, 0, true));
+        let xbt = &status.pairs[0];
+        assert_eq!((xbt.pair.as_str(), xbt.phase()), ("XXBTZUSD", orderbook::TradingPhase::Continuous));
+        assert_eq!((xbt.resting_orders, xbt.stop_orders), (3, 1));
+        assert_eq!((xbt.best_bid.as_str(), xbt.best_ask.as_str(), xbt.last_price.as_str()), ("99", "100", ""));
+
+        // Halts need a duration, a halted pair rejects orders until it is resumed
+        let halt = |duration: u64| Request::new(HaltRequest { pair: "XXBTZUSD".to_string(), duration, ..Default::default() });
+        assert_eq!(admin.halt_trading(halt(0)).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+        let halted = admin.halt_trading(halt(60)).await.unwrap().into_inner();
+        assert_eq!((halted.phase(), halted.reason.as_str()), (orderbook::TradingPhase::Halted, "halted by the operator"));
+        assert_eq!(admin.halt_trading(halt(60)).await.unwrap_err().code(), tonic::Code::FailedPrecondition);
+        let report = process_order(&service, Uuid::new_v4(), order("trader2", OrderType::Limit, Side::Buy, dec!(98)), None).await;
+        assert_eq!(report.status, OrderStatus::Rejected);
+        let resume = || Request::new(PairRequest { pair: "XXBTZUSD".to_string() });
+        // Ending the pause starts the re-opening auction, resuming again uncrosses it
+        assert_eq!(admin.resume_trading(resume()).await.unwrap().into_inner().phase(), orderbook::TradingPhase::Auction);
+        assert_eq!(admin.resume_trading(resume()).await.unwrap().into_inner().phase(), orderbook::TradingPhase::Continuous);
+        assert_eq!(admin.resume_trading(resume()).await.unwrap_err().code(), tonic::Code::FailedPrecondition);
+
+        // Cancel all takes resting and stop orders of the trader only
+        let request = Request::new(CancelAllRequest { trader: "trader1".to_string(), pair: String::new() });
+        let response = admin.cancel_all_orders(request).await.unwrap().into_inner();
+        assert_eq!(response.order_ids, vec![limit_id.to_string(), stop_id.to_string()]);
+        {
+            let trade_books = service.trade_books.lock().await;
+            assert_eq!(trade_books["trader1"].order(&stop_id).unwrap().status, OrderStatus::Canceled);
+            assert_eq!(service.order_books.lock().await["XXBTZUSD"].best_bid(), Some(dec!(98)));
+        }
+
+        // Added pairs take the rules of the request, then of config.yaml
+        let add = |pair: &str, rules: Option<InstrumentRules>| Request::new(AddPairRequest { pair: pair.to_string(), rules });
+        let sui = admin.add_pair(add("SUIUSD", None)).await.unwrap().into_inner();
+        assert_eq!(sui.rules.unwrap().tick_size, "0.001");
+        assert_eq!(admin.add_pair(add("SUIUSD", None)).await.unwrap_err().code(), tonic::Code::AlreadyExists);
+        let rules = InstrumentRules { price_scale: 2, tick_size: "0.01".to_string(), ..Default::default() };
+        let eth = admin.add_pair(add("XETHZUSD", Some(rules))).await.unwrap().into_inner();
+        let rules = eth.rules.unwrap();
+        assert_eq!((rules.price_scale, rules.volume_scale, rules.halt_window), (2, 8, 60));
+        assert_eq!(service.instrument("XETHZUSD").unwrap().tick_size, dec!(0.01));
+
+        // Purging cancels the remaining orders and drops Kraken liquidity
+        let response = admin.purge_book(Request::new(PairRequest { pair: "XXBTZUSD".to_string() })).await.unwrap().into_inner();
+        assert_eq!(response.message, "book purged, 1 orders canceled, 1 external orders removed");
+        assert!(service.order_books.lock().await["XXBTZUSD"].is_empty());
+
+        // Streams of a removed pair end, orders for it are rejected as unknown
+        let subscribe = || Request::new(OrderBookRequest { pair: "SUIUSD".to_string() });
+        let mut book_stream = service.subscribe_order_book(subscribe()).await.unwrap().into_inner();
+        let mut auction_stream = service.subscribe_auction(subscribe()).await.unwrap().into_inner();
+        let mut status_stream = service.subscribe_trading_status(subscribe()).await.unwrap().into_inner();
+        let trade_request = Request::new(TradeStreamRequest { pair: "SUIUSD".to_string() });
+        let mut trade_stream = service.subscribe_trades(trade_request).await.unwrap().into_inner();
+        admin.remove_pair(Request::new(PairRequest { pair: "SUIUSD".to_string() })).await.unwrap();
+        assert!(book_stream.next().await.unwrap().unwrap().snapshot);
+        assert!(book_stream.next().await.is_none());
+        assert!(auction_stream.next().await.unwrap().is_ok());
+        assert!(auction_stream.next().await.is_none());
+        assert!(status_stream.next().await.unwrap().is_ok());
+        assert!(status_stream.next().await.is_none());
+        assert!(trade_stream.next().await.is_none());
+        let request = Request::new(OrderRequest {
+            pair: "SUIUSD".to_string(),
+            trader: "trader1".to_string(),
+            volume: "1".to_string(),
+            side: orderbook::Side::Buy.into(),
+            order_type: orderbook::OrderType::Market.into(),
+            ..Default::default()
+        });
+        assert_eq!(service.place_market_order(request).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+        let status = admin.get_engine_status(Request::new(EngineStatusRequest {})).await.unwrap().into_inner();
+        let pairs: Vec<&str> = status.pairs.iter().map(|pair| pair.pair.as_str()).collect();
+        assert_eq!(pairs, vec!["XETHZUSD", "XXBTZUSD"]);
+
+        // Online, a pair without a Kraken order book is not added
+        let online = AdminService { offline_mode: false, ..admin };
+        let code = online.add_pair(add("NOTAPAIR", None)).await.unwrap_err().code();
+        assert!(matches!(code, tonic::Code::NotFound | tonic::Code::Unavailable));
+        assert!(service.instrument("NOTAPAIR").is_none());
+        assert!(!service.order_books.lock().await.contains_key("NOTAPAIR"));
+    }
+
+    #[tokio::test]
+    async fn test_get_depth() {
+        let (order_tx, _order_rx) = mpsc::channel(100);
+        let initial: HashMap<String, Book> = HashMap::from([
+            ("XXBTZUSD".to_string(), vec![
+                book_order(dec!(65303.2), dec!(0.5), Side::Sell),
+                book_order(dec!(65296.7), dec!(0.375), Side::Sell),
+                book_order(dec!(65290.6), dec!(0.25), Side::Sell),
+                book_order(dec!(65290.6), dec!(0.25), Side::Sell),
+                book_order(dec!(65290.0), dec!(1.0), Side::Buy),
+                book_order(dec!(65279.4), dec!(0.5), Side::Buy),
+                book_order(dec!(65249.9), dec!(0.25), Side::Buy)
+            ]
+                .into_iter()
+                .collect()),
+        ]);
+        let service = Arc::new(OrderBookService::new(initial, order_tx));
+        let depth = |depth: u32, group: &str| Request::new(DepthRequest {
+            pair: "XXBTZUSD".to_string(),
+            depth,
+            group: group.to_string(),
+        });
+        let levels = |levels: &[PriceLevel]| -> Vec<(String, String, u32)> {
+            levels.iter().map(|l| (l.price.clone(), l.volume.clone(), l.order_count)).collect()
+        };
+        let expected = |levels: &[(&str, &str, u32)]| -> Vec<(String, String, u32)> {
+            levels.iter().map(|(p, v, c)| (p.to_string(), v.to_string(), *c)).collect()
+        };
+
+        // Best first on both sides with order count per level
+        let response = service.get_depth(depth(2, "")).await.unwrap().into_inner();
+        assert_eq!(levels(&response.asks), expected(&[("65290.6", "0.5", 2), ("65296.7", "0.375", 1)]));
+        assert_eq!(levels(&response.bids), expected(&[("65290", "1", 1), ("65279.4", "0.5", 1)]));
+
+        // 10 USD buckets: asks rounded up, bids rounded down
+        let response = service.get_depth(depth(0, "10")).await.unwrap().into_inner();
+        assert_eq!(levels(&response.asks), expected(&[("65300", "0.875", 3), ("65310", "0.5", 1)]));
+        assert_eq!(levels(&response.bids), expected(&[("65290", "1", 1), ("65270", "0.5", 1), ("65240", "0.25", 1)]));
+
+        let response = service.get_depth(depth(1, "100")).await.unwrap().into_inner();
+        assert_eq!((response.asks.len(), response.bids.len()), (1, 1));
+        assert_eq!(levels(&response.bids), expected(&[("65200", "1.75", 3)]));
+
+        assert!(service.get_depth(depth(0, "-1")).await.is_err());
+    }
+}